@@ -41,7 +41,9 @@ use neuron_shape::NeuronShape;
 mod line;
 use line::Line;
 
+use qu::host::{HostState, SimulationHost};
 use qu::scheduler::Scheduler;
+use qu::stimulation::StimulationProtocol;
 
 static mut shapes_pool: BTreeMap<String, NeuronShape> = BTreeMap::new();
 
@@ -223,14 +225,17 @@ fn create_scheduler<F: Sized + Facade>(display: &F) -> Scheduler {
 fn draw_scheduler<F: Sized + Facade>(
     delta: f32,
     perspective: [[f32; 4]; 4],
-    scheduler: &Scheduler,
+    state: &HostState,
     display: &F,
     target: &mut Frame
 ) {
-    for (id, neuron) in scheduler.pool.iter() {
+    for id in state.snapshot.potentials.keys() {
         let mut shape = unsafe { 
             shapes_pool.get_mut(id).unwrap() };
         shape.update(delta);
+        // neurons that fired on the last tick are drawn bigger
+        let size = if state.fired.contains(id) { 0.15 } else { 0.1 };
+        shape.scale = [size, size, 1.0];
         shape.draw(delta, perspective, target);
         // println!("d={} {} - {}", delta, neuron.get_name(), neuron.potential);
     }
@@ -250,7 +255,10 @@ fn main() {
     let mut delta: Duration = Duration::from_secs_f32(0.0f32);
 
     let cube = Cube::new(&display);
-    let scheduler = create_scheduler(&display);
+    // the network runs on its own thread, the render loop only reads its published state
+    let mut scheduler = create_scheduler(&display);
+    scheduler.stimulation = StimulationProtocol::from_file("protocols/cpg.json").unwrap_or_default();
+    let host = SimulationHost::spawn(scheduler, Duration::from_millis(100));
     let mut line = Line::new(&display);
 
     let mut value = 0;
@@ -302,6 +310,7 @@ fn main() {
                 event: WindowEvent::RedrawRequested,
                 ..
             } => {
+                let state = host.state();
                 let ui = imgui_context.frame();
 
                 // ui.show_demo_window(&mut true);
@@ -316,6 +325,19 @@ fn main() {
         
                         ui.button("This...is...imgui-rs!");
                         ui.separator();
+                        if state.running {
+                            if ui.button("Pause") {
+                                host.pause();
+                            }
+                        } else if ui.button("Run") {
+                            host.run();
+                        }
+                        ui.same_line();
+                        if ui.button("Step") {
+                            host.step(1);
+                        }
+                        ui.text(format!("time {} fired {:?}", state.snapshot.time, state.fired));
+                        ui.separator();
                         let mouse_pos = ui.io().mouse_pos;
                         ui.text(format!(
                             "Mouse Position: ({:.1},{:.1})",
@@ -326,7 +348,7 @@ fn main() {
                 let mut target = display.draw();
                 target.clear_color_srgb(0.0, 0.0, 0.0, 1.0);
                 winit_platform.prepare_render(ui, &window);
-                draw_scheduler(delta.as_secs_f32(), perspective, &scheduler, &display, &mut target);
+                draw_scheduler(delta.as_secs_f32(), perspective, &state, &display, &mut target);
                 line.draw(delta.as_secs_f32(), perspective, &mut target);
                 let draw_data = imgui_context.render();
                 renderer
@@ -0,0 +1,300 @@
+use std::collections::BTreeMap;
+use std::f64::consts::PI;
+
+use crate::scheduler::Spike;
+
+// Statistics over recorded spikes (`Scheduler::spikes`), all times are in ms
+
+// Spike times of every neuron, sorted
+pub fn spike_trains(spikes: &[Spike]) -> BTreeMap<String, Vec<f64>> {
+  let mut trains: BTreeMap<String, Vec<f64>> = BTreeMap::new();
+  for spike in spikes.iter() {
+    trains.entry(spike.neuron.clone()).or_default().push(spike.time);
+  }
+  for times in trains.values_mut() {
+    times.sort_by(|a, b| a.total_cmp(b));
+  }
+  trains
+}
+
+// Spikes per second of every neuron that fired during `duration` ms
+pub fn firing_rates(spikes: &[Spike], duration: f64) -> BTreeMap<String, f64> {
+  spike_trains(spikes).into_iter()
+    .map(|(neuron, times)| (neuron, 1000.0 * times.len() as f64 / duration))
+    .collect()
+}
+
+pub fn inter_spike_intervals(times: &[f64]) -> Vec<f64> {
+  times.windows(2).map(|pair| pair[1] - pair[0]).collect()
+}
+
+// Counts of intervals in [i * bin, (i + 1) * bin), empty unless `bin` is positive
+pub fn isi_histogram(times: &[f64], bin: f64) -> Vec<usize> {
+  if bin.is_nan() || bin <= 0.0 {
+    return Vec::new();
+  }
+  let intervals = inter_spike_intervals(times);
+  let mut histogram = Vec::new();
+  for interval in intervals {
+    let idx = (interval / bin).floor() as usize;
+    if idx >= histogram.len() {
+      histogram.resize(idx + 1, 0);
+    }
+    histogram[idx] += 1;
+  }
+  histogram
+}
+
+// Standard deviation over mean of the intervals, 0 for a perfect clock, 1 for Poisson
+pub fn coefficient_of_variation(times: &[f64]) -> Option<f64> {
+  let intervals = inter_spike_intervals(times);
+  if intervals.len() < 2 {
+    return None;
+  }
+  let n = intervals.len() as f64;
+  let mean = intervals.iter().sum::<f64>() / n;
+  if mean == 0.0 {
+    return None;
+  }
+  let variance = intervals.iter().map(|i| (i - mean).powi(2)).sum::<f64>() / n;
+  Some(variance.sqrt() / mean)
+}
+
+// Histogram of `b - a` over all spike pairs with |b - a| <= max_lag,
+// returned as (lag at the bin center, count) from -max_lag to max_lag.
+// Empty unless `bin` is positive and `max_lag` is finite and not negative.
+pub fn cross_correlation(a: &[f64], b: &[f64], max_lag: f64, bin: f64) -> Vec<(f64, usize)> {
+  if !bin.is_finite() || bin <= 0.0 || !max_lag.is_finite() || max_lag < 0.0 {
+    return Vec::new();
+  }
+  let n_bins = (max_lag / bin).round() as i64;
+  let mut counts = vec![0; (2 * n_bins + 1) as usize];
+  for t_a in a.iter() {
+    for t_b in b.iter() {
+      let lag = t_b - t_a;
+      if lag.abs() > max_lag {
+        continue;
+      }
+      let idx = (lag / bin).round() as i64 + n_bins;
+      if idx >= 0 && (idx as usize) < counts.len() {
+        counts[idx as usize] += 1;
+      }
+    }
+  }
+  counts.into_iter()
+    .enumerate()
+    .map(|(i, count)| ((i as i64 - n_bins) as f64 * bin, count))
+    .collect()
+}
+
+#[derive(Clone, Debug, PartialEq)]
+pub struct Burst {
+  pub start: f64,
+  pub end: f64,
+  pub spikes: usize,
+}
+
+// Runs of at least `min_spikes` spikes where every interval is at most `max_isi`
+pub fn detect_bursts(times: &[f64], max_isi: f64, min_spikes: usize) -> Vec<Burst> {
+  let mut bursts = Vec::new();
+  let mut start = 0;
+  for i in 1..=times.len() {
+    if i < times.len() && times[i] - times[i - 1] <= max_isi {
+      continue;
+    }
+    if i - start >= min_spikes && i > start {
+      bursts.push(Burst {
+        start: times[start],
+        end: times[i - 1],
+        spikes: i - start,
+      });
+    }
+    start = i;
+  }
+  bursts
+}
+
+// Period of the strongest rhythm: the lag of the autocorrelation peak of the
+// spike train binned by `bin`, looking at lags up to `max_period`.
+// The shortest lag within 90% of the peak wins, so harmonics don't.
+// None for a `bin` that isn't positive.
+pub fn dominant_period(times: &[f64], bin: f64, max_period: f64) -> Option<f64> {
+  if times.len() < 2 || !bin.is_finite() || bin <= 0.0 {
+    return None;
+  }
+  let start = times[0];
+  let n = ((times[times.len() - 1] - start) / bin).floor() as usize + 1;
+  let mut train = vec![0.0; n];
+  for t in times.iter() {
+    train[((t - start) / bin).floor() as usize] += 1.0;
+  }
+  let mean = train.iter().sum::<f64>() / n as f64;
+  let centered: Vec<f64> = train.iter().map(|x| x - mean).collect();
+
+  let max_lag = ((max_period / bin).round() as usize).min(n - 1);
+  let autocorrelation: Vec<f64> = (0..=max_lag)
+    .map(|lag| (0..n - lag).map(|i| centered[i] * centered[i + lag]).sum())
+    .collect();
+  let peak = autocorrelation.iter().skip(1).cloned().fold(f64::NEG_INFINITY, f64::max);
+  if peak <= 0.0 {
+    return None;
+  }
+  (1..=max_lag)
+    .find(|&lag| {
+      let is_local_max = autocorrelation[lag] >= autocorrelation[lag - 1]
+        && (lag == max_lag || autocorrelation[lag] >= autocorrelation[lag + 1]);
+      is_local_max && autocorrelation[lag] >= 0.9 * peak
+    })
+    .map(|lag| lag as f64 * bin)
+}
+
+// Phase of every spike of `other` inside the cycle of `reference`,
+// as a fraction of the cycle between the two surrounding reference spikes
+pub fn phases(reference: &[f64], other: &[f64]) -> Vec<f64> {
+  let mut result = Vec::new();
+  for t in other.iter() {
+    let idx = reference.partition_point(|r| r <= t);
+    if idx == 0 || idx == reference.len() {
+      continue;
+    }
+    let (r0, r1) = (reference[idx - 1], reference[idx]);
+    result.push((t - r0) / (r1 - r0));
+  }
+  result
+}
+
+// Circular mean of `phases`: 0 means in phase, 0.5 anti-phase
+pub fn phase_lag(reference: &[f64], other: &[f64]) -> Option<f64> {
+  let phases = phases(reference, other);
+  if phases.is_empty() {
+    return None;
+  }
+  let (sin, cos) = phases.iter().fold((0.0, 0.0), |(s, c), phase| {
+    (s + (2.0 * PI * phase).sin(), c + (2.0 * PI * phase).cos())
+  });
+  if sin.abs() < 1e-12 && cos.abs() < 1e-12 {
+    return None;
+  }
+  Some((sin.atan2(cos) / (2.0 * PI)).rem_euclid(1.0))
+}
+
+#[cfg(test)]
+mod tests {
+  use std::collections::HashMap;
+
+  use crate::harness::{build_central_pattern_generator, excitatory_ring};
+  use crate::scheduler::{Scheduler, Spike};
+  use crate::stimulation::StimulationProtocol;
+
+  use super::{
+    coefficient_of_variation,
+    cross_correlation,
+    detect_bursts,
+    dominant_period,
+    firing_rates,
+    isi_histogram,
+    phase_lag,
+    spike_trains,
+    Burst,
+  };
+
+  fn periodic(start: f64, period: f64, n: usize) -> Vec<f64> {
+    (0..n).map(|i| start + period * i as f64).collect()
+  }
+
+  #[test]
+  fn rates_and_intervals() {
+    let spikes: Vec<Spike> = periodic(0.0, 10.0, 10).into_iter()
+      .map(|time| Spike { time, neuron: "a".to_string() })
+      .collect();
+    let rates = firing_rates(&spikes, 100.0);
+    assert_eq!(rates["a"], 100.0);
+
+    let times = &spike_trains(&spikes)["a"];
+    assert_eq!(coefficient_of_variation(times), Some(0.0));
+    assert_eq!(isi_histogram(times, 4.0), vec![0, 0, 9]);
+    assert!(isi_histogram(times, 0.0).is_empty());
+    assert!(isi_histogram(times, -1.0).is_empty());
+    assert!(isi_histogram(times, f64::NAN).is_empty());
+
+    let irregular = vec![0.0, 1.0, 5.0, 6.0, 10.0];
+    let cv = coefficient_of_variation(&irregular).unwrap();
+    assert!((cv - 0.6).abs() < 1e-9, "cv = {}", cv);
+  }
+
+  #[test]
+  fn correlation_and_bursts() {
+    let a = periodic(0.0, 20.0, 5);
+    let b = periodic(5.0, 20.0, 5);
+    let correlation = cross_correlation(&a, &b, 10.0, 1.0);
+    let best = correlation.iter().max_by_key(|(_, count)| *count).unwrap();
+    assert_eq!(*best, (5.0, 5));
+    assert!(cross_correlation(&a, &b, 10.0, 0.0).is_empty());
+    assert!(cross_correlation(&a, &b, 10.0, f64::NAN).is_empty());
+    assert!(cross_correlation(&a, &b, -1.0, 1.0).is_empty());
+    assert!(cross_correlation(&a, &b, f64::INFINITY, 1.0).is_empty());
+
+    let times = vec![0.0, 1.0, 2.0, 10.0, 20.0, 21.0, 22.0, 23.0, 40.0];
+    let bursts = detect_bursts(&times, 1.5, 3);
+    assert_eq!(bursts, vec![
+      Burst { start: 0.0, end: 2.0, spikes: 3 },
+      Burst { start: 20.0, end: 23.0, spikes: 4 },
+    ]);
+  }
+
+  #[test]
+  fn oscillation_period_and_phase() {
+    // bursts of 3 spikes every 25 ms
+    let mut times = Vec::new();
+    for cycle in 0..8 {
+      let start = 25.0 * cycle as f64;
+      times.extend([start, start + 1.0, start + 2.0]);
+    }
+    assert_eq!(dominant_period(&times, 1.0, 60.0), Some(25.0));
+    assert_eq!(dominant_period(&times, 0.0, 60.0), None);
+    assert_eq!(dominant_period(&times, -1.0, 60.0), None);
+    assert_eq!(dominant_period(&times, f64::NAN, 60.0), None);
+
+    let a = periodic(0.0, 10.0, 20);
+    let b = periodic(5.0, 10.0, 20);
+    let lag = phase_lag(&a, &b).unwrap();
+    assert!((lag - 0.5).abs() < 1e-9, "lag = {}", lag);
+    let lag = phase_lag(&a, &periodic(2.5, 10.0, 20)).unwrap();
+    assert!((lag - 0.25).abs() < 1e-9, "lag = {}", lag);
+  }
+
+  #[test]
+  fn excitatory_ring_alternates() {
+    let mut scheduler = Scheduler::new();
+    let (a, b) = excitatory_ring(&mut scheduler, "a", "b", 3.0);
+    scheduler.recording = true;
+    scheduler.inject(HashMap::from([(a.clone(), vec![1.0])]));
+    scheduler.run(60);
+
+    let trains = spike_trains(&scheduler.spikes);
+    assert_eq!(dominant_period(&trains[&a], 1.0, 20.0), Some(6.0));
+    let lag = phase_lag(&trains[&a], &trains[&b]).unwrap();
+    assert!((lag - 0.5).abs() < 1e-9, "lag = {}", lag);
+  }
+
+  #[test]
+  fn central_pattern_generator_drives_alternate() {
+    let mut scheduler = Scheduler::new();
+    build_central_pattern_generator(&mut scheduler);
+    scheduler.stimulation = StimulationProtocol::from_json(r#"{
+      "seed": 0,
+      "stimuli": [
+        { "type": "pulse", "targets": ["l1"], "start": 0, "amplitude": 1.0, "period": 10, "width": 1 },
+        { "type": "pulse", "targets": ["signal"], "start": 0, "amplitude": 1.0, "period": 2, "width": 1 }
+      ]
+    }"#).unwrap();
+    scheduler.recording = true;
+    scheduler.run(1000);
+
+    let trains = spike_trains(&scheduler.spikes);
+    assert!(trains["d1"].len() > 20, "{} d1 spikes", trains["d1"].len());
+    assert!(trains["d1"].len().abs_diff(trains["d2"].len()) <= 1);
+    let lag = phase_lag(&trains["d1"], &trains["d2"]).unwrap();
+    assert!((lag - 0.5).abs() < 0.05, "lag = {}", lag);
+  }
+}
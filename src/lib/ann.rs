@@ -1,141 +1,1252 @@
+use std::fmt;
+use std::io::{Error, Result};
+
 use ndarray::prelude::*;
-use ndarray::Array2;
+use ndarray::{Array2, Zip};
 use ndarray_rand::RandomExt;
-use ndarray_rand::rand_distr::StandardNormal;
+use ndarray_rand::rand_distr::{StandardNormal, Uniform};
+use rand::{Rng, SeedableRng};
+use rand::rngs::StdRng;
+use rand::seq::SliceRandom;
+use serde::{Serialize, Deserialize};
+
+use crate::npy;
+
+// Activation function of a layer. The derivative is taken from the output,
+// which all of them can do and which saves keeping the pre-activations around.
+pub trait Activation: Send + Sync {
+  fn name(&self) -> &'static str;
+  fn forward(&self, z: ArrayView1<f32>) -> Array1<f32>;
+  // dL/dz from the output `y` of `forward` and dL/dy
+  fn backward(&self, y: ArrayView1<f32>, grad_y: ArrayView1<f32>) -> Array1<f32>;
+  fn clone_box(&self) -> Box<dyn Activation>;
+
+  // Parameter of the function, saved with the name
+  fn parameter(&self) -> Option<f32> {
+    None
+  }
+
+  // Same for a batch with one sample per row
+  fn forward_batch(&self, z: ArrayView2<f32>) -> Array2<f32> {
+    let mut y = Array2::zeros(z.raw_dim());
+    for (mut row, z) in y.rows_mut().into_iter().zip(z.rows()) {
+      row.assign(&self.forward(z));
+    }
+    y
+  }
+
+  fn backward_batch(&self, y: ArrayView2<f32>, grad_y: ArrayView2<f32>) -> Array2<f32> {
+    let mut grad_z = Array2::zeros(y.raw_dim());
+    for ((mut row, y), grad_y) in grad_z.rows_mut().into_iter().zip(y.rows()).zip(grad_y.rows()) {
+      row.assign(&self.backward(y, grad_y));
+    }
+    grad_z
+  }
+}
+
+impl Clone for Box<dyn Activation> {
+  fn clone(&self) -> Self {
+    self.clone_box()
+  }
+}
+
+impl fmt::Debug for dyn Activation {
+  fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+    write!(f, "{}", self.name())
+  }
+}
+
+// Activation applied to every unit on its own, the Jacobian is diagonal
+pub trait Elementwise: Clone + Send + Sync + 'static {
+  fn name(&self) -> &'static str;
+  fn apply(&self, z: f32) -> f32;
+  // df/dz as a function of y = f(z)
+  fn derivative(&self, y: f32) -> f32;
+
+  fn parameter(&self) -> Option<f32> {
+    None
+  }
+}
+
+impl<T: Elementwise> Activation for T {
+  fn name(&self) -> &'static str {
+    Elementwise::name(self)
+  }
+
+  fn forward(&self, z: ArrayView1<f32>) -> Array1<f32> {
+    z.mapv(|z| self.apply(z))
+  }
+
+  fn backward(&self, y: ArrayView1<f32>, grad_y: ArrayView1<f32>) -> Array1<f32> {
+    &grad_y * &y.mapv(|y| self.derivative(y))
+  }
+
+  fn clone_box(&self) -> Box<dyn Activation> {
+    Box::new(self.clone())
+  }
+
+  fn parameter(&self) -> Option<f32> {
+    Elementwise::parameter(self)
+  }
+
+  fn forward_batch(&self, z: ArrayView2<f32>) -> Array2<f32> {
+    z.mapv(|z| self.apply(z))
+  }
+
+  fn backward_batch(&self, y: ArrayView2<f32>, grad_y: ArrayView2<f32>) -> Array2<f32> {
+    &grad_y * &y.mapv(|y| self.derivative(y))
+  }
+}
+
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct Sigmoid;
+
+impl Elementwise for Sigmoid {
+  fn name(&self) -> &'static str {
+    "sigmoid"
+  }
+
+  fn apply(&self, z: f32) -> f32 {
+    1.0 / (1.0 + (-z).exp())
+  }
+
+  fn derivative(&self, y: f32) -> f32 {
+    y * (1.0 - y)
+  }
+}
+
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct Tanh;
+
+impl Elementwise for Tanh {
+  fn name(&self) -> &'static str {
+    "tanh"
+  }
+
+  fn apply(&self, z: f32) -> f32 {
+    z.tanh()
+  }
+
+  fn derivative(&self, y: f32) -> f32 {
+    1.0 - y * y
+  }
+}
+
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct Relu;
+
+impl Elementwise for Relu {
+  fn name(&self) -> &'static str {
+    "relu"
+  }
+
+  fn apply(&self, z: f32) -> f32 {
+    z.max(0.0)
+  }
+
+  // 0 at z = 0, like most libraries
+  fn derivative(&self, y: f32) -> f32 {
+    if y > 0.0 { 1.0 } else { 0.0 }
+  }
+}
+
+// `slope` has to be positive, y and z have the same sign then
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct LeakyRelu {
+  pub slope: f32,
+}
+
+impl Elementwise for LeakyRelu {
+  fn name(&self) -> &'static str {
+    "leaky_relu"
+  }
+
+  fn apply(&self, z: f32) -> f32 {
+    if z > 0.0 { z } else { self.slope * z }
+  }
+
+  fn derivative(&self, y: f32) -> f32 {
+    if y > 0.0 { 1.0 } else { self.slope }
+  }
+
+  fn parameter(&self) -> Option<f32> {
+    Some(self.slope)
+  }
+}
+
+// alpha (e^z - 1) for negative z, `alpha` has to be positive
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct Elu {
+  pub alpha: f32,
+}
+
+impl Elementwise for Elu {
+  fn name(&self) -> &'static str {
+    "elu"
+  }
+
+  fn apply(&self, z: f32) -> f32 {
+    if z > 0.0 { z } else { self.alpha * z.exp_m1() }
+  }
+
+  // alpha e^z = y + alpha for negative z
+  fn derivative(&self, y: f32) -> f32 {
+    if y > 0.0 { 1.0 } else { y + self.alpha }
+  }
+
+  fn parameter(&self) -> Option<f32> {
+    Some(self.alpha)
+  }
+}
+
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct Linear;
+
+impl Elementwise for Linear {
+  fn name(&self) -> &'static str {
+    "linear"
+  }
+
+  fn apply(&self, z: f32) -> f32 {
+    z
+  }
+
+  fn derivative(&self, _y: f32) -> f32 {
+    1.0
+  }
+}
+
+// Probabilities over the units of the layer
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct Softmax;
+
+impl Activation for Softmax {
+  fn name(&self) -> &'static str {
+    "softmax"
+  }
+
+  fn forward(&self, z: ArrayView1<f32>) -> Array1<f32> {
+    // shifted by the maximum so that exp can't overflow
+    let max = z.fold(f32::NEG_INFINITY, |m, z| m.max(*z));
+    let e = z.mapv(|z| (z - max).exp());
+    let sum = e.sum();
+    e / sum
+  }
+
+  // dy_i/dz_j = y_i (δ_ij - y_j), so dL/dz = y ⊙ (dL/dy - y · dL/dy)
+  fn backward(&self, y: ArrayView1<f32>, grad_y: ArrayView1<f32>) -> Array1<f32> {
+    let dot = y.dot(&grad_y);
+    &y * &grad_y.mapv(|g| g - dot)
+  }
+
+  fn clone_box(&self) -> Box<dyn Activation> {
+    Box::new(*self)
+  }
+}
+
+// Inverse of `name` and `parameter`
+pub fn activation_by_name(name: &str, parameter: Option<f32>) -> Result<Box<dyn Activation>> {
+  let needs = |what: &str| parameter.ok_or_else(|| Error::other(format!("{} needs a {}", name, what)));
+  Ok(match name {
+    "sigmoid" => Box::new(Sigmoid),
+    "tanh" => Box::new(Tanh),
+    "relu" => Box::new(Relu),
+    "leaky_relu" => Box::new(LeakyRelu { slope: needs("slope")? }),
+    "elu" => Box::new(Elu { alpha: needs("alpha")? }),
+    "linear" => Box::new(Linear),
+    "softmax" => Box::new(Softmax),
+    _ => return Err(Error::other(format!("unknown activation {}", name))),
+  })
+}
+
+// Loss of one sample, averaged over the outputs
+pub trait Loss: Send + Sync {
+  fn name(&self) -> &'static str;
+  fn loss(&self, y: ArrayView1<f32>, target: ArrayView1<f32>) -> f32;
+  // dL/dy
+  fn gradient(&self, y: ArrayView1<f32>, target: ArrayView1<f32>) -> Array1<f32>;
+  fn clone_box(&self) -> Box<dyn Loss>;
+
+  // Mean loss of a batch with one sample per row
+  fn loss_batch(&self, y: ArrayView2<f32>, target: ArrayView2<f32>) -> f32 {
+    let total: f32 = y.rows().into_iter().zip(target.rows()).map(|(y, target)| self.loss(y, target)).sum();
+    total / y.nrows().max(1) as f32
+  }
+
+  // Gradient of `loss_batch`, so every row is scaled down by the batch size
+  fn gradient_batch(&self, y: ArrayView2<f32>, target: ArrayView2<f32>) -> Array2<f32> {
+    let n = y.nrows().max(1) as f32;
+    let mut grad = Array2::zeros(y.raw_dim());
+    for ((mut row, y), target) in grad.rows_mut().into_iter().zip(y.rows()).zip(target.rows()) {
+      row.assign(&(self.gradient(y, target) / n));
+    }
+    grad
+  }
+}
+
+impl Clone for Box<dyn Loss> {
+  fn clone(&self) -> Self {
+    self.clone_box()
+  }
+}
+
+impl fmt::Debug for dyn Loss {
+  fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+    write!(f, "{}", self.name())
+  }
+}
+
+// Half the mean squared error, the gradient is the mean of y - target
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct MeanSquaredError;
+
+impl Loss for MeanSquaredError {
+  fn name(&self) -> &'static str {
+    "mse"
+  }
+
+  fn loss(&self, y: ArrayView1<f32>, target: ArrayView1<f32>) -> f32 {
+    0.5 * (&y - &target).mapv(|d| d * d).sum() / y.len() as f32
+  }
+
+  fn gradient(&self, y: ArrayView1<f32>, target: ArrayView1<f32>) -> Array1<f32> {
+    (&y - &target) / y.len() as f32
+  }
+
+  fn clone_box(&self) -> Box<dyn Loss> {
+    Box::new(*self)
+  }
+}
+
+// Squared error up to `delta`, linear beyond, less sensitive to outliers
+// like the rare large TD errors of a Q-network
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct Huber {
+  pub delta: f32,
+}
+
+impl Loss for Huber {
+  fn name(&self) -> &'static str {
+    "huber"
+  }
+
+  fn loss(&self, y: ArrayView1<f32>, target: ArrayView1<f32>) -> f32 {
+    let delta = self.delta;
+    (&y - &target)
+      .mapv(|d| if d.abs() <= delta { 0.5 * d * d } else { delta * (d.abs() - 0.5 * delta) })
+      .sum() / y.len() as f32
+  }
+
+  fn gradient(&self, y: ArrayView1<f32>, target: ArrayView1<f32>) -> Array1<f32> {
+    let delta = self.delta;
+    (&y - &target).mapv(|d| d.clamp(-delta, delta)) / y.len() as f32
+  }
+
+  fn clone_box(&self) -> Box<dyn Loss> {
+    Box::new(*self)
+  }
+}
+
+// keeps the logarithms of the probability losses finite
+const EPSILON: f32 = 1e-7;
+
+// For sigmoid outputs, every output is the probability of its own class
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct BinaryCrossEntropy;
+
+impl Loss for BinaryCrossEntropy {
+  fn name(&self) -> &'static str {
+    "binary_cross_entropy"
+  }
+
+  fn loss(&self, y: ArrayView1<f32>, target: ArrayView1<f32>) -> f32 {
+    y.iter().zip(target.iter())
+      .map(|(y, t)| {
+        let y = y.clamp(EPSILON, 1.0 - EPSILON);
+        -(t * y.ln() + (1.0 - t) * (1.0 - y).ln())
+      })
+      .sum::<f32>() / y.len() as f32
+  }
+
+  fn gradient(&self, y: ArrayView1<f32>, target: ArrayView1<f32>) -> Array1<f32> {
+    let n = y.len() as f32;
+    let mut grad = Array1::zeros(y.len());
+    for ((g, y), t) in grad.iter_mut().zip(y.iter()).zip(target.iter()) {
+      let y = y.clamp(EPSILON, 1.0 - EPSILON);
+      *g = (y - t) / (y * (1.0 - y)) / n;
+    }
+    grad
+  }
+
+  fn clone_box(&self) -> Box<dyn Loss> {
+    Box::new(*self)
+  }
+}
+
+// Cross-entropy of the softmax of the outputs, meant for a `Linear` output layer
+// (logits): the gradient softmax(y) - target stays exact for confident predictions.
+// Not summed over the outputs, the targets are a probability distribution.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct SoftmaxCrossEntropy;
+
+impl Loss for SoftmaxCrossEntropy {
+  fn name(&self) -> &'static str {
+    "softmax_cross_entropy"
+  }
+
+  fn loss(&self, y: ArrayView1<f32>, target: ArrayView1<f32>) -> f32 {
+    // log softmax = y - max - ln sum e^(y - max)
+    let max = y.fold(f32::NEG_INFINITY, |m, y| m.max(*y));
+    let log_sum = y.mapv(|y| (y - max).exp()).sum().ln();
+    -y.iter().zip(target.iter()).map(|(y, t)| t * (y - max - log_sum)).sum::<f32>()
+  }
+
+  fn gradient(&self, y: ArrayView1<f32>, target: ArrayView1<f32>) -> Array1<f32> {
+    Softmax.forward(y) - target
+  }
+
+  fn clone_box(&self) -> Box<dyn Loss> {
+    Box::new(*self)
+  }
+}
+
+// Gradients of the loss with respect to the parameters of one layer
+#[derive(Clone, Debug)]
+pub struct Gradients {
+  pub weights: Array2<f32>,
+  pub bias: Array1<f32>,
+}
+
+impl Gradients {
+  // One zero buffer per parameter, the state of an optimizer
+  pub fn zeros_like(layers: &[Layer]) -> Vec<Gradients> {
+    layers.iter()
+      .map(|layer| Gradients {
+        weights: Array2::zeros(layer.weights.raw_dim()),
+        bias: Array1::zeros(layer.bias.raw_dim()),
+      })
+      .collect()
+  }
+}
+
+// Turns gradients into parameter updates, keeping whatever state it needs per parameter
+pub trait Optimizer: Send + Sync {
+  fn name(&self) -> &'static str;
+  fn step(&mut self, layers: &mut [Layer], gradients: &[Gradients], learning_rate: f32);
+  fn clone_box(&self) -> Box<dyn Optimizer>;
+}
+
+impl Clone for Box<dyn Optimizer> {
+  fn clone(&self) -> Self {
+    self.clone_box()
+  }
+}
+
+impl fmt::Debug for dyn Optimizer {
+  fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+    write!(f, "{}", self.name())
+  }
+}
+
+// Gradient descent, with heavy-ball or Nesterov momentum if `momentum` > 0
+#[derive(Clone, Debug)]
+pub struct Sgd {
+  pub momentum: f32,
+  pub nesterov: bool,
+  velocity: Vec<Gradients>,
+}
+
+impl Sgd {
+  pub fn new(momentum: f32) -> Self {
+    Sgd { momentum, nesterov: false, velocity: Vec::new() }
+  }
+
+  pub fn nesterov(momentum: f32) -> Self {
+    Sgd { momentum, nesterov: true, velocity: Vec::new() }
+  }
+}
+
+fn sgd_update<D: Dimension>(p: &mut Array<f32, D>, g: &Array<f32, D>, v: &mut Array<f32, D>, rate: f32, momentum: f32, nesterov: bool) {
+  Zip::from(p).and(g).and(v).for_each(|p, g, v| {
+    *v = momentum * *v + g;
+    // Nesterov looks ahead along the new velocity
+    *p -= rate * if nesterov { g + momentum * *v } else { *v };
+  });
+}
+
+impl Optimizer for Sgd {
+  fn name(&self) -> &'static str {
+    if self.nesterov { "nesterov" } else { "sgd" }
+  }
+
+  fn step(&mut self, layers: &mut [Layer], gradients: &[Gradients], learning_rate: f32) {
+    if self.velocity.len() != layers.len() {
+      self.velocity = Gradients::zeros_like(layers);
+    }
+    for ((layer, g), v) in layers.iter_mut().zip(gradients.iter()).zip(self.velocity.iter_mut()) {
+      sgd_update(&mut layer.weights, &g.weights, &mut v.weights, learning_rate, self.momentum, self.nesterov);
+      sgd_update(&mut layer.bias, &g.bias, &mut v.bias, learning_rate, self.momentum, self.nesterov);
+    }
+  }
+
+  fn clone_box(&self) -> Box<dyn Optimizer> {
+    Box::new(self.clone())
+  }
+}
+
+// Divides by a running average of the squared gradients
+#[derive(Clone, Debug)]
+pub struct RmsProp {
+  pub decay: f32,
+  pub epsilon: f32,
+  square: Vec<Gradients>,
+}
+
+impl RmsProp {
+  pub fn new(decay: f32) -> Self {
+    RmsProp { decay, epsilon: 1e-8, square: Vec::new() }
+  }
+}
+
+impl Default for RmsProp {
+  fn default() -> Self {
+    Self::new(0.9)
+  }
+}
+
+fn rms_prop_update<D: Dimension>(p: &mut Array<f32, D>, g: &Array<f32, D>, s: &mut Array<f32, D>, rate: f32, decay: f32, epsilon: f32) {
+  Zip::from(p).and(g).and(s).for_each(|p, g, s| {
+    *s = decay * *s + (1.0 - decay) * g * g;
+    *p -= rate * g / (s.sqrt() + epsilon);
+  });
+}
+
+impl Optimizer for RmsProp {
+  fn name(&self) -> &'static str {
+    "rms_prop"
+  }
+
+  fn step(&mut self, layers: &mut [Layer], gradients: &[Gradients], learning_rate: f32) {
+    if self.square.len() != layers.len() {
+      self.square = Gradients::zeros_like(layers);
+    }
+    for ((layer, g), s) in layers.iter_mut().zip(gradients.iter()).zip(self.square.iter_mut()) {
+      rms_prop_update(&mut layer.weights, &g.weights, &mut s.weights, learning_rate, self.decay, self.epsilon);
+      rms_prop_update(&mut layer.bias, &g.bias, &mut s.bias, learning_rate, self.decay, self.epsilon);
+    }
+  }
+
+  fn clone_box(&self) -> Box<dyn Optimizer> {
+    Box::new(self.clone())
+  }
+}
+
+// Running averages of the gradients and their squares, corrected for starting at 0
+#[derive(Clone, Debug)]
+pub struct Adam {
+  pub beta1: f32,
+  pub beta2: f32,
+  pub epsilon: f32,
+  steps: i32,
+  mean: Vec<Gradients>,
+  square: Vec<Gradients>,
+}
+
+impl Adam {
+  pub fn new(beta1: f32, beta2: f32) -> Self {
+    Adam { beta1, beta2, epsilon: 1e-8, steps: 0, mean: Vec::new(), square: Vec::new() }
+  }
+
+  fn update<D: Dimension>(&self, p: &mut Array<f32, D>, g: &Array<f32, D>, m: &mut Array<f32, D>, v: &mut Array<f32, D>, rate: f32) {
+    let (beta1, beta2) = (self.beta1, self.beta2);
+    let correction1 = 1.0 - beta1.powi(self.steps);
+    let correction2 = 1.0 - beta2.powi(self.steps);
+    Zip::from(p).and(g).and(m).and(v).for_each(|p, g, m, v| {
+      *m = beta1 * *m + (1.0 - beta1) * g;
+      *v = beta2 * *v + (1.0 - beta2) * g * g;
+      *p -= rate * (*m / correction1) / ((*v / correction2).sqrt() + self.epsilon);
+    });
+  }
+}
+
+impl Default for Adam {
+  fn default() -> Self {
+    Self::new(0.9, 0.999)
+  }
+}
+
+impl Optimizer for Adam {
+  fn name(&self) -> &'static str {
+    "adam"
+  }
+
+  fn step(&mut self, layers: &mut [Layer], gradients: &[Gradients], learning_rate: f32) {
+    if self.mean.len() != layers.len() {
+      self.mean = Gradients::zeros_like(layers);
+      self.square = Gradients::zeros_like(layers);
+      self.steps = 0;
+    }
+    self.steps += 1;
+    let mut mean = std::mem::take(&mut self.mean);
+    let mut square = std::mem::take(&mut self.square);
+    for (((layer, g), m), v) in layers.iter_mut().zip(gradients.iter()).zip(mean.iter_mut()).zip(square.iter_mut()) {
+      self.update(&mut layer.weights, &g.weights, &mut m.weights, &mut v.weights, learning_rate);
+      self.update(&mut layer.bias, &g.bias, &mut m.bias, &mut v.bias, learning_rate);
+    }
+    self.mean = mean;
+    self.square = square;
+  }
+
+  fn clone_box(&self) -> Box<dyn Optimizer> {
+    Box::new(self.clone())
+  }
+}
+
+// Learning rate of every epoch of a `train` run, from the base rate of the network
+pub trait Schedule: Send + Sync {
+  fn name(&self) -> &'static str;
+  // `epoch` counts from 0 at the start of the run
+  fn rate(&self, base: f32, epoch: usize) -> f32;
+  fn clone_box(&self) -> Box<dyn Schedule>;
+}
+
+impl Clone for Box<dyn Schedule> {
+  fn clone(&self) -> Self {
+    self.clone_box()
+  }
+}
+
+impl fmt::Debug for dyn Schedule {
+  fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+    write!(f, "{}", self.name())
+  }
+}
+
+// Multiplied by `gamma` every `every` epochs
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct StepDecay {
+  pub every: usize,
+  pub gamma: f32,
+}
+
+impl Schedule for StepDecay {
+  fn name(&self) -> &'static str {
+    "step"
+  }
+
+  fn rate(&self, base: f32, epoch: usize) -> f32 {
+    base * self.gamma.powi((epoch / self.every.max(1)) as i32)
+  }
+
+  fn clone_box(&self) -> Box<dyn Schedule> {
+    Box::new(*self)
+  }
+}
+
+// Multiplied by `gamma` every epoch
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct ExponentialDecay {
+  pub gamma: f32,
+}
+
+impl Schedule for ExponentialDecay {
+  fn name(&self) -> &'static str {
+    "exponential"
+  }
+
+  fn rate(&self, base: f32, epoch: usize) -> f32 {
+    base * self.gamma.powi(epoch as i32)
+  }
+
+  fn clone_box(&self) -> Box<dyn Schedule> {
+    Box::new(*self)
+  }
+}
+
+// Half a cosine from the base rate down to `min_rate` over `epochs`, then flat
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct CosineAnnealing {
+  pub epochs: usize,
+  pub min_rate: f32,
+}
+
+impl Schedule for CosineAnnealing {
+  fn name(&self) -> &'static str {
+    "cosine"
+  }
+
+  fn rate(&self, base: f32, epoch: usize) -> f32 {
+    let progress = epoch.min(self.epochs) as f32 / self.epochs.max(1) as f32;
+    self.min_rate + (base - self.min_rate) * 0.5 * (1.0 + (std::f32::consts::PI * progress).cos())
+  }
+
+  fn clone_box(&self) -> Box<dyn Schedule> {
+    Box::new(*self)
+  }
+}
+
+// How the weights of a layer are drawn, the biases always start at 0
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum Initializer {
+  // Glorot, U(-a, a) with a = sqrt(6 / (inputs + outputs)), keeps the variance of
+  // sigmoid and tanh layers about the same forward and backward
+  XavierUniform,
+  // N(0, 2 / (inputs + outputs))
+  XavierNormal,
+  // Kaiming, N(0, 2 / inputs), makes up for ReLU zeroing half of its inputs
+  He,
+  // orthonormal rows (or columns, whichever are fewer) times `gain`
+  Orthogonal { gain: f32 },
+  Zeros,
+  Constant(f32),
+}
 
-fn sigmoid(x: f32) -> f32 {
-  1.0 / (1.0 + (-x).exp())
+impl Initializer {
+  // He for the ReLU family, Xavier uniform for everything else
+  pub fn for_activation(activation: &dyn Activation) -> Initializer {
+    match activation.name() {
+      "relu" | "leaky_relu" | "elu" => Initializer::He,
+      _ => Initializer::XavierUniform,
+    }
+  }
+
+  // outputs x inputs
+  pub fn weights<R: Rng>(&self, n_inputs: usize, n_outputs: usize, rng: &mut R) -> Array2<f32> {
+    let shape = (n_outputs, n_inputs);
+    let fans = (n_inputs + n_outputs).max(1) as f32;
+    match *self {
+      Initializer::XavierUniform => {
+        let limit = (6.0 / fans).sqrt();
+        Array2::random_using(shape, Uniform::new_inclusive(-limit, limit), rng)
+      },
+      Initializer::XavierNormal => Array2::random_using(shape, StandardNormal, rng) * (2.0 / fans).sqrt(),
+      Initializer::He => Array2::random_using(shape, StandardNormal, rng) * (2.0 / n_inputs.max(1) as f32).sqrt(),
+      Initializer::Orthogonal { gain } => orthogonal(n_outputs, n_inputs, rng) * gain,
+      Initializer::Zeros => Array2::zeros(shape),
+      Initializer::Constant(value) => Array2::from_elem(shape, value),
+    }
+  }
 }
 
-fn d_sigmoid(x: f32) -> f32 {
-  x * (1.0 - x)
+// Gram-Schmidt on the columns of a tall gaussian matrix, transposed for a wide one
+fn orthogonal<R: Rng>(rows: usize, cols: usize, rng: &mut R) -> Array2<f32> {
+  let (tall, short) = (rows.max(cols), rows.min(cols));
+  let mut q: Array2<f32> = Array2::random_using((tall, short), StandardNormal, rng);
+  for j in 0..short {
+    for k in 0..j {
+      let projection = q.column(k).dot(&q.column(j));
+      let basis = q.column(k).to_owned();
+      q.column_mut(j).scaled_add(-projection, &basis);
+    }
+    let norm = q.column(j).dot(&q.column(j)).sqrt();
+    q.column_mut(j).mapv_inplace(|x| x / norm);
+  }
+  if rows >= cols { q } else { q.reversed_axes() }
 }
 
-fn relu(x: f32) -> f32 {
-  x.max(0.0)
+// Fully connected layer y = f(W x + b)
+#[derive(Clone, Debug)]
+pub struct Layer {
+  // outputs x inputs
+  pub weights: Array2<f32>,
+  pub bias: Array1<f32>,
+  pub activation: Box<dyn Activation>,
+  // used by `initialize`
+  pub initializer: Initializer,
+  // share of the outputs dropped in training mode, in [0, 1), see `set_dropout`
+  dropout: f32,
 }
 
-fn d_relu(x: f32) -> f32 {
-  if x > 0.0 {
-    1.0
-  } else {
-    0.1
+impl Layer {
+  // Weights from the default initializer of `activation`
+  pub fn new<R: Rng>(n_inputs: usize, n_outputs: usize, activation: Box<dyn Activation>, rng: &mut R) -> Layer {
+    let initializer = Initializer::for_activation(activation.as_ref());
+    Layer {
+      weights: initializer.weights(n_inputs, n_outputs, rng),
+      bias: Array1::zeros(n_outputs),
+      activation,
+      initializer,
+      dropout: 0.0,
+    }
+  }
+
+  // Draws new weights with `initializer` and resets the bias
+  pub fn initialize<R: Rng>(&mut self, rng: &mut R) {
+    self.weights = self.initializer.weights(self.n_inputs(), self.n_outputs(), rng);
+    self.bias.fill(0.0);
+  }
+
+  pub fn dropout(&self) -> f32 {
+    self.dropout
+  }
+
+  // Fails for `p` outside [0, 1), a layer can't drop every unit
+  pub fn set_dropout(&mut self, p: f32) -> Result<()> {
+    if !(0.0..1.0).contains(&p) {
+      return Err(Error::other(format!("dropout {} must be in [0, 1)", p)));
+    }
+    self.dropout = p;
+    Ok(())
+  }
+
+  pub fn n_inputs(&self) -> usize {
+    self.weights.ncols()
+  }
+
+  pub fn n_outputs(&self) -> usize {
+    self.weights.nrows()
+  }
+
+  pub fn forward(&self, x: ArrayView1<f32>) -> Array1<f32> {
+    let z: Array1<f32> = self.weights.dot(&x) + &self.bias;
+    self.activation.forward(z.view())
+  }
+
+  // `x` holds one sample per row
+  pub fn forward_batch(&self, x: ArrayView2<f32>) -> Array2<f32> {
+    let z = x.dot(&self.weights.t()) + &self.bias;
+    self.activation.forward_batch(z.view())
+  }
+
+  // Like `backward` for a batch, the gradients are summed over the rows
+  pub fn backward_batch(&self, x: ArrayView2<f32>, y: ArrayView2<f32>, grad_y: ArrayView2<f32>) -> (Gradients, Array2<f32>) {
+    let delta = self.activation.backward_batch(y, grad_y);
+    let grad_x = delta.dot(&self.weights);
+    let gradients = Gradients {
+      weights: delta.t().dot(&x),
+      bias: delta.sum_axis(Axis(0)),
+    };
+    (gradients, grad_x)
+  }
+
+  // From the input `x` and output `y` of the forward pass and dL/dy,
+  // returns the gradients of the parameters and dL/dx
+  pub fn backward(&self, x: ArrayView1<f32>, y: ArrayView1<f32>, grad_y: ArrayView1<f32>) -> (Gradients, Array1<f32>) {
+    let delta = self.activation.backward(y, grad_y);
+    let grad_x = self.weights.t().dot(&delta);
+    let weights = delta.view().insert_axis(Axis(1)).dot(&x.insert_axis(Axis(0)));
+    (Gradients { weights, bias: delta }, grad_x)
+  }
+}
+
+// Training draws new dropout masks for every pass, inference drops nothing
+// and is deterministic. The `&self` methods of `ANN` always run in inference mode.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Mode {
+  Training,
+  Inference,
+}
+
+// What backprop needs from a forward pass
+struct Pass {
+  // input of every layer after dropout, the last one is the output of the network
+  inputs: Vec<Array2<f32>>,
+  // output of every layer before dropout
+  outputs: Vec<Array2<f32>>,
+  // 0 for a dropped unit, 1 / (1 - dropout) for a kept one
+  masks: Vec<Option<Array2<f32>>>,
+}
+
+// Dropout only with an `rng`, that is in training mode
+fn forward_pass(layers: &[Layer], inputs: ArrayView2<f32>, mut rng: Option<&mut StdRng>) -> Pass {
+  let mut pass = Pass {
+    inputs: vec![inputs.to_owned()],
+    outputs: Vec::with_capacity(layers.len()),
+    masks: Vec::with_capacity(layers.len()),
+  };
+  for layer in layers.iter() {
+    let y = layer.forward_batch(pass.inputs[pass.inputs.len() - 1].view());
+    let mask = match rng.as_deref_mut() {
+      Some(rng) if layer.dropout > 0.0 => {
+        let keep = 1.0 - layer.dropout;
+        Some(Array2::from_shape_fn(y.raw_dim(), |_| if rng.gen::<f32>() < keep { 1.0 / keep } else { 0.0 }))
+      },
+      _ => None,
+    };
+    pass.inputs.push(match mask.as_ref() {
+      Some(mask) => &y * mask,
+      None => y.clone(),
+    });
+    pass.outputs.push(y);
+    pass.masks.push(mask);
+  }
+  pass
+}
+
+// Scales all gradients together so that their norm is at most `max_norm`,
+// returns the norm before
+pub fn clip_gradients(gradients: &mut [Gradients], max_norm: f32) -> f32 {
+  let squares: f32 = gradients.iter()
+    .map(|g| g.weights.iter().chain(g.bias.iter()).map(|x| x * x).sum::<f32>())
+    .sum();
+  let norm = squares.sqrt();
+  if norm > max_norm {
+    let scale = max_norm / norm;
+    for g in gradients.iter_mut() {
+      g.weights *= scale;
+      g.bias *= scale;
+    }
   }
+  norm
 }
 
+// Version of the JSON written by `ANN::to_json`, bumped on incompatible changes
+pub const FORMAT_VERSION: u32 = 1;
+
+#[derive(Serialize, Deserialize)]
+struct LayerRecord {
+  activation: String,
+  #[serde(default, skip_serializing_if = "Option::is_none")]
+  parameter: Option<f32>,
+  inputs: usize,
+  outputs: usize,
+  // row by row, outputs x inputs
+  weights: Vec<f32>,
+  bias: Vec<f32>,
+  #[serde(default, skip_serializing_if = "is_zero")]
+  dropout: f32,
+}
+
+fn is_zero(x: &f32) -> bool {
+  *x == 0.0
+}
+
+#[derive(Serialize, Deserialize)]
+struct NetworkRecord {
+  version: u32,
+  layers: Vec<LayerRecord>,
+}
+
+#[derive(Clone, Debug)]
 pub struct ANN {
   epoch: i64,
-  learning_rate: f32,
-  weights_1: Array2<f32>,
-  weights_2: Array2<f32>,
-  bias_1: Array1<f32>,
-  bias_2: Array1<f32>,
+  // base rate, see `schedule`
+  pub learning_rate: f32,
+  pub layers: Vec<Layer>,
+  pub loss: Box<dyn Loss>,
+  // samples per update of `train`
+  pub batch_size: usize,
+  pub optimizer: Box<dyn Optimizer>,
+  // L2 penalty 0.5 * weight_decay * |W|^2 on the weights (not the biases), it is
+  // added to the gradients of `train`, the reported losses don't include it
+  pub weight_decay: f32,
+  // largest norm of all gradients of an update together, `None` doesn't clip
+  pub clip_norm: Option<f32>,
+  // learning rate per epoch of `train`, the base rate throughout if `None`
+  pub schedule: Option<Box<dyn Schedule>>,
+  // shuffles the samples every epoch
+  rng: StdRng,
 }
 
 impl ANN {
-  pub fn new(n_neurons_per_layer: [usize; 3]) -> ANN {
-    let w1 = Array2::random((n_neurons_per_layer[0], n_neurons_per_layer[1]), StandardNormal) * 0.1;
-    let mut w2 = Array2::random((n_neurons_per_layer[1], n_neurons_per_layer[2]), StandardNormal) * 0.1;
-    w2.swap_axes(0, 1); // special case for 1-D matrix and dot implementation in ndarray
+  // Sigmoid hidden layers and a leaky ReLU output, `sizes` starts with the number of inputs
+  pub fn new(sizes: &[usize]) -> ANN {
+    let mut activations: Vec<Box<dyn Activation>> = (2..sizes.len()).map(|_| Box::new(Sigmoid) as Box<dyn Activation>).collect();
+    activations.push(Box::new(LeakyRelu { slope: 0.1 }));
+    ANN::with_activations(sizes, activations)
+  }
 
-    let b1 = Array1::zeros(n_neurons_per_layer[1]);
-    let b2 = Array1::zeros(n_neurons_per_layer[2]);
-    
+  // One activation per layer, that is one less than `sizes`. The weights are
+  // drawn from seed 0, the network is the same as after `initialize(0)`.
+  pub fn with_activations(sizes: &[usize], activations: Vec<Box<dyn Activation>>) -> ANN {
+    assert!(sizes.len() >= 2, "a network needs at least an input and an output size");
+    assert_eq!(activations.len(), sizes.len() - 1, "one activation per layer");
+    let mut rng = StdRng::seed_from_u64(0);
+    let layers = sizes.windows(2)
+      .zip(activations)
+      .map(|(pair, activation)| Layer::new(pair[0], pair[1], activation, &mut rng))
+      .collect();
     ANN {
       epoch: 0,
       learning_rate: 0.1,
-      weights_1: w1,
-      weights_2: w2,
-      bias_1: b1,
-      bias_2: b2,
+      layers,
+      loss: Box::new(MeanSquaredError),
+      batch_size: 1,
+      optimizer: Box::new(Sgd::new(0.0)),
+      weight_decay: 0.0,
+      clip_norm: None,
+      schedule: None,
+      rng,
     }
   }
 
-  pub fn back_prop_layer_2(&mut self, y1: ArrayView1<f32>, y2: f32, y: f32) -> f32 {
-    let diff = y - y2;
-    let grad_2 = d_relu(y2); // d_sigmoid(y2);
-    let d_2: f32 = diff * grad_2;
-    self.bias_2 = &self.bias_2 + d_2 * self.learning_rate;
-    
-    let dw2: Array1<f32> = d_2 * &y1;
-    self.weights_2 = &self.weights_2 + &dw2 * self.learning_rate;
+  pub fn n_inputs(&self) -> usize {
+    self.layers[0].n_inputs()
+  }
 
-    d_2
+  pub fn n_outputs(&self) -> usize {
+    self.layers[self.layers.len() - 1].n_outputs()
   }
 
-  pub fn back_prop_layer_1(&mut self, y1: ArrayView1<f32>, d2: f32, x: ArrayView1<f32>) {
-    let i2: Array1<f32> = Array1::ones(y1.raw_dim());
-    let grad_1: Array1<f32> = &y1 * (&i2 - &y1); // sigmoid derivative
-    // let w2: ArrayView1<f32> = self.weights_2.index_axis(Axis(0), 0);
-    let d_1: Array2<f32> = d2 * &grad_1 * &self.weights_2;
-    self.bias_1 = &self.bias_1 + &d_1.index_axis(Axis(0), 0) * self.learning_rate;
-    
-    // let d_1_t: ArrayView2<f32> = d_1.insert_axis(Axis(0)).t(); // shape 2,1
-    let dw1: Array2<f32> = &d_1.t() * &x;
-    self.weights_1 = &self.weights_1 + &dw1 * self.learning_rate;
-  }
-
-  pub fn train(&mut self, epochs: i32, inputs: Array2<f32>, outputs: Array1<f32>) {
-    println!("Training started >>>>>>");
-    for _ in 0..epochs {
-      let n_training_steps = inputs.shape()[0];
-      for idx in 0..n_training_steps {
-        let x: ArrayView1<f32> = inputs.index_axis(Axis(0), idx);
-        let y: &f32 = outputs.get(idx).unwrap();
-
-        // First hidden layer
-        let y_1: Array1<f32> = self.forward_layer_1(x);
-        // Output
-        let y_2: Array1<f32> = self.forward_layer_2(y_1.view());
-        let y_: f32 = y_2.get(0).unwrap().to_owned();
-
-        // let diff = y - y_;
-        // let grad_2 = 1.0; // y_ * (1.0 - y_); // sigmoid derivative
-        // let d_2: f32 = diff * grad_2;
-        // let dw2: Array1<f32> = &y_1 * d_2;
-        
-        // self.weights_2 = &self.weights_2 + &dw2 * self.learning_rate;
-        // self.bias_2 = &self.bias_2 + d_2 * self.learning_rate;
-        
-        let d_2: f32 = self.back_prop_layer_2(y_1.view(), y_, *y);
-
-        // let i2: Array1<f32> = Array1::ones(y_1.raw_dim());
-        // let grad_1: Array1<f32> = &y_1 * (&i2 - &y_1); // sigmoid derivative
-        // let w2 = self.weights_2.clone();
-        // let d_1: Array1<f32> = w2.remove_axis(Axis(0)) * d_2 * &grad_1;
-        // self.bias_1 = &self.bias_1 + &d_1 * self.learning_rate;
-        // let d_1_t: Array2<f32> = d_1.insert_axis(Axis(0)); // shape 2,1
-        // let dw1: Array2<f32> = &d_1_t.t() * &x;
-        // self.weights_1 = &self.weights_1 + &dw1 * self.learning_rate;
-
-        self.back_prop_layer_1(y_1.view(), d_2, x);
+  pub fn forward(&self, x: ArrayView1<f32>) -> Array1<f32> {
+    self.forward_all(x).pop().unwrap()
+  }
+
+  // Input followed by the output of every layer
+  pub fn forward_all(&self, x: ArrayView1<f32>) -> Vec<Array1<f32>> {
+    let mut outputs = vec![x.to_owned()];
+    for layer in self.layers.iter() {
+      let y = layer.forward(outputs[outputs.len() - 1].view());
+      outputs.push(y);
+    }
+    outputs
+  }
+
+  // Rows of the output for the rows of `inputs`
+  pub fn forward_batch(&self, inputs: ArrayView2<f32>) -> Array2<f32> {
+    self.forward_all_batch(inputs).pop().unwrap()
+  }
+
+  pub fn forward_all_batch(&self, inputs: ArrayView2<f32>) -> Vec<Array2<f32>> {
+    let mut outputs = vec![inputs.to_owned()];
+    for layer in self.layers.iter() {
+      let y = layer.forward_batch(outputs[outputs.len() - 1].view());
+      outputs.push(y);
+    }
+    outputs
+  }
+
+  // Loss of one sample and the gradients of every layer
+  pub fn gradients(&self, x: ArrayView1<f32>, target: ArrayView1<f32>) -> (f32, Vec<Gradients>) {
+    self.gradients_batch(x.insert_axis(Axis(0)), target.insert_axis(Axis(0)))
+  }
+
+  // Mean loss of a batch and the gradients of that mean, in inference mode
+  pub fn gradients_batch(&self, inputs: ArrayView2<f32>, targets: ArrayView2<f32>) -> (f32, Vec<Gradients>) {
+    self.backprop(&forward_pass(&self.layers, inputs, None), targets)
+  }
+
+  fn backprop(&self, pass: &Pass, targets: ArrayView2<f32>) -> (f32, Vec<Gradients>) {
+    let y = pass.inputs[pass.inputs.len() - 1].view();
+    let loss = self.loss.loss_batch(y, targets);
+    let mut grad_y = self.loss.gradient_batch(y, targets);
+    let mut gradients = Vec::with_capacity(self.layers.len());
+    for (i, layer) in self.layers.iter().enumerate().rev() {
+      if let Some(mask) = pass.masks[i].as_ref() {
+        grad_y *= mask;
       }
+      let (layer_gradients, grad_x) = layer.backward_batch(pass.inputs[i].view(), pass.outputs[i].view(), grad_y.view());
+      gradients.push(layer_gradients);
+      grad_y = grad_x;
+    }
+    gradients.reverse();
+    (loss, gradients)
+  }
+
+  // Output for the rows of `inputs`, with dropout in training mode
+  pub fn forward_in(&mut self, inputs: ArrayView2<f32>, mode: Mode) -> Array2<f32> {
+    match mode {
+      Mode::Inference => self.forward_batch(inputs),
+      Mode::Training => forward_pass(&self.layers, inputs, Some(&mut self.rng)).inputs.pop().unwrap(),
     }
   }
 
-  pub fn forward_layer_1(&self, x: ArrayView1<f32>) -> Array1<f32> {
-    let y_1: Array1<f32> = self.weights_1.dot(&x) + &self.bias_1;
-    y_1.mapv(sigmoid)
+  // One step of the optimizer at the base learning rate
+  pub fn apply(&mut self, gradients: &[Gradients]) {
+    self.optimizer.step(&mut self.layers, gradients, self.learning_rate);
   }
 
-  pub fn forward_layer_2(&self, y: ArrayView1<f32>) -> Array1<f32> {
-    let y_2: Array1<f32> = self.weights_2.dot(&y) + &self.bias_2;
-    y_2.mapv(relu)
+  // Restarts the shuffling of `train` from `seed`
+  pub fn seed(&mut self, seed: u64) {
+    self.rng = StdRng::seed_from_u64(seed);
   }
 
-  pub fn forward(&self, x: ArrayView1<f32>) -> Array1<f32> {
-    let mut y_1: Array1<f32> = self.weights_1.dot(&x) + &self.bias_1;
-    y_1 = y_1.mapv(sigmoid);
-    let mut y_2: Array1<f32> = self.weights_2.dot(&y_1) + &self.bias_2;
-    y_2 = y_2.mapv(relu);
-    y_2
+  // Draws the weights of every layer with its initializer and also restarts the
+  // shuffling, the same seed gives the same network and the same training
+  pub fn initialize(&mut self, seed: u64) {
+    let mut rng = StdRng::seed_from_u64(seed);
+    for layer in self.layers.iter_mut() {
+      layer.initialize(&mut rng);
+    }
+    self.rng = rng;
+  }
+
+  // Mean loss over the rows of `inputs` and `targets`
+  pub fn evaluate(&self, inputs: ArrayView2<f32>, targets: ArrayView2<f32>) -> f32 {
+    self.loss.loss_batch(self.forward_batch(inputs).view(), targets)
+  }
+
+  // One row of `targets` per sample, as wide as the output layer. Every epoch goes
+  // through the samples in a new random order, `batch_size` of them per update.
+  // Every call is a new run, the schedule starts over at epoch 0.
+  // Returns the mean loss of every epoch, taken during the updates.
+  pub fn train(&mut self, epochs: i32, inputs: Array2<f32>, targets: Array2<f32>) -> Vec<f32> {
+    (0..epochs.max(0) as usize)
+      .map(|run_epoch| self.train_epoch(inputs.view(), targets.view(), run_epoch))
+      .collect()
+  }
+
+  // One pass over the samples in training mode, `run_epoch` counts the epochs of the current run for the schedule
+  pub fn train_epoch(&mut self, inputs: ArrayView2<f32>, targets: ArrayView2<f32>, run_epoch: usize) -> f32 {
+    assert_eq!(inputs.nrows(), targets.nrows(), "one target per sample");
+    assert_eq!(inputs.ncols(), self.n_inputs(), "inputs don't match the input layer");
+    assert_eq!(targets.ncols(), self.n_outputs(), "targets don't match the output layer");
+    let rate = self.schedule.as_ref().map_or(self.learning_rate, |s| s.rate(self.learning_rate, run_epoch));
+    let mut order: Vec<usize> = (0..inputs.nrows()).collect();
+    order.shuffle(&mut self.rng);
+    let mut total = 0.0;
+    for batch in order.chunks(self.batch_size.max(1)) {
+      let x = inputs.select(Axis(0), batch);
+      let target = targets.select(Axis(0), batch);
+      let pass = forward_pass(&self.layers, x.view(), Some(&mut self.rng));
+      let (loss, mut gradients) = self.backprop(&pass, target.view());
+      if self.weight_decay > 0.0 {
+        for (g, layer) in gradients.iter_mut().zip(self.layers.iter()) {
+          g.weights.scaled_add(self.weight_decay, &layer.weights);
+        }
+      }
+      if let Some(max_norm) = self.clip_norm {
+        clip_gradients(&mut gradients, max_norm);
+      }
+      self.optimizer.step(&mut self.layers, &gradients, rate);
+      total += loss * batch.len() as f32;
+    }
+    self.epoch += 1;
+    total / inputs.nrows().max(1) as f32
+  }
+
+  // dL/dp by a central difference, `parameter` picks p out of the network
+  fn central_difference(
+    &mut self,
+    inputs: ArrayView2<f32>,
+    targets: ArrayView2<f32>,
+    epsilon: f32,
+    parameter: impl Fn(&mut ANN) -> &mut f32,
+  ) -> f32 {
+    let original = *parameter(self);
+    *parameter(self) = original + epsilon;
+    let plus = self.evaluate(inputs, targets);
+    *parameter(self) = original - epsilon;
+    let minus = self.evaluate(inputs, targets);
+    *parameter(self) = original;
+    (plus - minus) / (2.0 * epsilon)
+  }
+
+  // Compares the gradients of `gradients_batch` on a batch with central differences
+  // of every parameter and returns the largest relative error |a - n| / max(|a| + |n|, 1e-3)
+  // of every layer. Gradients much smaller than 1e-3 are lost to f32 rounding,
+  // those are compared absolutely.
+  pub fn gradient_check(&self, inputs: ArrayView2<f32>, targets: ArrayView2<f32>, epsilon: f32) -> Vec<f32> {
+    let (_, analytic) = self.gradients_batch(inputs, targets);
+    let relative = |a: f32, n: f32| (a - n).abs() / (a.abs() + n.abs()).max(1e-3);
+    let mut probe = self.clone();
+    let mut errors = Vec::with_capacity(self.layers.len());
+    for (l, gradients) in analytic.iter().enumerate() {
+      let mut error: f32 = 0.0;
+      for (index, a) in gradients.weights.indexed_iter() {
+        let n = probe.central_difference(inputs, targets, epsilon, |net| &mut net.layers[l].weights[index]);
+        error = error.max(relative(*a, n));
+      }
+      for (i, a) in gradients.bias.iter().enumerate() {
+        let n = probe.central_difference(inputs, targets, epsilon, |net| &mut net.layers[l].bias[i]);
+        error = error.max(relative(*a, n));
+      }
+      errors.push(error);
+    }
+    errors
+  }
+
+  // Architecture, activations and weights, not the training state
+  pub fn to_json(&self) -> Result<String> {
+    let record = NetworkRecord {
+      version: FORMAT_VERSION,
+      layers: self.layers.iter()
+        .map(|layer| LayerRecord {
+          activation: layer.activation.name().to_string(),
+          parameter: layer.activation.parameter(),
+          inputs: layer.n_inputs(),
+          outputs: layer.n_outputs(),
+          weights: layer.weights.iter().cloned().collect(),
+          bias: layer.bias.to_vec(),
+          dropout: layer.dropout,
+        })
+        .collect(),
+    };
+    Ok(serde_json::to_string_pretty(&record)?)
+  }
+
+  pub fn from_json(json: &str) -> Result<ANN> {
+    let record: NetworkRecord = serde_json::from_str(json)?;
+    if record.version != FORMAT_VERSION {
+      return Err(Error::other(format!("unsupported network format version {}, expected {}", record.version, FORMAT_VERSION)));
+    }
+    if record.layers.is_empty() {
+      return Err(Error::other("the network has no layers"));
+    }
+    let mut sizes = vec![record.layers[0].inputs];
+    let mut activations = Vec::new();
+    for (i, layer) in record.layers.iter().enumerate() {
+      if layer.inputs != sizes[i] {
+        return Err(Error::other(format!("layer {} takes {} inputs but gets {}", i + 1, layer.inputs, sizes[i])));
+      }
+      if layer.weights.len() != layer.inputs * layer.outputs {
+        return Err(Error::other(format!("layer {} has {} weights, {} x {} needs {}",
+          i + 1, layer.weights.len(), layer.outputs, layer.inputs, layer.inputs * layer.outputs)));
+      }
+      if layer.bias.len() != layer.outputs {
+        return Err(Error::other(format!("layer {} has {} biases for {} outputs", i + 1, layer.bias.len(), layer.outputs)));
+      }
+      sizes.push(layer.outputs);
+      activations.push(activation_by_name(&layer.activation, layer.parameter)
+        .map_err(|e| Error::other(format!("layer {}: {}", i + 1, e)))?);
+    }
+    let mut network = ANN::with_activations(&sizes, activations);
+    for (i, (layer, record)) in network.layers.iter_mut().zip(record.layers).enumerate() {
+      layer.set_dropout(record.dropout).map_err(|e| Error::other(format!("layer {}: {}", i + 1, e)))?;
+      layer.weights = Array2::from_shape_vec((record.outputs, record.inputs), record.weights).map_err(Error::other)?;
+      layer.bias = Array1::from_vec(record.bias);
+    }
+    Ok(network)
+  }
+
+  pub fn save(&self, path: &str) -> Result<()> {
+    std::fs::write(path, self.to_json()?)
+  }
+
+  pub fn load(path: &str) -> Result<ANN> {
+    ANN::from_json(&std::fs::read_to_string(path)?)
+  }
+
+  // Writes weights_1.npy, bias_1.npy, weights_2.npy, ... into `dir`,
+  // the weights are outputs x inputs like `Layer::weights`
+  pub fn save_npy(&self, dir: &str) -> Result<()> {
+    std::fs::create_dir_all(dir)?;
+    for (i, layer) in self.layers.iter().enumerate() {
+      npy::write(&format!("{}/weights_{}.npy", dir, i + 1), layer.weights.view())?;
+      npy::write(&format!("{}/bias_{}.npy", dir, i + 1), layer.bias.view())?;
+    }
+    Ok(())
+  }
+
+  // Replaces the weights with the arrays of `save_npy`, they must fit the layers
+  pub fn load_npy(&mut self, dir: &str) -> Result<()> {
+    let mut loaded = Vec::with_capacity(self.layers.len());
+    for (i, layer) in self.layers.iter().enumerate() {
+      let weights = npy::read(&format!("{}/weights_{}.npy", dir, i + 1))?;
+      if weights.shape() != layer.weights.shape() {
+        return Err(Error::other(format!("weights_{}.npy has shape {:?}, layer {} needs {:?}",
+          i + 1, weights.shape(), i + 1, layer.weights.shape())));
+      }
+      let bias = npy::read(&format!("{}/bias_{}.npy", dir, i + 1))?;
+      if bias.shape() != layer.bias.shape() {
+        return Err(Error::other(format!("bias_{}.npy has shape {:?}, layer {} needs {:?}",
+          i + 1, bias.shape(), i + 1, layer.bias.shape())));
+      }
+      loaded.push((weights.into_dimensionality::<Ix2>().map_err(Error::other)?,
+        bias.into_dimensionality::<Ix1>().map_err(Error::other)?));
+    }
+    for (layer, (weights, bias)) in self.layers.iter_mut().zip(loaded) {
+      layer.weights = weights;
+      layer.bias = bias;
+    }
+    Ok(())
   }
 
   pub fn print(&self) {
-    println!("weights 1: {:?}", self.weights_1);
-    println!("bias 1: {:?}", self.bias_1);
-    println!("weights 2: {:?}", self.weights_2);
-    println!("bias 2: {:?}", self.bias_2);
+    for (i, layer) in self.layers.iter().enumerate() {
+      println!("weights {}: {:?}", i + 1, layer.weights);
+      println!("bias {}: {:?}", i + 1, layer.bias);
+    }
   }
 
 }
@@ -146,8 +1257,20 @@ mod tests {
   
   use ndarray::prelude::*;
   use ndarray::{arr1, arr2, Array1, Array2};
+  use ndarray_rand::RandomExt;
+  use ndarray_rand::rand_distr::StandardNormal;
+  use rand::{Rng, SeedableRng};
+  use rand::rngs::StdRng;
+
+  use crate::npy;
 
-  use super::ANN;
+  use super::{
+    clip_gradients,
+    Activation, Adam, BinaryCrossEntropy, CosineAnnealing, Elementwise, Elu, ExponentialDecay, Gradients, Huber,
+    Initializer, Layer, LeakyRelu,
+    Linear, Loss, MeanSquaredError, Mode, Optimizer, Relu, RmsProp, Schedule, Sgd, Sigmoid, Softmax, SoftmaxCrossEntropy,
+    StepDecay, Tanh, ANN, FORMAT_VERSION,
+  };
 
   fn take_first(a: Array1<f32>) -> f32 {
     // y_2.get(0).unwrap().to_owned()
@@ -259,14 +1382,14 @@ mod tests {
 
   #[test]
   fn forward_layer_1() {
-    let mut network = ANN::new([2, 2, 1]);
-    network.weights_1 = arr2(&[
+    let mut network = ANN::new(&[2, 2, 1]);
+    network.layers[0].weights = arr2(&[
       [-0.5, -0.5],
       [-0.5, -0.5],
     ]);
     
     let x = arr1(&[1.0, 1.0]);
-    let y = network.forward_layer_1(x.view());
+    let y = network.layers[0].forward(x.view());
     let expected = 1.0 / (1.0 + E);
     let y_: Array1<f32> = arr1(&[expected, expected]);
     assert!(y == y_, "y = {}, expected = {}", y, y_);
@@ -274,27 +1397,32 @@ mod tests {
 
   #[test]
   fn forward_layer_2() {
-    let mut network = ANN::new([2, 2, 1]);
-    network.weights_2 = arr2(&[[1.0, 1.0]]);
+    let mut network = ANN::new(&[2, 2, 1]);
+    network.layers[1].weights = arr2(&[[1.0, 1.0]]);
     
     let e = 1.0 / (1.0 + E);
     let y: Array1<f32> = arr1(&[e, e]);
-    let z = network.forward_layer_2(y.view());
+    let z = network.layers[1].forward(y.view());
     let expected = 2.0 * e;
     let z_: Array1<f32> = arr1(&[expected]);
     assert!(z == z_, "z = {}, expected = {}", z, z_);
   }
 
-  #[test]
-  fn xor_forward_pretrained() {
-    let mut network = ANN::new([2, 2, 1]);
-    network.weights_1 = arr2(&[
+  fn pretrained_xor() -> ANN {
+    let mut network = ANN::new(&[2, 2, 1]);
+    network.layers[0].weights = arr2(&[
       [2.27, -2.23],
       [-2.698, 2.64],
     ]);
-    network.bias_1 = arr1(&[-1.507, -1.896]);
-    network.weights_2 = arr2(&[[2.618, 2.58]]);
-    network.bias_2 = arr1(&[-0.812]);
+    network.layers[0].bias = arr1(&[-1.507, -1.896]);
+    network.layers[1].weights = arr2(&[[2.618, 2.58]]);
+    network.layers[1].bias = arr1(&[-0.812]);
+    network
+  }
+
+  #[test]
+  fn xor_forward_pretrained() {
+    let network = pretrained_xor();
 
     let x_1 = arr1(&[0.0, 0.0]);
     let y_1 = take_first(network.forward(x_1.view()));
@@ -343,46 +1471,46 @@ mod tests {
 
   #[test]
   fn back_prop_layer_2() {
-    let mut network = ANN::new([2, 2, 1]);
-    network.weights_2 = arr2(&[[0.0, 0.0]]);
-    network.bias_2 = arr1(&[0.0]);
-
-    // let y1: Array1<f32> = arr1(&[1.0, 1.0]);
-    // let y2 = 1.0;
-    // let y = 1.0;
-    // let d2 = network.back_prop_layer_2(y1.view(), y2, y);
-
-    // assert!(network.bias_2 == arr1(&[0.0]), "bias 2 = {}", network.bias_2);
-    // assert!(network.weights_2 == arr2(&[[0.0, 0.0]]), "weight 2 = {}", network.weights_2);
-    // assert!(d2 == 0.0, "d2 = {}", d2);
+    let mut network = ANN::new(&[2, 2, 1]);
+    network.layers[1].weights = arr2(&[[1.0, 1.0]]);
+    network.layers[1].bias = arr1(&[0.0]);
 
+    // output 0.5 for a target of 1, squared error
     let y1: Array1<f32> = arr1(&[1.0, 2.0]);
-    let y2 = 0.5;
-    let y = 1.0;
-    let d2 = network.back_prop_layer_2(y1.view(), y2, y);
+    let y2: Array1<f32> = arr1(&[0.5]);
+    let grad_y2: Array1<f32> = arr1(&[0.5 - 1.0]);
+    let (gradients, grad_y1) = network.layers[1].backward(y1.view(), y2.view(), grad_y2.view());
+    let layer = &mut network.layers[1];
+    layer.weights.scaled_add(-0.1, &gradients.weights);
+    layer.bias.scaled_add(-0.1, &gradients.bias);
 
-    assert!(network.bias_2 == arr1(&[0.05]), "bias 2 = {}", network.bias_2);
-    assert!(network.weights_2 == arr2(&[[0.05, 0.1]]), "weight 2 = {}", network.weights_2);
-    assert!(d2 == 0.5, "d2 = {}", d2);
+    assert!(layer.bias == arr1(&[0.05]), "bias 2 = {}", layer.bias);
+    assert!(layer.weights == arr2(&[[1.05, 1.1]]), "weight 2 = {}", layer.weights);
+    // computed with the weights before the update
+    assert!(grad_y1 == arr1(&[-0.5, -0.5]), "grad y1 = {}", grad_y1);
   }
 
   #[test]
   fn back_prop_layer_1() {
-    let mut network = ANN::new([2, 2, 1]);
-    network.weights_1 = arr2(&[
+    let mut network = ANN::new(&[2, 2, 1]);
+    network.layers[0].weights = arr2(&[
       [0.0, 0.0],
       [0.0, 0.0],
     ]);
-    network.bias_1 = arr1(&[0.0, 0.0]);
-    network.weights_2 = arr2(&[[4.0, -1.0]]);
+    network.layers[0].bias = arr1(&[0.0, 0.0]);
+    network.layers[1].weights = arr2(&[[4.0, -1.0]]);
 
+    // dL/dy1 = W2^T dL/dz2 for dL/dz2 = -1
     let y1: Array1<f32> = arr1(&[0.5, 2.0]);
-    let d2 = 1.0;
+    let grad_y1: Array1<f32> = network.layers[1].weights.t().dot(&arr1(&[-1.0]));
     let x: Array1<f32> = arr1(&[1.0, 3.0]);
-    network.back_prop_layer_1(y1.view(), d2, x.view());
+    let (gradients, _) = network.layers[0].backward(x.view(), y1.view(), grad_y1.view());
+    let layer = &mut network.layers[0];
+    layer.weights.scaled_add(-0.1, &gradients.weights);
+    layer.bias.scaled_add(-0.1, &gradients.bias);
 
-    assert!(network.bias_1 == arr1(&[0.1, 0.2]), "bias 1 = {}", network.bias_1);
-    assert!(network.weights_1 == arr2(&[[0.1, 0.3], [0.2, 0.6]]), "weight 1 = {}", network.weights_1);
+    assert!(layer.bias == arr1(&[0.1, 0.2]), "bias 1 = {}", layer.bias);
+    assert!(layer.weights == arr2(&[[0.1, 0.3], [0.2, 0.6]]), "weight 1 = {}", layer.weights);
   }
 
   #[test]
@@ -414,7 +1542,10 @@ mod tests {
 
   #[test]
   fn xor_train() {
-    let mut network = ANN::new([2, 2, 1]);
+    // seed 0 for the weights and the order of the samples, two hidden units get
+    // stuck in a local minimum from some seeds
+    let mut network = ANN::new(&[2, 2, 1]);
+    network.initialize(0);
     let epochs = 3000;
     let inputs: Array2<f32> = arr2(&[
       [0.0, 0.0],
@@ -422,13 +1553,10 @@ mod tests {
       [1.0, 0.0],
       [1.0, 1.0]
     ]);
-    let outputs: Array1<f32> = arr1(&[0.0, 1.0, 1.0, 0.0]);
+    let outputs: Array2<f32> = arr2(&[[0.0], [1.0], [1.0], [0.0]]);
     network.train(epochs, inputs, outputs);
     
-    println!("weights 1: {:?}", network.weights_1);
-    println!("bias 1: {:?}", network.bias_1);
-    println!("weights 2: {:?}", network.weights_2);
-    println!("bias 2: {:?}", network.bias_2);
+    network.print();
 
     let x_1 = arr1(&[0.0, 0.0]);
     let y_1 = take_first(network.forward(x_1.view()));
@@ -450,4 +1578,582 @@ mod tests {
     let y_4_ = 0.0;
     assert!(y_4.round() == y_4_, "(1) y = {}, y_ = {}", y_4, y_4_);
   }
-}
\ No newline at end of file
+
+  #[test]
+  fn deep_network() {
+    // the width of the FrozenLake transition encoding, one output per action
+    let sizes = [55, 32, 16, 8, 4];
+    let activations: Vec<Box<dyn Activation>> = vec![Box::new(Relu), Box::new(Relu), Box::new(Sigmoid), Box::new(Tanh)];
+    let network = ANN::with_activations(&sizes, activations);
+    assert_eq!(network.layers.len(), 4);
+    assert_eq!((network.n_inputs(), network.n_outputs()), (55, 4));
+
+    let x: Array1<f32> = Array1::ones(55);
+    let outputs = network.forward_all(x.view());
+    let widths: Vec<usize> = outputs.iter().map(|y| y.len()).collect();
+    assert_eq!(widths, sizes.to_vec());
+
+    let (_, gradients) = network.gradients(x.view(), Array1::zeros(4).view());
+    for (layer, gradients) in network.layers.iter().zip(gradients.iter()) {
+      assert_eq!(gradients.weights.shape(), layer.weights.shape());
+      assert_eq!(gradients.bias.len(), layer.bias.len());
+    }
+  }
+
+  #[test]
+  fn derivatives_match_finite_differences() {
+    let activations: Vec<Box<dyn Activation>> = vec![
+      Box::new(Sigmoid), Box::new(Tanh), Box::new(Relu), Box::new(LeakyRelu { slope: 0.1 }),
+      Box::new(Elu { alpha: 1.0 }), Box::new(Linear), Box::new(Softmax),
+    ];
+    // away from the kinks at 0
+    let z: Array1<f32> = arr1(&[-1.3, -0.2, 0.4, 1.7]);
+    let grad_y: Array1<f32> = arr1(&[0.5, -1.0, 2.0, 0.3]);
+    let h = 1e-3;
+    for activation in activations.iter() {
+      let y = activation.forward(z.view());
+      let grad_z = activation.backward(y.view(), grad_y.view());
+      for j in 0..z.len() {
+        let mut plus = z.clone();
+        plus[j] += h;
+        let mut minus = z.clone();
+        minus[j] -= h;
+        let numeric = (activation.forward(plus.view()) - activation.forward(minus.view())).dot(&grad_y) / (2.0 * h);
+        assert!((numeric - grad_z[j]).abs() < 1e-2, "{} at {}: {} vs {}", activation.name(), j, numeric, grad_z[j]);
+      }
+    }
+  }
+
+  #[test]
+  fn relu_has_no_slope_below_zero() {
+    let y = Relu.forward(arr1(&[-2.0, 3.0]).view());
+    assert_eq!(Relu.backward(y.view(), arr1(&[1.0, 1.0]).view()), arr1(&[0.0, 1.0]));
+    let y = LeakyRelu { slope: 0.1 }.forward(arr1(&[-2.0, 3.0]).view());
+    assert_eq!(y, arr1(&[-0.2, 3.0]));
+  }
+
+  #[test]
+  fn softmax_is_stable() {
+    let y = Softmax.forward(arr1(&[1000.0, 1000.0, f32::NEG_INFINITY]).view());
+    assert_eq!(y, arr1(&[0.5, 0.5, 0.0]));
+    let mut network = ANN::with_activations(&[3, 4], vec![Box::new(Softmax)]);
+    network.layers[0].bias = arr1(&[0.0, 1.0, 2.0, 3.0]);
+    let p = network.forward(arr1(&[0.3, -0.2, 0.8]).view());
+    assert!((p.sum() - 1.0).abs() < 1e-6, "sum = {}", p.sum());
+  }
+
+  #[test]
+  fn loss_gradients_match_finite_differences() {
+    let losses: Vec<Box<dyn Loss>> = vec![
+      Box::new(MeanSquaredError), Box::new(Huber { delta: 0.5 }), Box::new(BinaryCrossEntropy), Box::new(SoftmaxCrossEntropy),
+    ];
+    let y: Array1<f32> = arr1(&[0.2, 0.7, 0.45, 0.9]);
+    let target: Array1<f32> = arr1(&[0.0, 1.0, 0.0, 0.0]);
+    let h = 1e-3;
+    for loss in losses.iter() {
+      let grad = loss.gradient(y.view(), target.view());
+      for j in 0..y.len() {
+        let mut plus = y.clone();
+        plus[j] += h;
+        let mut minus = y.clone();
+        minus[j] -= h;
+        let numeric = (loss.loss(plus.view(), target.view()) - loss.loss(minus.view(), target.view())) / (2.0 * h);
+        assert!((numeric - grad[j]).abs() < 1e-2, "{} at {}: {} vs {}", loss.name(), j, numeric, grad[j]);
+      }
+    }
+    // no infinities for saturated probabilities
+    let certain = BinaryCrossEntropy.loss(arr1(&[0.0, 1.0]).view(), arr1(&[1.0, 0.0]).view());
+    assert!(certain.is_finite(), "loss = {}", certain);
+  }
+
+  #[test]
+  fn one_value_per_action() {
+    // a Q-table for 4 one-hot states and the 4 actions of FrozenLake
+    let inputs: Array2<f32> = Array2::eye(4);
+    let targets: Array2<f32> = arr2(&[
+      [0.1, 0.5, 0.2, 0.0],
+      [0.0, 0.3, 0.9, 0.1],
+      [0.4, 0.0, 0.0, 0.6],
+      [1.0, 0.2, 0.3, 0.7],
+    ]);
+    let mut network = ANN::with_activations(&[4, 8, 4], vec![Box::new(Tanh), Box::new(Linear)]);
+    network.loss = Box::new(Huber { delta: 1.0 });
+    let losses = network.train(2000, inputs.clone(), targets.clone());
+    assert_eq!(losses.len(), 2000);
+    assert!(losses[1999] < losses[0] / 10.0, "loss {} -> {}", losses[0], losses[1999]);
+    let error = network.evaluate(inputs.view(), targets.view());
+    assert!(error < 1e-3, "loss = {}", error);
+  }
+
+  #[test]
+  fn softmax_classifier() {
+    let inputs: Array2<f32> = arr2(&[[1.0, 0.0], [0.0, 1.0], [1.0, 1.0], [0.0, 0.0]]);
+    let targets: Array2<f32> = arr2(&[[1.0, 0.0, 0.0], [0.0, 1.0, 0.0], [0.0, 0.0, 1.0], [0.0, 0.0, 1.0]]);
+    let mut network = ANN::with_activations(&[2, 6, 3], vec![Box::new(Tanh), Box::new(Linear)]);
+    network.loss = Box::new(SoftmaxCrossEntropy);
+    let losses = network.train(1000, inputs.clone(), targets.clone());
+    assert!(losses[999] < 0.1, "loss = {}", losses[999]);
+    for (x, target) in inputs.rows().into_iter().zip(targets.rows()) {
+      let p = Softmax.forward(network.forward(x).view());
+      assert!(p.dot(&target) > 0.8, "x = {}, p = {}", x, p);
+    }
+  }
+
+  #[test]
+  fn batch_gradients_are_the_mean() {
+    let mut network = ANN::with_activations(&[3, 5, 4], vec![Box::new(Tanh), Box::new(Softmax)]);
+    network.loss = Box::new(BinaryCrossEntropy);
+    let inputs: Array2<f32> = arr2(&[[0.5, -1.0, 2.0], [1.0, 0.0, 0.3], [-0.7, 0.2, 0.1]]);
+    let targets: Array2<f32> = arr2(&[[1.0, 0.0, 0.0, 0.0], [0.0, 0.0, 1.0, 0.0], [0.0, 1.0, 0.0, 0.0]]);
+
+    let outputs = network.forward_batch(inputs.view());
+    for (x, y) in inputs.rows().into_iter().zip(outputs.rows()) {
+      let single = network.forward(x);
+      assert!((&single - &y).mapv(f32::abs).sum() < 1e-6, "{} vs {}", single, y);
+    }
+
+    let (loss, gradients) = network.gradients_batch(inputs.view(), targets.view());
+    let mut mean_loss = 0.0;
+    let mut mean: Vec<Array2<f32>> = network.layers.iter().map(|l| Array2::zeros(l.weights.raw_dim())).collect();
+    for (x, target) in inputs.rows().into_iter().zip(targets.rows()) {
+      let (loss, single) = network.gradients(x, target);
+      mean_loss += loss / 3.0;
+      for (m, g) in mean.iter_mut().zip(single.iter()) {
+        m.scaled_add(1.0 / 3.0, &g.weights);
+      }
+    }
+    assert!((loss - mean_loss).abs() < 1e-5, "{} vs {}", loss, mean_loss);
+    for (m, g) in mean.iter().zip(gradients.iter()) {
+      assert!((m - &g.weights).mapv(f32::abs).sum() < 1e-5, "{} vs {}", m, g.weights);
+    }
+  }
+
+  #[test]
+  fn shuffling_is_seeded() {
+    let inputs: Array2<f32> = Array2::from_shape_fn((10, 2), |(i, j)| (i * 2 + j) as f32 / 20.0);
+    let targets: Array2<f32> = inputs.map_axis(Axis(1), |x| x.sum()).insert_axis(Axis(1));
+    let mut network = ANN::with_activations(&[2, 3, 1], vec![Box::new(Tanh), Box::new(Linear)]);
+    network.batch_size = 3;
+    let mut same = network.clone();
+    let mut other = network.clone();
+    network.seed(7);
+    same.seed(7);
+    other.seed(8);
+    let losses = network.train(5, inputs.clone(), targets.clone());
+    assert_eq!(same.train(5, inputs.clone(), targets.clone()), losses);
+    assert_ne!(other.train(5, inputs, targets), losses);
+    assert_eq!(network.layers[0].weights, same.layers[0].weights);
+  }
+
+  #[test]
+  fn mini_batches_of_a_large_buffer() {
+    let mut rng = StdRng::seed_from_u64(3);
+    let n = 4096;
+    let inputs: Array2<f32> = Array2::from_shape_fn((n, 8), |_| rng.gen_range(-1.0..1.0));
+    let map: Array2<f32> = Array2::from_shape_fn((8, 4), |_| rng.gen_range(-0.5..0.5));
+    let targets = inputs.dot(&map);
+    let mut network = ANN::with_activations(&[8, 16, 4], vec![Box::new(Tanh), Box::new(Linear)]);
+    network.batch_size = 64;
+    let losses = network.train(20, inputs.clone(), targets.clone());
+    assert!(losses[19] < losses[0] / 5.0, "loss {} -> {}", losses[0], losses[19]);
+    assert!(network.evaluate(inputs.view(), targets.view()) < 0.01);
+  }
+
+  // one weight of 1 with a gradient of 0.5 for two steps
+  fn two_steps(optimizer: &mut dyn Optimizer) -> f32 {
+    let mut layers = vec![Layer::new(1, 1, Box::new(Linear), &mut StdRng::seed_from_u64(0))];
+    layers[0].weights = arr2(&[[1.0]]);
+    let gradients = [Gradients { weights: arr2(&[[0.5]]), bias: arr1(&[0.0]) }];
+    optimizer.step(&mut layers, &gradients, 0.1);
+    optimizer.step(&mut layers, &gradients, 0.1);
+    layers[0].weights[[0, 0]]
+  }
+
+  #[test]
+  fn optimizer_steps() {
+    let close = |a: f32, b: f32| (a - b).abs() < 1e-5;
+    // 1 - 0.1 * 0.5 - 0.1 * 0.5
+    assert!(close(two_steps(&mut Sgd::new(0.0)), 0.9));
+    // velocity 0.5, then 0.45 + 0.5
+    assert!(close(two_steps(&mut Sgd::new(0.9)), 1.0 - 0.05 - 0.095));
+    // steps of 0.5 + 0.9 * 0.5 and 0.5 + 0.9 * 0.95
+    assert!(close(two_steps(&mut Sgd::nesterov(0.9)), 1.0 - 0.095 - 0.1355));
+    // the first steps of RMSProp are g / sqrt(0.1 g^2) and g / sqrt(0.19 g^2)
+    let expected = 1.0 - 0.1 / 0.1f32.sqrt() - 0.1 / 0.19f32.sqrt();
+    assert!(close(two_steps(&mut RmsProp::default()), expected));
+    // Adam corrects its averages to g and g^2, steps are the learning rate
+    assert!(close(two_steps(&mut Adam::default()), 0.8));
+  }
+
+  #[test]
+  fn momentum_converges_faster() {
+    // fit y = 2 a - b from zero weights with full batches, no randomness involved
+    let inputs: Array2<f32> = arr2(&[[1.0, 0.0], [0.0, 1.0], [1.0, 1.0], [0.5, -1.0]]);
+    let targets: Array2<f32> = inputs.map_axis(Axis(1), |x| 2.0 * x[0] - x[1]).insert_axis(Axis(1));
+    let fit = |optimizer: Box<dyn Optimizer>| {
+      let mut network = ANN::with_activations(&[2, 1], vec![Box::new(Linear)]);
+      network.layers[0].weights.fill(0.0);
+      network.batch_size = 4;
+      network.optimizer = optimizer;
+      network.train(60, inputs.clone(), targets.clone())[59]
+    };
+    let sgd = fit(Box::new(Sgd::new(0.0)));
+    let momentum = fit(Box::new(Sgd::new(0.9)));
+    let nesterov = fit(Box::new(Sgd::nesterov(0.9)));
+    assert!(momentum < sgd / 10.0, "sgd {}, momentum {}", sgd, momentum);
+    assert!(nesterov < sgd / 10.0, "sgd {}, nesterov {}", sgd, nesterov);
+  }
+
+  #[test]
+  fn learning_rate_schedules() {
+    let close = |a: f32, b: f32| (a - b).abs() < 1e-6;
+    let step = StepDecay { every: 10, gamma: 0.5 };
+    assert!(close(step.rate(0.1, 9), 0.1));
+    assert!(close(step.rate(0.1, 25), 0.025));
+    assert!(close(ExponentialDecay { gamma: 0.9 }.rate(1.0, 2), 0.81));
+    let cosine = CosineAnnealing { epochs: 100, min_rate: 0.01 };
+    assert!(close(cosine.rate(0.1, 0), 0.1));
+    assert!(close(cosine.rate(0.1, 50), 0.055));
+    assert!(close(cosine.rate(0.1, 100), 0.01));
+    assert!(close(cosine.rate(0.1, 150), 0.01));
+
+    // a rate of 0 from the second epoch on freezes the network
+    let mut network = ANN::with_activations(&[2, 1], vec![Box::new(Linear)]);
+    network.schedule = Some(Box::new(StepDecay { every: 1, gamma: 0.0 }));
+    let inputs: Array2<f32> = arr2(&[[1.0, 0.0], [0.0, 1.0]]);
+    let targets: Array2<f32> = arr2(&[[1.0], [-1.0]]);
+    let initial = network.layers[0].weights.clone();
+    network.train_epoch(inputs.view(), targets.view(), 0);
+    let weights = network.layers[0].weights.clone();
+    assert_ne!(weights, initial);
+    for run_epoch in 1..5 {
+      network.train_epoch(inputs.view(), targets.view(), run_epoch);
+      assert_eq!(network.layers[0].weights, weights, "epoch {}", run_epoch);
+    }
+  }
+
+  #[test]
+  fn every_train_call_restarts_the_schedule() {
+    let mut network = ANN::with_activations(&[2, 1], vec![Box::new(Linear)]);
+    network.schedule = Some(Box::new(StepDecay { every: 1, gamma: 0.0 }));
+    let inputs: Array2<f32> = arr2(&[[1.0, 0.0], [0.0, 1.0]]);
+    let targets: Array2<f32> = arr2(&[[1.0], [-1.0]]);
+    network.train(3, inputs.clone(), targets.clone());
+    let weights = network.layers[0].weights.clone();
+    // the first epoch of the next run has the base rate again
+    network.train(1, inputs, targets);
+    assert_ne!(network.layers[0].weights, weights);
+  }
+
+  fn temp_path(name: &str) -> String {
+    let path = std::env::temp_dir().join(format!("qu_ann_{}_{}", std::process::id(), name));
+    path.to_str().unwrap().to_string()
+  }
+
+  #[test]
+  fn saves_and_loads_json() {
+    let path = temp_path("xor.json");
+    let network = pretrained_xor();
+    network.save(&path).unwrap();
+    let loaded = ANN::load(&path).unwrap();
+    std::fs::remove_file(&path).unwrap();
+    for x in [[0.0, 0.0], [0.0, 1.0], [1.0, 0.0], [1.0, 1.0]] {
+      assert_eq!(loaded.forward(arr1(&x).view()), network.forward(arr1(&x).view()));
+    }
+    assert_eq!(loaded.layers[1].activation.name(), "leaky_relu");
+    assert_eq!(loaded.layers[1].activation.parameter(), Some(0.1));
+
+    let json = ANN::with_activations(&[3, 4, 2], vec![Box::new(Elu { alpha: 0.5 }), Box::new(Softmax)]).to_json().unwrap();
+    let loaded = ANN::from_json(&json).unwrap();
+    assert_eq!(loaded.to_json().unwrap(), json);
+    assert!(json.contains(&format!("\"version\": {}", FORMAT_VERSION)), "{}", json);
+  }
+
+  #[test]
+  fn rejects_broken_json() {
+    let json = pretrained_xor().to_json().unwrap();
+    let error = |json: String| ANN::from_json(&json).unwrap_err().to_string();
+    assert!(error(json.replace("\"version\": 1", "\"version\": 9")).contains("version 9"));
+    assert!(error(json.replace("\"leaky_relu\"", "\"swish\"")).contains("unknown activation swish"));
+    assert!(error(json.replacen("\"inputs\": 2", "\"inputs\": 3", 1)).contains("layer 1 has 4 weights, 2 x 3 needs 6"));
+    // layer 2 no longer fits behind layer 1
+    let last = json.rfind("\"inputs\": 2").unwrap();
+    let mismatched = format!("{}\"inputs\": 3{}", &json[..last], &json[last + "\"inputs\": 2".len()..]);
+    assert_eq!(error(mismatched), "layer 2 takes 3 inputs but gets 2");
+  }
+
+  #[test]
+  fn saves_and_loads_npy() {
+    let dir = temp_path("npy");
+    let network = pretrained_xor();
+    network.save_npy(&dir).unwrap();
+    assert_eq!(npy::read(&format!("{}/weights_1.npy", dir)).unwrap().shape(), &[2, 2]);
+
+    let mut loaded = ANN::new(&[2, 2, 1]);
+    loaded.load_npy(&dir).unwrap();
+    assert_eq!(loaded.layers[0].weights, network.layers[0].weights);
+    assert_eq!(loaded.layers[1].bias, network.layers[1].bias);
+
+    let mut wider = ANN::new(&[2, 3, 1]);
+    let error = wider.load_npy(&dir).unwrap_err().to_string();
+    assert_eq!(error, "weights_1.npy has shape [2, 2], layer 1 needs [3, 2]");
+    std::fs::remove_dir_all(&dir).unwrap();
+  }
+
+  fn every_activation() -> Vec<Box<dyn Activation>> {
+    vec![
+      Box::new(Sigmoid), Box::new(Tanh), Box::new(Relu), Box::new(LeakyRelu { slope: 0.1 }),
+      Box::new(Elu { alpha: 1.0 }), Box::new(Linear), Box::new(Softmax),
+    ]
+  }
+
+  fn near_kink(network: &ANN, inputs: ArrayView2<f32>, targets: ArrayView2<f32>, margin: f32) -> bool {
+    let outputs = network.forward_all_batch(inputs);
+    let kinked = network.layers.iter().zip(outputs.iter())
+      .filter(|(layer, _)| ["relu", "leaky_relu", "elu"].contains(&layer.activation.name()))
+      .any(|(layer, x)| (x.dot(&layer.weights.t()) + &layer.bias).iter().any(|z| z.abs() < margin));
+    let residuals = &outputs[outputs.len() - 1] - &targets;
+    kinked || (network.loss.name() == "huber" && residuals.iter().any(|d| (d.abs() - 0.3).abs() < margin))
+  }
+
+  #[test]
+  fn gradient_check_of_every_activation_and_loss() {
+    let mut rng = StdRng::seed_from_u64(5);
+    let inputs: Array2<f32> = Array2::from_shape_fn((4, 3), |_| rng.gen_range(-1.0..1.0));
+    // rows of probabilities, they work as targets of every loss
+    let targets: Array2<f32> = arr2(&[[0.7, 0.2, 0.1], [0.0, 1.0, 0.0], [0.3, 0.3, 0.4], [0.0, 0.0, 1.0]]);
+    let losses: Vec<Box<dyn Loss>> = vec![
+      Box::new(MeanSquaredError), Box::new(Huber { delta: 0.3 }), Box::new(BinaryCrossEntropy), Box::new(SoftmaxCrossEntropy),
+    ];
+    for hidden in every_activation() {
+      for output in every_activation() {
+        for loss in losses.iter() {
+          // cross-entropy of probabilities needs outputs in (0, 1)
+          if loss.name() == "binary_cross_entropy" && !["sigmoid", "softmax"].contains(&output.name()) {
+            continue;
+          }
+          let name = format!("{} -> {} with {}", hidden.name(), output.name(), loss.name());
+          let mut network = ANN::with_activations(&[3, 5, 3], vec![hidden.clone(), output.clone()]);
+          network.loss = loss.clone();
+          // central differences are off where they straddle a kink, so draw again
+          // until the pre-activations and the Huber residuals stay clear of theirs
+          let clear = (0..1000).any(|_| {
+            for layer in network.layers.iter_mut() {
+              layer.weights.mapv_inplace(|_| rng.gen_range(-1.0..1.0));
+              layer.bias.mapv_inplace(|_| rng.gen_range(-0.5..0.5));
+            }
+            !near_kink(&network, inputs.view(), targets.view(), 0.05)
+          });
+          assert!(clear, "{}: 1000 draws all landed near a kink", name);
+          let errors = network.gradient_check(inputs.view(), targets.view(), 1e-2);
+          assert_eq!(errors.len(), 2);
+          for error in errors {
+            assert!(error < 2e-2, "{}: relative error {}", name, error);
+          }
+        }
+      }
+    }
+  }
+
+  // the derivative is off by a factor of 2
+  #[derive(Clone)]
+  struct BrokenTanh;
+
+  impl Elementwise for BrokenTanh {
+    fn name(&self) -> &'static str {
+      "broken_tanh"
+    }
+
+    fn apply(&self, z: f32) -> f32 {
+      z.tanh()
+    }
+
+    fn derivative(&self, y: f32) -> f32 {
+      2.0 * (1.0 - y * y)
+    }
+  }
+
+  #[test]
+  fn gradient_check_finds_a_wrong_derivative() {
+    let inputs: Array2<f32> = arr2(&[[0.5, -0.3], [0.1, 0.9]]);
+    let targets: Array2<f32> = arr2(&[[1.0], [0.0]]);
+    let mut network = ANN::with_activations(&[2, 3, 1], vec![Box::new(BrokenTanh), Box::new(Linear)]);
+    network.layers[0].weights = arr2(&[[0.4, -0.6], [0.8, 0.2], [-0.5, 0.7]]);
+    network.layers[1].weights = arr2(&[[1.0, -1.0, 0.5]]);
+    let errors = network.gradient_check(inputs.view(), targets.view(), 1e-2);
+    // the output layer is right, the hidden layer gets twice its gradient
+    assert!(errors[1] < 1e-2, "errors {:?}", errors);
+    assert!(errors[0] > 0.3, "errors {:?}", errors);
+  }
+
+  #[test]
+  fn initializer_statistics() {
+    let mut rng = StdRng::seed_from_u64(9);
+    let (n_in, n_out) = (300, 200);
+    let std = |w: &Array2<f32>| w.std(0.0);
+
+    let w = Initializer::XavierUniform.weights(n_in, n_out, &mut rng);
+    assert_eq!(w.shape(), &[200, 300]);
+    let limit = (6.0f32 / 500.0).sqrt();
+    assert!(w.iter().all(|x| x.abs() <= limit));
+    assert!((std(&w) - (2.0f32 / 500.0).sqrt()).abs() < 2e-3, "std {}", std(&w));
+    let w = Initializer::XavierNormal.weights(n_in, n_out, &mut rng);
+    assert!((std(&w) - (2.0f32 / 500.0).sqrt()).abs() < 2e-3, "std {}", std(&w));
+    let w = Initializer::He.weights(n_in, n_out, &mut rng);
+    assert!((std(&w) - (2.0f32 / 300.0).sqrt()).abs() < 2e-3, "std {}", std(&w));
+
+    for (n_in, n_out) in [(3, 5), (5, 3), (4, 4)] {
+      let w = Initializer::Orthogonal { gain: 2.0 }.weights(n_in, n_out, &mut rng);
+      // W W^T or W^T W, whichever is the smaller, is 4 I
+      let product = if n_out <= n_in { w.dot(&w.t()) } else { w.t().dot(&w) };
+      let error = (&product - &(Array2::<f32>::eye(product.nrows()) * 4.0)).mapv(f32::abs).sum();
+      assert!(error < 1e-4, "{} x {}: {}", n_out, n_in, product);
+    }
+
+    assert_eq!(Initializer::Zeros.weights(2, 3, &mut rng), Array2::<f32>::zeros((3, 2)));
+    assert_eq!(Initializer::Constant(0.5).weights(2, 1, &mut rng), arr2(&[[0.5, 0.5]]));
+    assert_eq!(Initializer::for_activation(&Relu), Initializer::He);
+    assert_eq!(Initializer::for_activation(&Tanh), Initializer::XavierUniform);
+  }
+
+  #[test]
+  fn seeded_initialization() {
+    let mut a = ANN::with_activations(&[3, 4, 2], vec![Box::new(Relu), Box::new(Sigmoid)]);
+    a.layers[1].initializer = Initializer::Orthogonal { gain: 1.0 };
+    let mut b = a.clone();
+    a.initialize(3);
+    b.layers[0].bias.fill(1.0);
+    b.initialize(3);
+    assert_eq!(a.layers[0].weights, b.layers[0].weights);
+    assert_eq!(a.layers[1].weights, b.layers[1].weights);
+    assert_eq!(b.layers[0].bias, Array1::<f32>::zeros(4));
+    b.initialize(4);
+    assert_ne!(a.layers[0].weights, b.layers[0].weights);
+  }
+
+  #[test]
+  fn construction_is_seeded() {
+    let a = ANN::new(&[3, 4, 2]);
+    let mut b = ANN::new(&[3, 4, 2]);
+    assert_eq!(a.layers[0].weights, b.layers[0].weights);
+    b.initialize(0);
+    assert_eq!(a.layers[0].weights, b.layers[0].weights);
+    assert_eq!(a.layers[1].weights, b.layers[1].weights);
+  }
+
+  #[test]
+  fn deep_sigmoid_stack_keeps_its_signal() {
+    let mut rng = StdRng::seed_from_u64(1);
+    let inputs: Array2<f32> = Array2::from_shape_fn((64, 4), |_| rng.gen_range(-1.0..1.0));
+    let targets: Array2<f32> = inputs.map_axis(Axis(1), |x| if x[0] * x[1] > 0.0 { 1.0 } else { 0.0 }).insert_axis(Axis(1));
+    let sizes = [4, 32, 32, 32, 32, 32, 1];
+    let activations: Vec<Box<dyn Activation>> = (0..6).map(|_| Box::new(Sigmoid) as Box<dyn Activation>).collect();
+    let mut xavier = ANN::with_activations(&sizes, activations);
+    xavier.initialize(0);
+    // the old fixed scheme, N(0, 0.1^2)
+    let mut small = xavier.clone();
+    for layer in small.layers.iter_mut() {
+      layer.weights = Array2::random_using(layer.weights.raw_dim(), StandardNormal, &mut rng) * 0.1;
+    }
+
+    // how much the last hidden layer still varies with the input
+    let spread = |network: &ANN| network.forward_all_batch(inputs.view())[5].std_axis(Axis(0), 0.0).mean().unwrap();
+    assert!(spread(&xavier) > 10.0 * spread(&small), "xavier {}, small {}", spread(&xavier), spread(&small));
+
+    // and how much of the gradient makes it back to the first layer
+    let reach = |network: &ANN| {
+      let (_, gradients) = network.gradients_batch(inputs.view(), targets.view());
+      let norm = |g: &Array2<f32>| g.mapv(|x| x * x).sum().sqrt();
+      norm(&gradients[0].weights) / norm(&gradients[5].weights)
+    };
+    assert!(reach(&xavier) > 3.0 * reach(&small), "xavier {}, small {}", reach(&xavier), reach(&small));
+  }
+
+  #[test]
+  fn dropout_only_in_training() {
+    let mut network = ANN::with_activations(&[4, 1000], vec![Box::new(Linear)]);
+    network.layers[0].initializer = Initializer::Constant(0.25);
+    network.layers[0].set_dropout(0.5).unwrap();
+    network.initialize(0);
+    let inputs = Array2::<f32>::ones((2, 4));
+
+    let y = network.forward_in(inputs.view(), Mode::Training);
+    assert!(y.iter().all(|&x| x == 0.0 || x == 2.0));
+    let dropped = y.iter().filter(|&&x| x == 0.0).count() as f32 / y.len() as f32;
+    assert!((dropped - 0.5).abs() < 0.05, "dropped {}", dropped);
+    assert!((y.mean().unwrap() - 1.0).abs() < 0.1);
+    assert_ne!(y, network.forward_in(inputs.view(), Mode::Training));
+
+    let y = network.forward_in(inputs.view(), Mode::Inference);
+    assert!(y.iter().all(|&x| x == 1.0));
+    assert_eq!(y, network.forward_batch(inputs.view()));
+
+    let json = network.to_json().unwrap();
+    assert_eq!(ANN::from_json(&json).unwrap().layers[0].dropout(), 0.5);
+    assert!(ANN::from_json(&json.replace("\"dropout\": 0.5", "\"dropout\": 1.0")).is_err());
+    // files written before dropout existed
+    assert_eq!(ANN::from_json(&json.replace(",\n      \"dropout\": 0.5", "")).unwrap().layers[0].dropout(), 0.0);
+
+    let layer = &mut network.layers[0];
+    assert!(layer.set_dropout(1.0).is_err());
+    assert!(layer.set_dropout(-0.1).is_err());
+    assert!(layer.set_dropout(f32::NAN).is_err());
+    assert_eq!(layer.dropout(), 0.5);
+  }
+
+  #[test]
+  fn dropped_units_get_no_gradient() {
+    let mut network = ANN::with_activations(&[2, 64, 1], vec![Box::new(Tanh), Box::new(Linear)]);
+    network.initialize(0);
+    network.layers[0].set_dropout(0.5).unwrap();
+    let before = network.clone();
+    network.train_epoch(arr2(&[[0.5, -1.0]]).view(), arr2(&[[1.0]]).view(), 0);
+    // a dropped hidden unit changes neither its own weights nor the ones reading it
+    let unchanged: Vec<usize> = (0..64)
+      .filter(|&j| network.layers[1].weights[[0, j]] == before.layers[1].weights[[0, j]])
+      .collect();
+    assert!(unchanged.len() > 16 && unchanged.len() < 48, "{} unchanged", unchanged.len());
+    for &j in unchanged.iter() {
+      assert_eq!(network.layers[0].weights.row(j), before.layers[0].weights.row(j));
+      assert_eq!(network.layers[0].bias[j], before.layers[0].bias[j]);
+    }
+  }
+
+  #[test]
+  fn weight_decay_shrinks_weights() {
+    let mut network = ANN::with_activations(&[2, 2], vec![Box::new(Linear)]);
+    network.initialize(0);
+    network.layers[0].weights.fill(1.0);
+    network.learning_rate = 0.1;
+    network.weight_decay = 0.5;
+    // zero inputs and targets leave only the penalty
+    let zeros = Array2::<f32>::zeros((1, 2));
+    assert_eq!(network.train_epoch(zeros.view(), zeros.view(), 0), 0.0);
+    assert!(network.layers[0].weights.iter().all(|w| (w - 0.95).abs() < 1e-6));
+    assert_eq!(network.layers[0].bias, Array1::<f32>::zeros(2));
+  }
+
+  #[test]
+  fn gradient_clipping() {
+    let mut gradients = vec![
+      Gradients { weights: arr2(&[[3.0, 0.0]]), bias: arr1(&[0.0]) },
+      Gradients { weights: arr2(&[[0.0]]), bias: arr1(&[4.0]) },
+    ];
+    assert_eq!(clip_gradients(&mut gradients, 10.0), 5.0);
+    assert_eq!(gradients[0].weights, arr2(&[[3.0, 0.0]]));
+    assert_eq!(clip_gradients(&mut gradients, 1.0), 5.0);
+    assert!((gradients[0].weights[[0, 0]] - 0.6).abs() < 1e-6);
+    assert!((gradients[1].bias[0] - 0.8).abs() < 1e-6);
+
+    // a clipped SGD step is learning_rate * clip_norm long
+    let mut network = ANN::with_activations(&[2, 1], vec![Box::new(Linear)]);
+    network.initialize(0);
+    network.learning_rate = 1.0;
+    network.clip_norm = Some(0.1);
+    let before = network.clone();
+    network.train_epoch(arr2(&[[10.0, -10.0]]).view(), arr2(&[[100.0]]).view(), 0);
+    let step = (&network.layers[0].weights - &before.layers[0].weights).mapv(|x| x * x).sum()
+      + (&network.layers[0].bias - &before.layers[0].bias).mapv(|x| x * x).sum();
+    assert!((step.sqrt() - 0.1).abs() < 1e-5, "step {}", step.sqrt());
+  }
+}
@@ -0,0 +1,263 @@
+use std::collections::HashMap;
+use std::io::Result;
+
+use crate::scheduler::Scheduler;
+
+// Anything the network can be plugged into: a lake, a grid, a robot
+pub trait Environment {
+  // One value per sensor, in the order the sensors are given to `CoSimulation`
+  fn observe(&self) -> Vec<f64>;
+  // Applies the action, returns the reward and whether the episode is over
+  fn act(&mut self, action: usize) -> (f64, bool);
+  // Starts a new episode, fails if the environment can't place the agent
+  fn reset(&mut self) -> Result<()>;
+  fn n_actions(&self) -> usize;
+}
+
+// Gets the reward after every environment step together with the spikes
+// of every tick the network ran during that step
+pub trait LearningRule {
+  fn learn(&mut self, scheduler: &mut Scheduler, fired: &[Vec<String>], reward: f64);
+}
+
+// Reward-modulated Hebbian rule: a connection is strengthened (or weakened for
+// negative reward) every time its pre-synaptic neuron fired one tick before the post-synaptic one
+pub struct RewardHebbian {
+  pub learning_rate: f64,
+  // weights are kept in [-max_weight, max_weight]
+  pub max_weight: f64,
+}
+
+impl RewardHebbian {
+  pub fn new(learning_rate: f64, max_weight: f64) -> Self {
+    RewardHebbian {
+      learning_rate,
+      max_weight,
+    }
+  }
+}
+
+impl LearningRule for RewardHebbian {
+  fn learn(&mut self, scheduler: &mut Scheduler, fired: &[Vec<String>], reward: f64) {
+    if reward == 0.0 {
+      return;
+    }
+    // how often every (pre, post) pair fired one tick apart
+    let mut pairs: HashMap<(&String, &String), u32> = HashMap::new();
+    for step in fired.windows(2) {
+      for pre_id in step[0].iter() {
+        for post_id in step[1].iter() {
+          *pairs.entry((pre_id, post_id)).or_default() += 1;
+        }
+      }
+    }
+    let mut pre_ids: Vec<String> = pairs.keys().map(|(pre_id, _)| (*pre_id).clone()).collect();
+    pre_ids.sort();
+    pre_ids.dedup();
+    scheduler.update_connection_strengths(&pre_ids, |pre_id, post_id, strength| {
+      pairs.get(&(pre_id, post_id)).map(|&count| {
+        (strength + self.learning_rate * reward * count as f64).clamp(-self.max_weight, self.max_weight)
+      })
+    });
+  }
+}
+
+pub struct StepResult {
+  pub action: usize,
+  pub reward: f64,
+  pub done: bool,
+}
+
+// Runs a spiking network and an environment in lock step:
+// observation -> sensors -> `ticks_per_step` ticks -> actuators -> action -> reward
+pub struct CoSimulation<E: Environment> {
+  pub scheduler: Scheduler,
+  pub env: E,
+  pub ticks_per_step: u64,
+  // sensor names, one per observation value
+  pub sensors: Vec<String>,
+  // actuator names, one per action
+  pub actuators: Vec<String>,
+  pub learning_rule: Option<Box<dyn LearningRule>>,
+}
+
+impl<E: Environment> CoSimulation<E> {
+  pub fn new(scheduler: Scheduler, env: E, ticks_per_step: u64, sensors: Vec<String>, actuators: Vec<String>) -> Self {
+    CoSimulation {
+      scheduler,
+      env,
+      ticks_per_step,
+      sensors,
+      actuators,
+      learning_rule: None,
+    }
+  }
+
+  pub fn with_learning_rule(mut self, rule: Box<dyn LearningRule>) -> Self {
+    self.learning_rule = Some(rule);
+    self
+  }
+
+  // Action with the strongest actuator command, the first one wins a tie
+  pub fn decode_action(&self) -> usize {
+    let mut action = 0;
+    let mut best = f64::NEG_INFINITY;
+    for (i, name) in self.actuators.iter().enumerate().take(self.env.n_actions()) {
+      let command = self.scheduler.read_actuator(name).unwrap_or(0.0);
+      if command > best {
+        best = command;
+        action = i;
+      }
+    }
+    action
+  }
+
+  pub fn step(&mut self) -> StepResult {
+    let observation = self.env.observe();
+    for (name, value) in self.sensors.iter().zip(observation.iter()) {
+      self.scheduler.set_sensor(name, *value);
+    }
+    // the command reflects only what the network did during this step
+    for name in self.actuators.iter() {
+      if let Some(actuator) = self.scheduler.actuators.get_mut(name) {
+        actuator.set_command(0.0);
+      }
+    }
+
+    let fired = self.scheduler.run(self.ticks_per_step);
+    let action = self.decode_action();
+    let (reward, done) = self.env.act(action);
+
+    if let Some(rule) = self.learning_rule.as_mut() {
+      rule.learn(&mut self.scheduler, &fired, reward);
+    }
+
+    StepResult {
+      action,
+      reward,
+      done,
+    }
+  }
+
+  // Returns the total reward and the number of steps taken
+  pub fn run_episode(&mut self, max_steps: usize) -> Result<(f64, usize)> {
+    self.env.reset()?;
+    let mut total_reward = 0.0;
+    for step in 0..max_steps {
+      let result = self.step();
+      total_reward += result.reward;
+      if result.done {
+        return Ok((total_reward, step + 1));
+      }
+    }
+    Ok((total_reward, max_steps))
+  }
+}
+
+#[cfg(test)]
+mod tests {
+  use crate::frozen_lake::FrozenLake;
+  use crate::grid_world::GridWorld;
+  use crate::scheduler::Scheduler;
+  use crate::sensor::Encoding;
+
+  use super::{CoSimulation, Environment, RewardHebbian};
+
+  const ACTION_NAMES: [&str; 4] = ["north", "south", "east", "west"];
+
+  // one input neuron per cell of the 4x4 lake, one output neuron per action,
+  // every cell is wired to the action given by `policy`
+  fn build_controller(policy: &[(usize, usize)]) -> Scheduler {
+    let mut scheduler = Scheduler::new();
+    let cells: Vec<String> = (0..16)
+      .map(|i| scheduler.add_neuron(1, Some(format!("cell{}", i))))
+      .collect();
+    for name in ACTION_NAMES {
+      scheduler.add_neuron(1, Some(name.to_string()));
+      scheduler.add_actuator(name, vec![(name.to_string(), 1.0)], 1.0);
+    }
+    for (cell, action) in policy {
+      scheduler.connect_neurons(&cells[*cell], &ACTION_NAMES[*action].to_string(), Some(1.0));
+    }
+    scheduler.add_sensor("position", cells, Encoding::OneHot { amplitude: 1.0 });
+    scheduler
+  }
+
+  fn actuators() -> Vec<String> {
+    ACTION_NAMES.iter().map(|name| name.to_string()).collect()
+  }
+
+  #[test]
+  fn frozen_lake_hand_wired_policy() {
+    // east, east, south, south, south, east
+    let policy = [(0, 2), (1, 2), (2, 1), (6, 1), (10, 1), (14, 2)];
+    let mut cosim = CoSimulation::new(
+      build_controller(&policy), FrozenLake::new(), 3, vec!["position".to_string()], actuators());
+
+    let (reward, steps) = cosim.run_episode(20).unwrap();
+    assert_eq!(steps, 6);
+    assert_eq!(reward, 1.0);
+    assert_eq!(cosim.env.observe(), vec![15.0]);
+  }
+
+  #[test]
+  fn grid_world_environment() {
+    let layout = "M M M M\nM S G M\nM H F M\nM M M M";
+    let mut grid = GridWorld::<char>::from_str(layout);
+    grid.reset().unwrap();
+    assert_eq!(grid.agent(), [1, 1]);
+    assert_eq!(grid.observe(), vec![5.0]);
+    // west into a wall and nowhere, south into the hole
+    assert_eq!(grid.act(3), (-1.0, false));
+    assert_eq!(grid.agent(), [1, 1]);
+    assert_eq!(grid.act(1), (-1.0, true));
+    assert_eq!(grid.agent(), [1, 2]);
+    assert_eq!(grid.observe(), vec![9.0]);
+    grid.reset().unwrap();
+
+    // "east" from the start cell 5 reaches the goal
+    let mut cosim = CoSimulation::new(
+      build_controller(&[(5, 2)]), grid, 3, vec!["position".to_string()], actuators());
+    assert_eq!(cosim.run_episode(5).unwrap(), (1.0, 1));
+    assert_eq!(cosim.env.observe(), vec![6.0]);
+
+    let mut no_start = GridWorld::<char>::from_str("M M M\nM F M\nM M M");
+    assert!(no_start.reset().is_err());
+  }
+
+  #[test]
+  fn reward_strengthens_used_connection() {
+    let policy = [(14, 2)];
+    let mut cosim = CoSimulation::new(
+      build_controller(&policy), FrozenLake::new(), 3, vec!["position".to_string()], actuators())
+      .with_learning_rule(Box::new(RewardHebbian::new(0.25, 5.0)));
+    cosim.env.reset().unwrap();
+    *cosim.env.loc_mut() = [3, 4];
+
+    let result = cosim.step();
+    assert_eq!(result.action, 2);
+    assert_eq!(result.reward, 1.0);
+    assert!(result.done);
+    // the sensor keeps the cell firing every tick, so the pair (cell14, east) happened twice
+    let cell = cosim.scheduler.find_neuron_by_id(&"cell14".to_string());
+    assert_eq!(cell.connections()[0].get_strength(), 1.5);
+  }
+
+  #[test]
+  fn learned_weights_survive_a_rewind() {
+    let mut cosim = CoSimulation::new(
+      build_controller(&[(14, 2)]), FrozenLake::new(), 3, vec!["position".to_string()], actuators())
+      .with_learning_rule(Box::new(RewardHebbian::new(0.25, 5.0)));
+    cosim.scheduler.enable_history(10);
+    cosim.scheduler.enable_dense_mode();
+    cosim.env.reset().unwrap();
+    *cosim.env.loc_mut() = [3, 4];
+
+    cosim.step();
+    cosim.scheduler.run(2);
+    assert!(cosim.scheduler.step_back());
+    let cell = cosim.scheduler.find_neuron_by_id(&"cell14".to_string());
+    assert_eq!(cell.connections()[0].get_strength(), 1.5);
+    assert!(cosim.scheduler.is_dense());
+  }
+}
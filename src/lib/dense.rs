@@ -0,0 +1,168 @@
+use std::collections::{BTreeMap, HashMap, VecDeque};
+
+use ndarray::prelude::*;
+use ndarray::Array2;
+
+use crate::scheduler::Scheduler;
+
+// Clock-driven copy of a `Scheduler` network: every tick is a matrix-vector product
+// of the weights with the spikes of the previous ticks, followed by elementwise
+// threshold and reset. Neurons are indexed in the order of `Scheduler::pool`.
+// Weights are copied when the network is built, changes to the neurons after that
+// are not seen until it is built again.
+pub struct DenseNetwork {
+  ids: Vec<String>,
+  index: HashMap<String, usize>,
+  potentials: Array1<f64>,
+  thresholds: Array1<f64>,
+  // leak factor per tick, 1 means no leak
+  decay: Array1<f64>,
+  // in ms
+  refractory: Array1<f64>,
+  last_spike: Vec<Option<u64>>,
+  // (delay in ticks, weights, number of connections), matrices are post x pre
+  synapses: Vec<(u64, Array2<f64>, Array2<f64>)>,
+  // spike vectors of the previous ticks, the most recent first
+  recent: VecDeque<Array1<f64>>,
+}
+
+impl DenseNetwork {
+  pub fn from_scheduler(scheduler: &Scheduler) -> Self {
+    let n = scheduler.pool.len();
+    let ids: Vec<String> = scheduler.pool.keys().cloned().collect();
+    let index: HashMap<String, usize> = ids.iter()
+      .enumerate()
+      .map(|(i, id)| (id.clone(), i))
+      .collect();
+
+    let mut potentials = Array1::zeros(n);
+    let mut thresholds = Array1::zeros(n);
+    let mut decay = Array1::ones(n);
+    let mut refractory = Array1::zeros(n);
+    let mut last_spike = vec![None; n];
+    let mut synapses: BTreeMap<u64, (Array2<f64>, Array2<f64>)> = BTreeMap::new();
+    for (pre, neuron) in scheduler.pool.values().enumerate() {
+      potentials[pre] = neuron.potential;
+      thresholds[pre] = neuron.threshold as f64;
+      if let Some(tau) = neuron.tau {
+        decay[pre] = (-scheduler.dt / tau).exp();
+      }
+      refractory[pre] = neuron.refractory;
+      last_spike[pre] = neuron.last_spike;
+      for dendrite in neuron.connections().iter() {
+        // signals to unknown neurons are lost on the event-driven path as well
+        if let Some(&post) = index.get(dendrite.get_neuron_id()) {
+          let delay = scheduler.ms_to_ticks(dendrite.get_delay());
+          let (weights, counts) = synapses.entry(delay)
+            .or_insert_with(|| (Array2::zeros((n, n)), Array2::zeros((n, n))));
+          weights[[post, pre]] += dendrite.get_strength();
+          counts[[post, pre]] += 1.0;
+        }
+      }
+    }
+
+    DenseNetwork {
+      ids,
+      index,
+      potentials,
+      thresholds,
+      decay,
+      refractory,
+      last_spike,
+      synapses: synapses.into_iter().map(|(delay, (w, c))| (delay, w, c)).collect(),
+      recent: VecDeque::new(),
+    }
+  }
+
+  pub fn len(&self) -> usize {
+    self.ids.len()
+  }
+
+  pub fn is_empty(&self) -> bool {
+    self.ids.is_empty()
+  }
+
+  pub fn potential(&self, neuron_id: &String) -> Option<f64> {
+    self.index.get(neuron_id).map(|&i| self.potentials[i])
+  }
+
+  pub fn last_spike(&self, neuron_id: &String) -> Option<u64> {
+    self.index.get(neuron_id).and_then(|&i| self.last_spike[i])
+  }
+
+  fn max_delay(&self) -> usize {
+    self.synapses.iter().map(|(delay, _, _)| *delay as usize).max().unwrap_or(0)
+  }
+
+  // Same contract as the event-driven tick: `external` holds the signals
+  // from events, stimulation and sensors for `time`
+  pub fn step(&mut self, time: u64, dt: f64, external: &HashMap<String, Vec<f64>>) -> Vec<String> {
+    let n = self.len();
+    self.potentials *= &self.decay;
+
+    let mut input: Array1<f64> = Array1::zeros(n);
+    let mut received: Array1<f64> = Array1::zeros(n);
+    for (delay, weights, counts) in self.synapses.iter() {
+      if let Some(spikes) = self.recent.get(*delay as usize - 1) {
+        input += &weights.dot(spikes);
+        received += &counts.dot(spikes);
+      }
+    }
+    for (neuron_id, signals) in external.iter() {
+      if let Some(&i) = self.index.get(neuron_id) {
+        input[i] += signals.iter().sum::<f64>();
+        received[i] += signals.len() as f64;
+      }
+    }
+
+    // only neurons that got a signal and are not refractory integrate and can fire
+    let active: Array1<f64> = Array1::from_shape_fn(n, |i| {
+      let refractory = match self.last_spike[i] {
+        Some(spike_time) => (time.saturating_sub(spike_time) as f64) * dt < self.refractory[i],
+        None => false,
+      };
+      if received[i] > 0.0 && !refractory { 1.0 } else { 0.0 }
+    });
+    self.potentials += &(&input * &active);
+    let spikes: Array1<f64> = Array1::from_shape_fn(n, |i| {
+      if active[i] > 0.0 && self.potentials[i] >= self.thresholds[i] { 1.0 } else { 0.0 }
+    });
+    self.potentials *= &spikes.mapv(|s| 1.0 - s);
+
+    let mut fired = Vec::new();
+    for (i, s) in spikes.iter().enumerate() {
+      if *s > 0.0 {
+        self.last_spike[i] = Some(time);
+        fired.push(self.ids[i].clone());
+      }
+    }
+
+    self.recent.push_front(spikes);
+    self.recent.truncate(self.max_delay());
+    fired
+  }
+
+  // Signals already sent but not delivered before `time`, in the form of `Scheduler::events`
+  pub fn pending_events(&self, time: u64) -> BTreeMap<u64, HashMap<String, Vec<f64>>> {
+    let mut events: BTreeMap<u64, HashMap<String, Vec<f64>>> = BTreeMap::new();
+    for (ticks_ago, spikes) in self.recent.iter().enumerate() {
+      let fired_at = time - 1 - ticks_ago as u64;
+      for (delay, weights, counts) in self.synapses.iter() {
+        let arrival = fired_at + delay;
+        if arrival < time {
+          continue;
+        }
+        let strength = weights.dot(spikes);
+        let count = counts.dot(spikes);
+        for i in 0..self.len() {
+          if count[i] > 0.0 {
+            events.entry(arrival).or_default()
+              .entry(self.ids[i].clone()).or_default()
+              .push(strength[i]);
+          }
+        }
+      }
+    }
+    events
+  }
+}
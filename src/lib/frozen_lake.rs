@@ -11,15 +11,9 @@ use rand::Rng;
 // };
 use crate::{
   ann::ANN,
-  grid_world::{GridWorld, Motion},
+  cosim::Environment,
+  grid_world::{GridWorld, ACTIONS},
 };
-
-pub(crate) const ACTIONS: [Motion; 4] = [
-  Motion::North(1),
-  Motion::South(1),
-  Motion::East(1),
-  Motion::West(1)
-];
   
 const STATES: [char; 5] = [
   'S',
@@ -365,9 +359,41 @@ impl FrozenLake {
 //   }
 // }
 
+// Observation is the index of the current cell inside the walls, row by row
+impl Environment for FrozenLake {
+  fn observe(&self) -> Vec<f64> {
+    let side = self.grid_world.height() - 2;
+    let cell = (self.loc[1] - 1) * side + (self.loc[0] - 1);
+    vec![cell as f64]
+  }
+
+  fn act(&mut self, action: usize) -> (f64, bool) {
+    let (state_sym, next_loc) = self.make_action(self.loc(), action);
+    let state_sym = state_sym.to_owned();
+    let reward = self.reward_map.get(&state_sym).unwrap().to_owned();
+    self.loc = next_loc;
+    self.reward += reward;
+    self.step += 1;
+    (reward as f64, state_sym == 'G' || state_sym == 'H')
+  }
+
+  fn reset(&mut self) -> Result<()> {
+    self.loc = [1, 1];
+    self.reward = 0.0;
+    self.step = 0;
+    Ok(())
+  }
+
+  fn n_actions(&self) -> usize {
+    ACTIONS.len()
+  }
+}
+
 #[cfg(test)]
 mod tests {
   use ndarray::{Array1, Array2};
+  use crate::cosim::Environment;
+
   use super::{FrozenLake, MetaState, ACTIONS, STATES};
   
   // meta state -> one hot encoding
@@ -410,6 +436,17 @@ mod tests {
     Array1::from_vec(transition)
   }
 
+  #[test]
+  fn reset_starts_a_new_episode() {
+    let mut env = FrozenLake::new();
+    env.act(2);
+    env.act(1);
+    assert_eq!(env.step, 2);
+    env.reset().unwrap();
+    assert_eq!(env.loc(), &[1, 1]);
+    assert_eq!(env.step, 0);
+  }
+
   #[test]
   fn train_rl() {
     let mut env = FrozenLake::new();
@@ -0,0 +1,252 @@
+use std::collections::{BTreeMap, BTreeSet, HashMap};
+use std::fs::File;
+use std::io::{BufReader, Error, Result};
+
+use serde::{Serialize, Deserialize};
+
+use crate::scheduler::Scheduler;
+
+// Weight of the self connection that keeps a state neuron firing, also its threshold
+const SUSTAIN: i32 = 2;
+
+#[derive(Clone, Debug, PartialEq, Serialize, Deserialize)]
+pub struct Transition {
+  pub from: String,
+  pub input: String,
+  pub to: String,
+}
+
+// Finite state machine with input symbols, e.g. the behavior modes of a robot.
+// States that are only named in transitions don't have to be listed.
+#[derive(Clone, Debug, Default, PartialEq, Serialize, Deserialize)]
+pub struct StateMachine {
+  pub initial: String,
+  #[serde(default)]
+  pub states: Vec<String>,
+  #[serde(default)]
+  pub transitions: Vec<Transition>,
+}
+
+impl StateMachine {
+  pub fn new(initial: &str) -> Self {
+    StateMachine {
+      initial: initial.to_string(),
+      states: vec![initial.to_string()],
+      transitions: Vec::new(),
+    }
+  }
+
+  pub fn from_json(json: &str) -> Result<Self> {
+    Ok(serde_json::from_str(json)?)
+  }
+
+  pub fn from_file(path: &str) -> Result<Self> {
+    let reader = BufReader::new(File::open(path)?);
+    Ok(serde_json::from_reader(reader)?)
+  }
+
+  pub fn add_transition(&mut self, from: &str, input: &str, to: &str) -> &mut Self {
+    for state in [from, to] {
+      if !self.states.iter().any(|s| s == state) {
+        self.states.push(state.to_string());
+      }
+    }
+    self.transitions.push(Transition {
+      from: from.to_string(),
+      input: input.to_string(),
+      to: to.to_string(),
+    });
+    self
+  }
+
+  pub fn all_states(&self) -> BTreeSet<String> {
+    let mut states: BTreeSet<String> = self.states.iter().cloned().collect();
+    states.insert(self.initial.clone());
+    for t in self.transitions.iter() {
+      states.insert(t.from.clone());
+      states.insert(t.to.clone());
+    }
+    states
+  }
+
+  pub fn inputs(&self) -> BTreeSet<String> {
+    self.transitions.iter().map(|t| t.input.clone()).collect()
+  }
+
+  // Reference semantics: the state after `input`, unknown inputs keep the state
+  pub fn next<'a>(&'a self, state: &'a str, input: &str) -> &'a str {
+    self.transitions.iter()
+      .find(|t| t.from == state && t.input == input)
+      .map_or(state, |t| t.to.as_str())
+  }
+
+  fn validate(&self) -> Result<()> {
+    let mut seen: HashMap<(&str, &str), &str> = HashMap::new();
+    for t in self.transitions.iter() {
+      if let Some(to) = seen.insert((t.from.as_str(), t.input.as_str()), t.to.as_str()) {
+        if to != t.to {
+          return Err(Error::other(format!(
+            "input {} in state {} goes to both {} and {}", t.input, t.from, to, t.to)));
+        }
+      }
+    }
+    Ok(())
+  }
+}
+
+// A compiled state machine. Every state is a neuron exciting itself, so once
+// started exactly one state neuron fires every tick. A transition is a gate
+// that fires when its state and its input fired in the same tick, it turns
+// the next state on and the current one off; the switch takes two ticks.
+pub struct StateCircuit {
+  // state name -> neuron id
+  pub states: BTreeMap<String, String>,
+  // input name -> neuron id, anything can drive these with a weight >= 1
+  pub inputs: BTreeMap<String, String>,
+  pub initial: String,
+}
+
+impl StateCircuit {
+  // Turns the initial state on, it fires from the current tick on
+  pub fn start(&self, scheduler: &mut Scheduler) {
+    let id = self.states[&self.initial].clone();
+    scheduler.inject(HashMap::from([(id, vec![SUSTAIN as f64])]));
+  }
+
+  // Fires the input neuron in the current tick, unknown inputs are ignored
+  pub fn trigger(&self, scheduler: &mut Scheduler, input: &str) -> bool {
+    match self.inputs.get(input) {
+      Some(id) => {
+        scheduler.inject(HashMap::from([(id.clone(), vec![1.0])]));
+        true
+      },
+      None => false,
+    }
+  }
+
+  // The state whose neuron is in `fired`, the spikes of one tick
+  pub fn active_state(&self, fired: &[String]) -> Option<String> {
+    self.states.iter()
+      .find(|(_, id)| fired.contains(id))
+      .map(|(name, _)| name.clone())
+  }
+}
+
+pub fn compile_state_machine(scheduler: &mut Scheduler, prefix: &str, machine: &StateMachine) -> Result<StateCircuit> {
+  machine.validate()?;
+  let dt = scheduler.dt;
+  let add_memoryless = |scheduler: &mut Scheduler, name: String, threshold: i32| {
+    let id = scheduler.add_neuron(threshold, Some(name));
+    scheduler.set_neuron_timing(&id, Some(0.0), 0.0);
+    id
+  };
+
+  let mut states = BTreeMap::new();
+  for name in machine.all_states() {
+    let id = add_memoryless(scheduler, format!("{}state/{}", prefix, name), SUSTAIN);
+    scheduler.connect_neurons_delayed(&id, &id, Some(SUSTAIN as f64), dt);
+    states.insert(name, id);
+  }
+  let mut inputs = BTreeMap::new();
+  for name in machine.inputs() {
+    let id = add_memoryless(scheduler, format!("{}input/{}", prefix, name), 1);
+    inputs.insert(name, id);
+  }
+
+  let mut seen = BTreeSet::new();
+  for (i, t) in machine.transitions.iter().enumerate() {
+    // staying in the same state needs no gate
+    if t.from == t.to || !seen.insert((t.from.clone(), t.input.clone())) {
+      continue;
+    }
+    let gate = add_memoryless(scheduler, format!("{}transition{}", prefix, i), 2);
+    scheduler.connect_neurons_delayed(&states[&t.from], &gate, Some(1.0), dt);
+    scheduler.connect_neurons_delayed(&inputs[&t.input], &gate, Some(1.0), dt);
+    // the inhibition arrives together with the last self excitation of `from`
+    scheduler.connect_neurons_delayed(&gate, &states[&t.from], Some(-2.0 * SUSTAIN as f64), dt);
+    scheduler.connect_neurons_delayed(&gate, &states[&t.to], Some(SUSTAIN as f64), dt);
+  }
+
+  Ok(StateCircuit {
+    states,
+    inputs,
+    initial: machine.initial.clone(),
+  })
+}
+
+#[cfg(test)]
+mod tests {
+  use crate::scheduler::Scheduler;
+
+  use super::{compile_state_machine, StateMachine};
+
+  fn robot_modes() -> StateMachine {
+    let mut machine = StateMachine::new("stop");
+    machine
+      .add_transition("stop", "go", "walk")
+      .add_transition("walk", "obstacle", "turn")
+      .add_transition("turn", "clear", "walk")
+      .add_transition("walk", "halt", "stop")
+      .add_transition("turn", "halt", "stop");
+    machine
+  }
+
+  #[test]
+  fn states_sustain_and_switch() {
+    let machine = robot_modes();
+    let mut scheduler = Scheduler::new();
+    let circuit = compile_state_machine(&mut scheduler, "modes/", &machine).unwrap();
+    circuit.start(&mut scheduler);
+
+    let mut expected = machine.initial.clone();
+    for input in ["go", "clear", "obstacle", "halt", "halt", "go", "obstacle", "clear"] {
+      let fired = scheduler.run(20);
+      // exactly one state is active every tick
+      for tick in fired.iter() {
+        let active: Vec<&String> = circuit.states.values().filter(|id| tick.contains(id)).collect();
+        assert_eq!(active.len(), 1);
+      }
+      assert_eq!(circuit.active_state(&fired[19]), Some(expected.clone()));
+
+      assert!(circuit.trigger(&mut scheduler, input));
+      let fired = scheduler.run(3);
+      expected = machine.next(&expected, input).to_string();
+      assert_eq!(circuit.active_state(&fired[2]), Some(expected.clone()), "after {}", input);
+    }
+    assert!(!circuit.trigger(&mut scheduler, "jump"));
+  }
+
+  #[test]
+  fn state_drives_actuator_in_dense_mode() {
+    let machine = StateMachine::from_json(r#"{
+      "initial": "stop",
+      "transitions": [
+        { "from": "stop", "input": "go", "to": "walk" },
+        { "from": "walk", "input": "halt", "to": "stop" }
+      ]
+    }"#).unwrap();
+    let mut scheduler = Scheduler::new();
+    let circuit = compile_state_machine(&mut scheduler, "", &machine).unwrap();
+    scheduler.add_actuator("forward", vec![(circuit.states["walk"].clone(), 1.0)], 1.0);
+    scheduler.enable_dense_mode();
+    circuit.start(&mut scheduler);
+
+    scheduler.run(5);
+    assert_eq!(scheduler.read_actuator("forward"), Some(0.0));
+    circuit.trigger(&mut scheduler, "go");
+    scheduler.run(12);
+    // walking from the third tick after the input on
+    assert_eq!(scheduler.read_actuator("forward"), Some(10.0));
+    circuit.trigger(&mut scheduler, "halt");
+    scheduler.run(10);
+    assert_eq!(scheduler.read_actuator("forward"), Some(12.0));
+  }
+
+  #[test]
+  fn conflicting_transitions() {
+    let mut machine = robot_modes();
+    machine.add_transition("walk", "obstacle", "stop");
+    let mut scheduler = Scheduler::new();
+    assert!(compile_state_machine(&mut scheduler, "", &machine).is_err());
+  }
+}
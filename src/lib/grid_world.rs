@@ -4,10 +4,12 @@ use std::{
     cmp,
     fmt::Debug,
     fs::File,
-    io::{Error as IOError, Read},
+    io::{self, Error as IOError, Read},
     str::FromStr,
 };
 
+use crate::cosim::Environment;
+
 #[derive(Clone, Copy)]
 pub enum Motion {
     North(usize),
@@ -21,6 +23,14 @@ pub enum Motion {
     SouthWest(usize),
 }
 
+// Moves of one cell in the order the environments number their actions
+pub const ACTIONS: [Motion; 4] = [
+    Motion::North(1),
+    Motion::South(1),
+    Motion::East(1),
+    Motion::West(1),
+];
+
 impl Motion {
     pub fn from_usize(i: usize, n: usize) -> Motion {
         match i {
@@ -35,10 +45,12 @@ impl Motion {
 
 pub struct GridWorld<T> {
     layout: Array2<T>,
+    // [x, y] of the agent when the grid is used as an `Environment`
+    agent: [usize; 2],
 }
 
 impl<T> GridWorld<T> {
-    pub fn new(layout: Array2<T>) -> GridWorld<T> { GridWorld { layout } }
+    pub fn new(layout: Array2<T>) -> GridWorld<T> { GridWorld { layout, agent: [0, 0] } }
 
     pub fn from_str(layout: &str) -> GridWorld<T>
     where
@@ -59,6 +71,7 @@ impl<T> GridWorld<T> {
 
         GridWorld {
             layout: Array2::from_shape_vec(shape, mvals).unwrap(),
+            agent: [0, 0],
         }
     }
 
@@ -84,6 +97,10 @@ impl<T> GridWorld<T> {
 
     pub fn get_mut(&mut self, loc: [usize; 2]) -> Option<&mut T> { self.layout.get_mut(loc) }
 
+    pub fn agent(&self) -> [usize; 2] { self.agent }
+
+    pub fn set_agent(&mut self, loc: [usize; 2]) { self.agent = loc; }
+
     pub fn move_north(&self, loc: [usize; 2], n: usize) -> [usize; 2] {
         [
             loc[0],
@@ -181,3 +198,42 @@ impl<T> GridWorld<T> {
         }
     }
 }
+
+// A grid of cells like the FrozenLake map is an environment on its own: the agent
+// starts on 'S', can't enter walls 'M' and the episode ends on the goal 'G' or in
+// a hole 'H'. Positions are [x, y] like in FrozenLake and the `move_*` helpers, a
+// line of the layout is a row y. Actions are `ACTIONS`, the observation is the index
+// of the agent's cell row by row.
+impl Environment for GridWorld<char> {
+    fn observe(&self) -> Vec<f64> {
+        vec![(self.agent[1] * self.width() + self.agent[0]) as f64]
+    }
+
+    fn act(&mut self, action: usize) -> (f64, bool) {
+        let next = self.perform_motion(&self.agent, ACTIONS[action]);
+        match self.get([next[1], next[0]]) {
+            Some('M') | None => (-1.0, false),
+            Some(cell) => {
+                let cell = *cell;
+                self.agent = next;
+                match cell {
+                    'G' => (1.0, true),
+                    'H' => (-1.0, true),
+                    _ => (0.0, false),
+                }
+            },
+        }
+    }
+
+    fn reset(&mut self) -> io::Result<()> {
+        let ((y, x), _) = self.layout.indexed_iter()
+            .find(|(_, cell)| **cell == 'S')
+            .ok_or_else(|| IOError::other("the grid has no start cell 'S'"))?;
+        self.agent = [x, y];
+        Ok(())
+    }
+
+    fn n_actions(&self) -> usize {
+        ACTIONS.len()
+    }
+}
@@ -0,0 +1,392 @@
+use std::collections::HashMap;
+use std::fmt;
+
+use crate::scheduler::Scheduler;
+
+// Test bench for spiking circuits: drives named input neurons with a sequence
+// of vectors (one per tick) and records which named outputs fired every tick.
+// Expected behavior is written as one pattern per output, e.g. `..1...1.`
+pub struct Harness {
+  // (name, neuron id), in the order of the values of an input vector
+  pub inputs: Vec<(String, String)>,
+  // (name, neuron id)
+  pub outputs: Vec<(String, String)>,
+}
+
+impl Harness {
+  pub fn new() -> Self {
+    Harness {
+      inputs: Vec::new(),
+      outputs: Vec::new(),
+    }
+  }
+
+  pub fn input(mut self, name: &str, neuron_id: &str) -> Self {
+    self.inputs.push((name.to_string(), neuron_id.to_string()));
+    self
+  }
+
+  pub fn output(mut self, name: &str, neuron_id: &str) -> Self {
+    self.outputs.push((name.to_string(), neuron_id.to_string()));
+    self
+  }
+
+  // Runs `ticks` ticks, the vector i is injected at the i-th tick,
+  // a value of 0 sends nothing to that input
+  pub fn run(&self, scheduler: &mut Scheduler, vectors: &[Vec<f64>], ticks: u64) -> Recording {
+    let mut spikes = vec![Vec::new(); self.outputs.len()];
+    for t in 0..ticks as usize {
+      if let Some(vector) = vectors.get(t) {
+        let signals: HashMap<String, Vec<f64>> = self.inputs.iter()
+          .zip(vector.iter())
+          .filter(|(_, value)| **value != 0.0)
+          .map(|((_, neuron_id), value)| (neuron_id.clone(), vec![*value]))
+          .collect();
+        scheduler.inject(signals);
+      }
+      let fired = scheduler.tick();
+      for (row, (_, neuron_id)) in spikes.iter_mut().zip(self.outputs.iter()) {
+        row.push(fired.contains(neuron_id));
+      }
+    }
+    Recording {
+      outputs: self.outputs.iter().map(|(name, _)| name.clone()).collect(),
+      spikes,
+    }
+  }
+
+  // Applies every row once and waits `ticks_per_row` ticks, an output is true
+  // for a row if it fired at least once during those ticks
+  pub fn truth_table(&self, scheduler: &mut Scheduler, rows: &[Vec<f64>], ticks_per_row: u64) -> Vec<Vec<bool>> {
+    rows.iter()
+      .map(|row| {
+        let recording = self.run(scheduler, std::slice::from_ref(row), ticks_per_row);
+        recording.spikes.iter().map(|spikes| spikes.contains(&true)).collect()
+      })
+      .collect()
+  }
+}
+
+impl Default for Harness {
+  fn default() -> Self {
+    Self::new()
+  }
+}
+
+// Spikes of every output, one bool per tick
+#[derive(Clone, Debug, PartialEq)]
+pub struct Recording {
+  pub outputs: Vec<String>,
+  pub spikes: Vec<Vec<bool>>,
+}
+
+impl Recording {
+  pub fn get(&self, output: &str) -> Option<&Vec<bool>> {
+    self.outputs.iter().position(|name| name == output).map(|i| &self.spikes[i])
+  }
+
+  pub fn pattern(&self, output: &str) -> Option<String> {
+    self.get(output).map(|spikes| spikes.iter().map(|s| if *s { '1' } else { '.' }).collect())
+  }
+}
+
+impl fmt::Display for Recording {
+  fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+    let width = self.outputs.iter().map(|name| name.len()).max().unwrap_or(0);
+    for name in self.outputs.iter() {
+      writeln!(f, "{:>width$} {}", name, self.pattern(name).unwrap(), width = width)?;
+    }
+    Ok(())
+  }
+}
+
+#[derive(Clone, Copy, Debug, PartialEq)]
+enum Cell {
+  Spike,
+  Silent,
+  Any,
+}
+
+#[derive(Clone, Debug, PartialEq)]
+pub enum Difference {
+  // an expected spike with no recorded spike within the window
+  Missing { output: String, tick: usize },
+  // a recorded spike where silence was expected
+  Unexpected { output: String, tick: usize },
+  UnknownOutput(String),
+}
+
+impl fmt::Display for Difference {
+  fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+    match self {
+      Difference::Missing { output, tick } => write!(f, "{}: missing spike at {}", output, tick),
+      Difference::Unexpected { output, tick } => write!(f, "{}: unexpected spike at {}", output, tick),
+      Difference::UnknownOutput(output) => write!(f, "{}: not recorded", output),
+    }
+  }
+}
+
+// Expected spike table. Patterns have one character per tick:
+// `1` a spike, `.` or `0` silence, `?` anything; spaces are ignored.
+// Ticks past the end of a pattern are not checked.
+#[derive(Clone, Debug, Default)]
+pub struct Expected {
+  rows: Vec<(String, Vec<Cell>)>,
+  // an expected spike matches a recorded one at most `window` ticks away
+  pub window: usize,
+}
+
+impl Expected {
+  pub fn new(window: usize) -> Self {
+    Expected {
+      rows: Vec::new(),
+      window,
+    }
+  }
+
+  pub fn output(mut self, name: &str, pattern: &str) -> Self {
+    let cells = pattern.chars()
+      .filter(|c| !c.is_whitespace())
+      .map(|c| match c {
+        '1' => Cell::Spike,
+        '?' => Cell::Any,
+        _ => Cell::Silent,
+      })
+      .collect();
+    self.rows.push((name.to_string(), cells));
+    self
+  }
+
+  pub fn check(&self, recording: &Recording) -> Vec<Difference> {
+    let mut differences = Vec::new();
+    for (name, cells) in self.rows.iter() {
+      let spikes = match recording.get(name) {
+        Some(spikes) => spikes,
+        None => {
+          differences.push(Difference::UnknownOutput(name.clone()));
+          continue;
+        },
+      };
+      let mut used = vec![false; spikes.len()];
+      for (tick, cell) in cells.iter().enumerate() {
+        if *cell != Cell::Spike {
+          continue;
+        }
+        // the closest unused spike, earlier ones win a tie
+        let from = tick.saturating_sub(self.window);
+        let to = (tick + self.window).min(spikes.len().saturating_sub(1));
+        let matched = (from..=to)
+          .filter(|t| *t < spikes.len() && spikes[*t] && !used[*t])
+          .min_by_key(|t| t.abs_diff(tick));
+        match matched {
+          Some(t) => used[t] = true,
+          None => differences.push(Difference::Missing { output: name.clone(), tick }),
+        }
+      }
+      for (tick, cell) in cells.iter().enumerate() {
+        if *cell == Cell::Silent && tick < spikes.len() && spikes[tick] && !used[tick] {
+          differences.push(Difference::Unexpected { output: name.clone(), tick });
+        }
+      }
+    }
+    differences.sort_by_key(|d| match d {
+      Difference::Missing { tick, .. } | Difference::Unexpected { tick, .. } => *tick,
+      Difference::UnknownOutput(_) => 0,
+    });
+    differences
+  }
+
+  // Expected and recorded patterns of every output with a difference,
+  // with `^` under the ticks that don't match, `None` if everything matches
+  pub fn diff(&self, recording: &Recording) -> Option<String> {
+    let differences = self.check(recording);
+    if differences.is_empty() {
+      return None;
+    }
+    let width = self.rows.iter().map(|(name, _)| name.len()).max().unwrap_or(0).max("expected".len());
+    let mut text = String::new();
+    for (name, cells) in self.rows.iter() {
+      let marks: Vec<usize> = differences.iter()
+        .filter_map(|d| match d {
+          Difference::Missing { output, tick } | Difference::Unexpected { output, tick } if output == name => Some(*tick),
+          _ => None,
+        })
+        .collect();
+      if marks.is_empty() {
+        continue;
+      }
+      let expected: String = cells.iter()
+        .map(|cell| match cell {
+          Cell::Spike => '1',
+          Cell::Silent => '.',
+          Cell::Any => '?',
+        })
+        .collect();
+      let got = recording.pattern(name).unwrap_or_default();
+      let marker: String = (0..expected.len().max(got.len()))
+        .map(|t| if marks.contains(&t) { '^' } else { ' ' })
+        .collect();
+      text += &format!("{}\n", name);
+      text += &format!("  {:>width$} {}\n", "expected", expected, width = width);
+      text += &format!("  {:>width$} {}\n", "got", got, width = width);
+      text += &format!("  {:>width$} {}\n", "", marker.trim_end(), width = width);
+    }
+    for difference in differences.iter() {
+      text += &format!("{}\n", difference);
+    }
+    Some(text)
+  }
+
+  pub fn assert_matches(&self, recording: &Recording) {
+    if let Some(diff) = self.diff(recording) {
+      panic!("spikes differ from the expected table:\n{}", diff);
+    }
+  }
+}
+
+// Two neurons exciting each other after `delay` ms. Once one of them is started
+// they fire in turns forever with a period of twice the delay. There is no
+// inhibition in it, so it is not a half-center oscillator.
+#[cfg(test)]
+pub fn excitatory_ring(scheduler: &mut Scheduler, first: &str, second: &str, delay: f64) -> (String, String) {
+  let first = scheduler.add_neuron(1, Some(first.to_string()));
+  let second = scheduler.add_neuron(1, Some(second.to_string()));
+  scheduler.connect_neurons_delayed(&first, &second, Some(1.0), delay);
+  scheduler.connect_neurons_delayed(&second, &first, Some(1.0), delay);
+  (first, second)
+}
+
+// CPG shared by the tests, the drives d1 and d2 inhibit each other so they take turns
+#[cfg(test)]
+pub fn build_central_pattern_generator(scheduler: &mut Scheduler) -> (String, String, String) {
+  let signal = scheduler.add_neuron(1, Some("signal".to_string()));
+  let feedback1 = scheduler.add_neuron(1, Some("l1".to_string()));
+  let feedback2 = scheduler.add_neuron(1, Some("l2".to_string()));
+  
+  {
+    let drive1 = scheduler.add_neuron(10, Some("d1".to_string()));
+    let drive2 = scheduler.add_neuron(10, Some("d2".to_string()));
+    let a1 = scheduler.add_neuron(2, Some("a1".to_string()));
+    let a2 = scheduler.add_neuron(2, Some("a2".to_string()));
+    let c1 = scheduler.add_neuron(1, Some("c1".to_string()));
+    let c2 = scheduler.add_neuron(1, Some("c2".to_string()));
+    let uv1 = scheduler.add_neuron(1, Some("uv1".to_string()));
+    let uv2 = scheduler.add_neuron(1, Some("uv2".to_string()));
+    
+    scheduler.connect_neurons(&signal, &a1, Some(1.0));
+    scheduler.connect_neurons(&signal, &a2, Some(1.0));
+    
+    scheduler.connect_neurons(&feedback1, &uv1, Some(0.5));
+    scheduler.connect_neurons(&feedback1, &c1, Some(0.5));
+    scheduler.connect_neurons(&feedback2, &uv2, Some(0.5));
+    scheduler.connect_neurons(&feedback2, &c2, Some(0.5));
+    
+    scheduler.connect_neurons(&uv1, &a1, Some(1.0));
+    scheduler.connect_neurons(&uv1, &uv1, Some(-1.0));
+    scheduler.connect_neurons(&uv1, &uv2, Some(-0.25));
+    scheduler.connect_neurons(&uv1, &c1, Some(0.25));
+    scheduler.connect_neurons(&uv1, &c2, Some(-0.25));
+    
+    scheduler.connect_neurons(&uv2, &a2, Some(1.0));
+    scheduler.connect_neurons(&uv2, &uv2, Some(-1.0));
+    scheduler.connect_neurons(&uv2, &uv1, Some(-0.25));
+    scheduler.connect_neurons(&uv2, &c2, Some(0.25));
+    scheduler.connect_neurons(&uv2, &c1, Some(-0.25));
+
+    scheduler.connect_neurons(&a1, &drive1, Some(1.0));
+    scheduler.connect_neurons(&a1, &uv1, Some(1.0));
+    scheduler.connect_neurons(&a1, &uv2, Some(-0.25));
+    scheduler.connect_neurons(&a2, &drive2, Some(1.0));
+    scheduler.connect_neurons(&a2, &uv2, Some(1.0));
+    scheduler.connect_neurons(&a2, &uv1, Some(-0.25));
+
+    scheduler.connect_neurons(&drive1, &drive2, Some(-2.0));
+    scheduler.connect_neurons(&drive2, &drive1, Some(-2.0));
+
+    scheduler.connect_neurons(&c1, &c2, Some(-1.0));
+    scheduler.connect_neurons(&c1, &uv1, Some(1.0)); // modulatory
+    scheduler.connect_neurons(&c1, &a1, Some(1.0)); // modulatory
+    scheduler.connect_neurons(&c2, &c1, Some(-1.0));
+    scheduler.connect_neurons(&c2, &uv2, Some(1.0)); // modulatory
+    scheduler.connect_neurons(&c2, &a2, Some(1.0)); // modulatory
+  }
+
+  (signal, feedback1, feedback2)
+}
+
+#[cfg(test)]
+mod tests {
+  use crate::scheduler::Scheduler;
+
+  use super::{Difference, Expected, Harness};
+
+  // a -> b with a delay of 2 ticks
+  fn delay_line(scheduler: &mut Scheduler) -> Harness {
+    let a = scheduler.add_neuron(1, Some("a".to_string()));
+    let b = scheduler.add_neuron(1, Some("b".to_string()));
+    scheduler.connect_neurons_delayed(&a, &b, Some(1.0), 2.0);
+    Harness::new()
+      .input("a", &a)
+      .output("a", &a)
+      .output("b", &b)
+  }
+
+  #[test]
+  fn records_and_matches() {
+    let mut scheduler = Scheduler::new();
+    let harness = delay_line(&mut scheduler);
+    let recording = harness.run(&mut scheduler, &[vec![1.0], vec![0.0], vec![0.0], vec![1.0]], 8);
+    assert_eq!(recording.pattern("b").unwrap(), "..1..1..");
+
+    Expected::new(0)
+      .output("a", "1..1....")
+      .output("b", "..1..1..")
+      .assert_matches(&recording);
+    // too early by one tick, fine with a window of 1
+    let early = Expected::new(1).output("b", ".1...1 ??");
+    assert!(early.check(&recording).is_empty());
+    assert_eq!(Expected::new(0).output("b", ".1...1").check(&recording), vec![
+      Difference::Missing { output: "b".to_string(), tick: 1 },
+      Difference::Unexpected { output: "b".to_string(), tick: 2 },
+    ]);
+  }
+
+  #[test]
+  fn readable_diff() {
+    let mut scheduler = Scheduler::new();
+    let harness = delay_line(&mut scheduler);
+    let recording = harness.run(&mut scheduler, &[vec![1.0]], 4);
+    let diff = Expected::new(0)
+      .output("a", "1...")
+      .output("b", "...1")
+      .output("c", "1")
+      .diff(&recording)
+      .unwrap();
+    assert_eq!(diff, [
+      "b",
+      "  expected ...1",
+      "       got ..1.",
+      "             ^^",
+      "c: not recorded",
+      "b: unexpected spike at 2",
+      "b: missing spike at 3",
+      "",
+    ].join("\n"));
+  }
+
+  #[test]
+  fn truth_table_of_and_gate() {
+    let mut scheduler = Scheduler::new();
+    let a = scheduler.add_neuron(1, Some("a".to_string()));
+    let b = scheduler.add_neuron(1, Some("b".to_string()));
+    let and = scheduler.add_neuron(2, Some("and".to_string()));
+    scheduler.set_neuron_timing(&and, Some(0.0), 0.0);
+    scheduler.connect_neurons(&a, &and, Some(1.0));
+    scheduler.connect_neurons(&b, &and, Some(1.0));
+    let harness = Harness::new().input("a", &a).input("b", &b).output("and", &and);
+
+    let rows = [vec![0.0, 0.0], vec![0.0, 1.0], vec![1.0, 0.0], vec![1.0, 1.0]];
+    let table = harness.truth_table(&mut scheduler, &rows, 3);
+    assert_eq!(table, vec![vec![false], vec![false], vec![false], vec![true]]);
+  }
+}
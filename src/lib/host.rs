@@ -0,0 +1,265 @@
+use std::collections::HashMap;
+use std::sync::{Arc, RwLock};
+use std::sync::mpsc::{self, Receiver, Sender, TryRecvError};
+use std::thread::{self, JoinHandle};
+use std::time::Duration;
+
+use crate::scheduler::Scheduler;
+use crate::snapshot::Snapshot;
+
+pub enum Command {
+  // run this many ticks and stop
+  Step(u64),
+  // keep ticking until `Pause`
+  Run,
+  Pause,
+  Inject(HashMap<String, Vec<f64>>),
+  SetWeight { pre: String, post: String, strength: f64 },
+  SetSensor { name: String, value: f64 },
+  // replies with the state once all previous commands are done
+  Sync(Sender<Arc<HostState>>),
+  Shutdown,
+}
+
+// What readers see, replaced as a whole after every command or tick
+#[derive(Debug, Default)]
+pub struct HostState {
+  pub snapshot: Snapshot,
+  // neurons fired on the last tick
+  pub fired: Vec<String>,
+  pub running: bool,
+  // why the last command failed, cleared by the next one
+  pub error: Option<String>,
+}
+
+// Owns a `Scheduler` on its own thread, driven by commands over a channel
+pub struct SimulationHost {
+  sender: Sender<Command>,
+  state: Arc<RwLock<Arc<HostState>>>,
+  handle: Option<JoinHandle<Scheduler>>,
+}
+
+impl SimulationHost {
+  // `tick_interval` paces free running, zero runs as fast as possible
+  pub fn spawn(scheduler: Scheduler, tick_interval: Duration) -> Self {
+    let (sender, receiver) = mpsc::channel();
+    let state = Arc::new(RwLock::new(Arc::new(HostState {
+      snapshot: scheduler.snapshot(),
+      fired: Vec::new(),
+      running: false,
+      error: None,
+    })));
+    let published = state.clone();
+    let handle = thread::spawn(move || {
+      simulation_loop(scheduler, receiver, published, tick_interval)
+    });
+    SimulationHost {
+      sender,
+      state,
+      handle: Some(handle),
+    }
+  }
+
+  pub fn send(&self, command: Command) {
+    // the thread is only gone after shutdown
+    let _ = self.sender.send(command);
+  }
+
+  pub fn step(&self, ticks: u64) {
+    self.send(Command::Step(ticks));
+  }
+
+  pub fn run(&self) {
+    self.send(Command::Run);
+  }
+
+  pub fn pause(&self) {
+    self.send(Command::Pause);
+  }
+
+  pub fn inject(&self, signals: HashMap<String, Vec<f64>>) {
+    self.send(Command::Inject(signals));
+  }
+
+  pub fn set_weight(&self, pre: &str, post: &str, strength: f64) {
+    self.send(Command::SetWeight { pre: pre.to_string(), post: post.to_string(), strength });
+  }
+
+  pub fn set_sensor(&self, name: &str, value: f64) {
+    self.send(Command::SetSensor { name: name.to_string(), value });
+  }
+
+  // Latest published state, never waits for the simulation
+  pub fn state(&self) -> Arc<HostState> {
+    self.state.read().unwrap().clone()
+  }
+
+  // Waits until every command sent before is processed
+  pub fn sync(&self) -> Arc<HostState> {
+    let (reply, response) = mpsc::channel();
+    self.send(Command::Sync(reply));
+    response.recv().unwrap_or_else(|_| self.state())
+  }
+
+  // Stops the thread and gives the scheduler back
+  pub fn shutdown(mut self) -> Scheduler {
+    self.send(Command::Shutdown);
+    self.handle.take().unwrap().join().expect("simulation thread panicked")
+  }
+}
+
+fn publish(
+  scheduler: &Scheduler,
+  fired: Vec<String>,
+  running: bool,
+  error: Option<String>,
+  state: &RwLock<Arc<HostState>>,
+) -> Arc<HostState> {
+  let new_state = Arc::new(HostState {
+    snapshot: scheduler.snapshot(),
+    fired,
+    running,
+    error,
+  });
+  *state.write().unwrap() = new_state.clone();
+  new_state
+}
+
+fn simulation_loop(
+  mut scheduler: Scheduler,
+  receiver: Receiver<Command>,
+  state: Arc<RwLock<Arc<HostState>>>,
+  tick_interval: Duration,
+) -> Scheduler {
+  let mut running = false;
+  let mut fired = Vec::new();
+  let mut error = None;
+  loop {
+    let command = if running {
+      match receiver.try_recv() {
+        Ok(command) => Some(command),
+        Err(TryRecvError::Empty) => None,
+        Err(TryRecvError::Disconnected) => break,
+      }
+    } else {
+      match receiver.recv() {
+        Ok(command) => Some(command),
+        Err(_) => break,
+      }
+    };
+
+    // a sync only reports, everything else starts with a clean slate
+    if command.as_ref().is_some_and(|command| !matches!(command, Command::Sync(_))) {
+      error = None;
+    }
+    match command {
+      Some(Command::Step(ticks)) => {
+        for _ in 0..ticks {
+          fired = scheduler.tick();
+        }
+      },
+      Some(Command::Run) => running = true,
+      Some(Command::Pause) => running = false,
+      Some(Command::Inject(signals)) => scheduler.inject(signals),
+      Some(Command::SetWeight { pre, post, strength }) => {
+        if !scheduler.set_connection_strength(&pre, &post, strength) {
+          error = Some(format!("no connection {} -> {}", pre, post));
+        }
+      },
+      Some(Command::SetSensor { name, value }) => {
+        if scheduler.sensors.contains_key(&name) {
+          scheduler.set_sensor(&name, value);
+        } else {
+          error = Some(format!("no sensor '{}'", name));
+        }
+      },
+      Some(Command::Sync(reply)) => {
+        let _ = reply.send(publish(&scheduler, fired.clone(), running, error.clone(), &state));
+        continue;
+      },
+      Some(Command::Shutdown) => break,
+      None => {
+        fired = scheduler.tick();
+        if !tick_interval.is_zero() {
+          thread::sleep(tick_interval);
+        }
+      },
+    }
+    publish(&scheduler, fired.clone(), running, error.clone(), &state);
+  }
+  scheduler
+}
+
+#[cfg(test)]
+mod tests {
+  use std::collections::HashMap;
+  use std::time::Duration;
+
+  use crate::scheduler::Scheduler;
+
+  use super::SimulationHost;
+
+  fn chain() -> Scheduler {
+    let mut scheduler = Scheduler::new();
+    let a = scheduler.add_neuron(1, Some("a".to_string()));
+    let b = scheduler.add_neuron(1, Some("b".to_string()));
+    scheduler.connect_neurons(&a, &b, Some(1.0));
+    scheduler.connect_neurons(&b, &a, Some(1.0));
+    scheduler
+  }
+
+  #[test]
+  fn step_and_inject() {
+    let host = SimulationHost::spawn(chain(), Duration::ZERO);
+    host.inject(HashMap::from([("a".to_string(), vec![1.0])]));
+    host.step(1);
+    let state = host.sync();
+    assert_eq!(state.snapshot.time, 1);
+    assert_eq!(state.fired, vec!["a".to_string()]);
+
+    host.step(2);
+    host.set_weight("a", "b", 0.5);
+    host.step(4);
+    let state = host.sync();
+    assert_eq!(state.snapshot.time, 7);
+    assert_eq!(state.snapshot.weights["a"], vec![0.5]);
+    assert_eq!(host.state().snapshot.time, 7);
+
+    let scheduler = host.shutdown();
+    assert_eq!(scheduler.time, 7);
+  }
+
+  #[test]
+  fn failed_edits_are_reported() {
+    let host = SimulationHost::spawn(chain(), Duration::ZERO);
+    host.set_weight("b", "c", 0.5);
+    let state = host.sync();
+    assert_eq!(state.error.as_deref(), Some("no connection b -> c"));
+    assert_eq!(state.snapshot.weights["b"], vec![1.0]);
+    // still there for a reader that comes later
+    assert_eq!(host.sync().error.as_deref(), Some("no connection b -> c"));
+
+    host.set_sensor("eye", 1.0);
+    assert_eq!(host.sync().error.as_deref(), Some("no sensor 'eye'"));
+
+    host.set_weight("b", "a", 0.5);
+    let state = host.sync();
+    assert_eq!(state.error, None);
+    assert_eq!(state.snapshot.weights["b"], vec![0.5]);
+    host.shutdown();
+  }
+
+  #[test]
+  fn run_and_pause() {
+    let host = SimulationHost::spawn(chain(), Duration::from_micros(100));
+    host.run();
+    std::thread::sleep(Duration::from_millis(20));
+    host.pause();
+    let paused = host.sync();
+    assert!(!paused.running);
+    assert!(paused.snapshot.time > 0);
+    std::thread::sleep(Duration::from_millis(5));
+    assert_eq!(host.state().snapshot.time, paused.snapshot.time);
+    host.shutdown();
+  }
+}
@@ -1,2 +1,21 @@
+pub mod analysis;
+pub mod ann;
+pub mod cosim;
+pub mod dense;
+pub mod frozen_lake;
+pub mod fsm;
+pub mod grid_world;
+pub mod harness;
+pub mod host;
+pub mod lint;
+pub mod logic;
+pub mod module;
+pub mod neat;
 pub mod neuron;
-pub mod scheduler;
\ No newline at end of file
+pub mod npy;
+pub mod reservoir;
+pub mod scheduler;
+pub mod sensor;
+pub mod snapshot;
+pub mod stimulation;
+pub mod trainer;
\ No newline at end of file
@@ -0,0 +1,313 @@
+use std::collections::{BTreeMap, BTreeSet, HashMap, VecDeque};
+use std::fmt;
+use std::io::Result;
+
+use serde::{Serialize, Deserialize};
+
+use crate::scheduler::Scheduler;
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq, PartialOrd, Ord, Hash, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum Severity {
+  Info,
+  Warning,
+  Error,
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Hash, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum Check {
+  // a connection to a neuron id that is not in the pool, its signals are lost
+  UnknownTarget,
+  // the same pre, post and delay more than once
+  DuplicateSynapse,
+  SelfLoop,
+  // no path from any input (a neuron without incoming connections, a sensor or a stimulus target)
+  Unreachable,
+  // the incoming weights can never add up to the threshold
+  UnreachableThreshold,
+  WeightOutOfBounds,
+}
+
+impl Check {
+  pub fn severity(&self) -> Severity {
+    match self {
+      Check::UnknownTarget => Severity::Error,
+      Check::WeightOutOfBounds => Severity::Error,
+      Check::DuplicateSynapse => Severity::Warning,
+      Check::Unreachable => Severity::Warning,
+      Check::UnreachableThreshold => Severity::Warning,
+      // recurrent self excitation is a legitimate way to hold a state
+      Check::SelfLoop => Severity::Info,
+    }
+  }
+}
+
+#[derive(Clone, Debug, PartialEq, Serialize, Deserialize)]
+pub struct Finding {
+  pub severity: Severity,
+  pub check: Check,
+  pub neuron: String,
+  // post-synaptic neuron for findings about a connection
+  #[serde(default, skip_serializing_if = "Option::is_none")]
+  pub target: Option<String>,
+  pub message: String,
+}
+
+impl fmt::Display for Finding {
+  fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+    let severity = match self.severity {
+      Severity::Info => "info",
+      Severity::Warning => "warning",
+      Severity::Error => "error",
+    };
+    write!(f, "{}: {}", severity, self.message)
+  }
+}
+
+#[derive(Clone, Debug, Default, PartialEq, Serialize, Deserialize)]
+pub struct LintConfig {
+  // allowed range of connection strengths, `None` skips the check
+  #[serde(default)]
+  pub weight_bounds: Option<(f64, f64)>,
+  // checks that are not run
+  #[serde(default)]
+  pub allow: Vec<Check>,
+}
+
+#[derive(Clone, Debug, Default, PartialEq, Serialize, Deserialize)]
+pub struct LintReport {
+  pub findings: Vec<Finding>,
+}
+
+impl LintReport {
+  pub fn is_clean(&self) -> bool {
+    self.findings.is_empty()
+  }
+
+  pub fn has_errors(&self) -> bool {
+    self.findings.iter().any(|f| f.severity == Severity::Error)
+  }
+
+  pub fn of(&self, check: Check) -> Vec<&Finding> {
+    self.findings.iter().filter(|f| f.check == check).collect()
+  }
+
+  pub fn at_least(&self, severity: Severity) -> Vec<&Finding> {
+    self.findings.iter().filter(|f| f.severity >= severity).collect()
+  }
+
+  pub fn to_json(&self) -> Result<String> {
+    Ok(serde_json::to_string_pretty(self)?)
+  }
+
+  pub fn from_json(json: &str) -> Result<Self> {
+    Ok(serde_json::from_str(json)?)
+  }
+}
+
+impl fmt::Display for LintReport {
+  fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+    for finding in self.findings.iter() {
+      writeln!(f, "{}", finding)?;
+    }
+    Ok(())
+  }
+}
+
+struct Linter<'a> {
+  config: &'a LintConfig,
+  findings: Vec<Finding>,
+}
+
+impl<'a> Linter<'a> {
+  fn report(&mut self, check: Check, neuron: &str, target: Option<&String>, message: String) {
+    if self.config.allow.contains(&check) {
+      return;
+    }
+    self.findings.push(Finding {
+      severity: check.severity(),
+      check,
+      neuron: neuron.to_string(),
+      target: target.cloned(),
+      message,
+    });
+  }
+}
+
+pub fn lint(scheduler: &Scheduler, config: &LintConfig) -> LintReport {
+  let mut linter = Linter { config, findings: Vec::new() };
+
+  // incoming connections (pre, strength) of every neuron in the pool
+  let mut incoming: HashMap<&String, Vec<(&String, f64)>> = HashMap::new();
+  for (pre_id, neuron) in scheduler.pool.iter() {
+    let mut seen: BTreeSet<(&String, u64)> = BTreeSet::new();
+    for dendrite in neuron.connections().iter() {
+      let post_id = dendrite.get_neuron_id();
+      let strength = dendrite.get_strength();
+      if !scheduler.pool.contains_key(post_id) {
+        linter.report(Check::UnknownTarget, pre_id, Some(post_id),
+          format!("{} is connected to {}, which does not exist", pre_id, post_id));
+        continue;
+      }
+      incoming.entry(post_id).or_default().push((pre_id, strength));
+      if post_id == pre_id {
+        linter.report(Check::SelfLoop, pre_id, Some(post_id), format!("{} is connected to itself", pre_id));
+      }
+      if !seen.insert((post_id, scheduler.ms_to_ticks(dendrite.get_delay()))) {
+        linter.report(Check::DuplicateSynapse, pre_id, Some(post_id),
+          format!("{} is connected to {} more than once with the same delay", pre_id, post_id));
+      }
+      if let Some((min, max)) = config.weight_bounds {
+        if strength < min || strength > max {
+          linter.report(Check::WeightOutOfBounds, pre_id, Some(post_id),
+            format!("{} -> {} has strength {}, outside of [{}, {}]", pre_id, post_id, strength, min, max));
+        }
+      }
+    }
+  }
+
+  // neurons that get signals from outside the network
+  let mut external: BTreeSet<&String> = BTreeSet::new();
+  for stimulus in scheduler.stimulation.stimuli.iter() {
+    external.extend(stimulus.targets.iter());
+  }
+  for sensor in scheduler.sensors.values() {
+    external.extend(sensor.targets.iter());
+  }
+
+  let inputs: Vec<&String> = scheduler.pool.keys()
+    .filter(|id| external.contains(id) || incoming.get(id).is_none_or(|pres| pres.iter().all(|(pre, _)| pre == id)))
+    .collect();
+  let mut reached: BTreeSet<&String> = inputs.iter().cloned().collect();
+  let mut queue: VecDeque<&String> = inputs.into_iter().collect();
+  let mut outgoing: BTreeMap<&String, Vec<&String>> = BTreeMap::new();
+  for (post_id, pres) in incoming.iter() {
+    for (pre_id, _) in pres.iter() {
+      outgoing.entry(*pre_id).or_default().push(*post_id);
+    }
+  }
+  while let Some(id) = queue.pop_front() {
+    for post_id in outgoing.get(id).into_iter().flatten() {
+      if reached.insert(*post_id) {
+        queue.push_back(*post_id);
+      }
+    }
+  }
+
+  for (id, neuron) in scheduler.pool.iter() {
+    if !reached.contains(id) {
+      linter.report(Check::Unreachable, id, None, format!("{} cannot be reached from any input", id));
+      continue;
+    }
+    // the strength of external input is not known
+    let pres = match incoming.get(id) {
+      Some(pres) if !external.contains(id) => pres,
+      _ => continue,
+    };
+    let per_tick: f64 = pres.iter().map(|(_, strength)| strength.max(0.0)).sum();
+    let decay = neuron.tau.map_or(1.0, |tau| (-scheduler.dt / tau).exp());
+    // highest potential the neuron can integrate to
+    let bound = if per_tick <= 0.0 {
+      neuron.potential.max(0.0)
+    } else if decay < 1.0 {
+      neuron.potential.max(per_tick / (1.0 - decay))
+    } else {
+      f64::INFINITY
+    };
+    if bound < neuron.threshold as f64 {
+      linter.report(Check::UnreachableThreshold, id, None,
+        format!("{} has threshold {} but its input adds up to at most {}", id, neuron.threshold, bound));
+    }
+  }
+
+  linter.findings.sort_by_key(|f| std::cmp::Reverse(f.severity));
+  LintReport { findings: linter.findings }
+}
+
+#[cfg(test)]
+mod tests {
+  use crate::scheduler::Scheduler;
+  use crate::sensor::Encoding;
+  use crate::stimulation::{Stimulus, Waveform};
+
+  use super::{Check, LintConfig, LintReport, Severity};
+
+  #[test]
+  fn clean_network() {
+    let mut scheduler = Scheduler::new();
+    let a = scheduler.add_neuron(1, Some("a".to_string()));
+    let b = scheduler.add_neuron(2, Some("b".to_string()));
+    scheduler.connect_neurons(&a, &b, Some(1.0));
+    let report = scheduler.lint(&LintConfig::default());
+    assert!(report.is_clean(), "{}", report);
+  }
+
+  #[test]
+  fn flags_every_check() {
+    let mut scheduler = Scheduler::new();
+    let input = scheduler.add_neuron(1, Some("input".to_string()));
+    let gate = scheduler.add_neuron(3, Some("gate".to_string()));
+    scheduler.set_neuron_timing(&gate, Some(0.0), 0.0);
+    let looped = scheduler.add_neuron(1, Some("looped".to_string()));
+    let island = scheduler.add_neuron(1, Some("island".to_string()));
+    let fed = scheduler.add_neuron(5, Some("fed".to_string()));
+    scheduler.set_neuron_timing(&fed, Some(0.0), 0.0);
+
+    scheduler.connect_neurons(&input, &gate, Some(1.0));
+    scheduler.connect_neurons(&input, &gate, Some(1.0));
+    scheduler.connect_neurons(&input, &"ghost".to_string(), Some(1.0));
+    scheduler.connect_neurons(&input, &looped, Some(8.0));
+    scheduler.connect_neurons(&looped, &looped, Some(1.0));
+    scheduler.connect_neurons(&island, &island, Some(1.0));
+    scheduler.connect_neurons(&looped, &island, Some(1.0));
+    // no warning for neurons driven by a sensor
+    scheduler.connect_neurons(&input, &fed, Some(1.0));
+    scheduler.add_sensor("touch", vec![fed.clone()], Encoding::Current { gain: 1.0 });
+
+    let config = LintConfig { weight_bounds: Some((-4.0, 4.0)), ..LintConfig::default() };
+    let report = scheduler.lint(&config);
+    assert!(report.has_errors());
+    // errors first
+    assert_eq!(report.findings[0].severity, Severity::Error);
+    assert_eq!(report.of(Check::UnknownTarget)[0].target, Some("ghost".to_string()));
+    assert_eq!(report.of(Check::WeightOutOfBounds)[0].target, Some("looped".to_string()));
+    assert_eq!(report.of(Check::DuplicateSynapse).len(), 1);
+    assert_eq!(report.of(Check::SelfLoop).len(), 2);
+    // island is fed by looped, which is reachable
+    assert!(report.of(Check::Unreachable).is_empty());
+    // two memoryless inputs of 1 can't reach 3
+    let unreachable = report.of(Check::UnreachableThreshold);
+    assert_eq!(unreachable.len(), 1);
+    assert_eq!(unreachable[0].neuron, "gate");
+
+    let json = report.to_json().unwrap();
+    assert!(json.contains("\"check\": \"unknown_target\""), "{}", json);
+    assert_eq!(LintReport::from_json(&json).unwrap(), report);
+
+    let config = LintConfig { allow: vec![Check::SelfLoop, Check::DuplicateSynapse], ..LintConfig::default() };
+    let report = scheduler.lint(&config);
+    assert!(report.of(Check::SelfLoop).is_empty());
+    assert_eq!(report.at_least(Severity::Warning).len(), report.findings.len());
+  }
+
+  #[test]
+  fn closed_loop_without_entry_is_unreachable() {
+    let mut scheduler = Scheduler::new();
+    let a = scheduler.add_neuron(1, Some("a".to_string()));
+    let b = scheduler.add_neuron(1, Some("b".to_string()));
+    scheduler.connect_neurons(&a, &b, Some(1.0));
+    scheduler.connect_neurons(&b, &a, Some(1.0));
+    let report = scheduler.lint(&LintConfig::default());
+    let neurons: Vec<&String> = report.of(Check::Unreachable).iter().map(|f| &f.neuron).collect();
+    assert_eq!(neurons, vec!["a", "b"]);
+
+    scheduler.stimulation.add(Stimulus {
+      targets: vec![a.clone()],
+      start: 0.0,
+      stop: None,
+      waveform: Waveform::Constant { amplitude: 1.0 },
+    });
+    assert!(scheduler.lint(&LintConfig::default()).is_clean());
+  }
+}
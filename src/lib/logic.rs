@@ -0,0 +1,657 @@
+use std::collections::{BTreeMap, BTreeSet, HashMap};
+use std::io::{Error, Result};
+
+use crate::scheduler::Scheduler;
+
+// Boolean logic compiled into threshold neurons.
+//
+// Every gate is a neuron with threshold 1 that forgets its potential between ticks
+// (`tau` = 0). It fires when the weighted sum of its inputs reaches the gate threshold T,
+// the offset comes from a bias neuron that fires on every evaluation together with
+// the inputs: sum(w * x) + (1 - T) >= 1. The bias is also what lets NOT fire.
+// Synaptic delays are chosen so that all outputs fire `latency` ticks after the inputs.
+
+// Rows of a truth table are numbered with one bit per input, implicants are u32 masks
+pub const MAX_INPUTS: usize = 31;
+
+fn check_input_count(n: usize) -> Result<()> {
+  if n > MAX_INPUTS {
+    return Err(Error::other(format!("{} inputs, at most {} are supported", n, MAX_INPUTS)));
+  }
+  Ok(())
+}
+
+#[derive(Clone, Debug, PartialEq, Eq, Hash)]
+pub enum Expr {
+  Const(bool),
+  Input(String),
+  Not(Box<Expr>),
+  And(Vec<Expr>),
+  Or(Vec<Expr>),
+  Xor(Vec<Expr>),
+  Majority(Vec<Expr>),
+  // true when the weighted sum of the inputs is at least the threshold
+  Threshold(Vec<(Expr, i32)>, i32),
+}
+
+impl Expr {
+  pub fn input(name: &str) -> Expr {
+    Expr::Input(name.to_string())
+  }
+
+  // Operators by precedence: `!`, `&`, `^`, `|`. Functions: and, or, xor, not, maj.
+  // Constants: 0, 1.
+  pub fn parse(text: &str) -> Result<Expr> {
+    let mut parser = Parser { chars: text.chars().collect(), pos: 0 };
+    let expr = parser.or()?;
+    parser.skip_spaces();
+    if parser.pos < parser.chars.len() {
+      return Err(parser.error("unexpected character"));
+    }
+    Ok(expr)
+  }
+
+  // `table[i]` is the output for the inputs given by the bits of `i`, the first input
+  // is the most significant bit. Linearly separable tables become a single gate.
+  pub fn from_truth_table(inputs: &[&str], table: &[bool]) -> Result<Expr> {
+    let n = inputs.len();
+    check_input_count(n)?;
+    if table.len() != 1 << n {
+      return Err(Error::other(format!("{} inputs need {} rows, got {}", n, 1 << n, table.len())));
+    }
+    if table.iter().all(|row| !row) {
+      return Ok(Expr::Const(false));
+    }
+    if table.iter().all(|row| *row) {
+      return Ok(Expr::Const(true));
+    }
+    if n <= 4 {
+      if let Some(expr) = single_threshold_gate(inputs, table) {
+        return Ok(expr);
+      }
+    }
+    Ok(sum_of_products(inputs, table))
+  }
+
+  pub fn eval(&self, assignment: &HashMap<String, bool>) -> bool {
+    match self {
+      Expr::Const(value) => *value,
+      Expr::Input(name) => *assignment.get(name).unwrap_or(&false),
+      Expr::Not(e) => !e.eval(assignment),
+      Expr::And(es) => es.iter().all(|e| e.eval(assignment)),
+      Expr::Or(es) => es.iter().any(|e| e.eval(assignment)),
+      Expr::Xor(es) => es.iter().filter(|e| e.eval(assignment)).count() % 2 == 1,
+      Expr::Majority(es) => 2 * es.iter().filter(|e| e.eval(assignment)).count() > es.len(),
+      Expr::Threshold(literals, threshold) => {
+        let sum: i32 = literals.iter()
+          .filter(|(e, _)| e.eval(assignment))
+          .map(|(_, w)| w)
+          .sum();
+        sum >= *threshold
+      },
+    }
+  }
+
+  pub fn inputs(&self) -> BTreeSet<String> {
+    let mut names = BTreeSet::new();
+    self.collect_inputs(&mut names);
+    names
+  }
+
+  fn collect_inputs(&self, names: &mut BTreeSet<String>) {
+    match self {
+      Expr::Const(_) => {},
+      Expr::Input(name) => { names.insert(name.clone()); },
+      Expr::Not(e) => e.collect_inputs(names),
+      Expr::And(es) | Expr::Or(es) | Expr::Xor(es) | Expr::Majority(es) => {
+        for e in es {
+          e.collect_inputs(names);
+        }
+      },
+      Expr::Threshold(literals, _) => {
+        for (e, _) in literals {
+          e.collect_inputs(names);
+        }
+      },
+    }
+  }
+
+  // Gate inputs with their weights and the gate threshold
+  fn as_gate(&self) -> Option<(Vec<(Expr, i32)>, i32)> {
+    let ones = |es: &Vec<Expr>| es.iter().map(|e| (e.clone(), 1)).collect();
+    match self {
+      Expr::Not(e) => Some((vec![(*e.clone(), -1)], 0)),
+      Expr::And(es) => Some((ones(es), es.len() as i32)),
+      Expr::Or(es) => Some((ones(es), 1)),
+      Expr::Majority(es) => Some((ones(es), es.len() as i32 / 2 + 1)),
+      Expr::Threshold(literals, threshold) => Some((literals.clone(), *threshold)),
+      _ => None,
+    }
+  }
+}
+
+// Ticks from the inputs to this expression, must agree with `Compiler::node`
+fn depth(expr: &Expr) -> u64 {
+  match expr {
+    Expr::Input(_) => 0,
+    Expr::Const(_) => 1,
+    Expr::Xor(es) => {
+      match es.len() {
+        0 => 1,
+        1 => depth(&es[0]),
+        _ => es[1..].iter().fold(depth(&es[0]), |d, e| d.max(depth(e)) + 2),
+      }
+    },
+    _ => {
+      let (literals, _) = expr.as_gate().unwrap();
+      literals.iter()
+        .map(|(e, _)| match e {
+          Expr::Not(inner) => depth(inner),
+          _ => depth(e),
+        })
+        .max()
+        .unwrap_or(0) + 1
+    },
+  }
+}
+
+fn single_threshold_gate(inputs: &[&str], table: &[bool]) -> Option<Expr> {
+  let n = inputs.len();
+  let mut best: Option<(Vec<i32>, i32)> = None;
+  let mut weights = vec![-2; n];
+  loop {
+    let sums: Vec<i32> = (0..table.len())
+      .map(|row| (0..n).filter(|i| row >> (n - 1 - i) & 1 == 1).map(|i| weights[i]).sum())
+      .collect();
+    let lowest_true = (0..table.len()).filter(|r| table[*r]).map(|r| sums[r]).min().unwrap();
+    let highest_false = (0..table.len()).filter(|r| !table[*r]).map(|r| sums[r]).max().unwrap();
+    if lowest_true > highest_false {
+      let cost = |w: &Vec<i32>| w.iter().map(|x| x.abs()).sum::<i32>();
+      if best.as_ref().is_none_or(|(b, _)| cost(&weights) < cost(b)) {
+        best = Some((weights.clone(), lowest_true));
+      }
+    }
+    // next combination of weights in [-2, 2]
+    let mut i = 0;
+    while i < n && weights[i] == 2 {
+      weights[i] = -2;
+      i += 1;
+    }
+    if i == n {
+      break;
+    }
+    weights[i] += 1;
+  }
+  best.map(|(weights, threshold)| {
+    let literals = inputs.iter()
+      .zip(weights.iter())
+      .filter(|(_, w)| **w != 0)
+      .map(|(name, w)| (Expr::input(name), *w))
+      .collect();
+    Expr::Threshold(literals, threshold)
+  })
+}
+
+// Quine-McCluskey prime implicants with a greedy cover
+fn sum_of_products(inputs: &[&str], table: &[bool]) -> Expr {
+  let n = inputs.len();
+  let minterms: Vec<u32> = (0..table.len() as u32).filter(|r| table[*r as usize]).collect();
+
+  // implicant = (bits, mask of don't-care bits), masked bits are 0 in `bits`
+  let mut current: BTreeSet<(u32, u32)> = minterms.iter().map(|m| (*m, 0)).collect();
+  let mut primes: BTreeSet<(u32, u32)> = BTreeSet::new();
+  while !current.is_empty() {
+    let mut next = BTreeSet::new();
+    let mut used = BTreeSet::new();
+    for a in current.iter() {
+      for b in current.iter() {
+        let diff = a.0 ^ b.0;
+        if a.1 == b.1 && diff.count_ones() == 1 {
+          next.insert((a.0 & !diff, a.1 | diff));
+          used.insert(*a);
+          used.insert(*b);
+        }
+      }
+    }
+    primes.extend(current.difference(&used).cloned());
+    current = next;
+  }
+
+  let covers = |p: &(u32, u32), m: u32| m & !p.1 == p.0;
+  let mut remaining: BTreeSet<u32> = minterms.into_iter().collect();
+  let mut terms = Vec::new();
+  while !remaining.is_empty() {
+    let best = primes.iter()
+      .max_by_key(|p| (remaining.iter().filter(|m| covers(p, **m)).count(), p.1.count_ones()))
+      .unwrap();
+    remaining.retain(|m| !covers(best, *m));
+    let literals: Vec<Expr> = (0..n)
+      .filter(|i| best.1 >> (n - 1 - i) & 1 == 0)
+      .map(|i| {
+        let input = Expr::input(inputs[i]);
+        if best.0 >> (n - 1 - i) & 1 == 1 { input } else { Expr::Not(Box::new(input)) }
+      })
+      .collect();
+    terms.push(if literals.len() == 1 { literals[0].clone() } else { Expr::And(literals) });
+  }
+  if terms.len() == 1 { terms.pop().unwrap() } else { Expr::Or(terms) }
+}
+
+struct Parser {
+  chars: Vec<char>,
+  pos: usize,
+}
+
+impl Parser {
+  fn error(&self, message: &str) -> Error {
+    Error::other(format!("{} at {}", message, self.pos))
+  }
+
+  fn skip_spaces(&mut self) {
+    while self.pos < self.chars.len() && self.chars[self.pos].is_whitespace() {
+      self.pos += 1;
+    }
+  }
+
+  fn eat(&mut self, c: char) -> bool {
+    self.skip_spaces();
+    if self.pos < self.chars.len() && self.chars[self.pos] == c {
+      self.pos += 1;
+      true
+    } else {
+      false
+    }
+  }
+
+  fn or(&mut self) -> Result<Expr> {
+    let mut terms = vec![self.xor()?];
+    while self.eat('|') {
+      terms.push(self.xor()?);
+    }
+    Ok(if terms.len() == 1 { terms.pop().unwrap() } else { Expr::Or(terms) })
+  }
+
+  fn xor(&mut self) -> Result<Expr> {
+    let mut terms = vec![self.and()?];
+    while self.eat('^') {
+      terms.push(self.and()?);
+    }
+    Ok(if terms.len() == 1 { terms.pop().unwrap() } else { Expr::Xor(terms) })
+  }
+
+  fn and(&mut self) -> Result<Expr> {
+    let mut terms = vec![self.unary()?];
+    while self.eat('&') {
+      terms.push(self.unary()?);
+    }
+    Ok(if terms.len() == 1 { terms.pop().unwrap() } else { Expr::And(terms) })
+  }
+
+  fn unary(&mut self) -> Result<Expr> {
+    if self.eat('!') {
+      return Ok(Expr::Not(Box::new(self.unary()?)));
+    }
+    self.primary()
+  }
+
+  fn primary(&mut self) -> Result<Expr> {
+    if self.eat('(') {
+      let expr = self.or()?;
+      if !self.eat(')') {
+        return Err(self.error("expected ')'"));
+      }
+      return Ok(expr);
+    }
+    if self.eat('0') {
+      return Ok(Expr::Const(false));
+    }
+    if self.eat('1') {
+      return Ok(Expr::Const(true));
+    }
+    let start = self.pos;
+    while self.pos < self.chars.len() && (self.chars[self.pos].is_alphanumeric() || self.chars[self.pos] == '_') {
+      self.pos += 1;
+    }
+    if start == self.pos {
+      return Err(self.error("expected an input"));
+    }
+    let name: String = self.chars[start..self.pos].iter().collect();
+    if !self.eat('(') {
+      return Ok(Expr::Input(name));
+    }
+    let mut args = vec![self.or()?];
+    while self.eat(',') {
+      args.push(self.or()?);
+    }
+    if !self.eat(')') {
+      return Err(self.error("expected ')'"));
+    }
+    match name.as_str() {
+      "and" => Ok(Expr::And(args)),
+      "or" => Ok(Expr::Or(args)),
+      "xor" => Ok(Expr::Xor(args)),
+      "maj" => Ok(Expr::Majority(args)),
+      "not" if args.len() == 1 => Ok(Expr::Not(Box::new(args.pop().unwrap()))),
+      _ => Err(self.error(&format!("unknown function '{}'", name))),
+    }
+  }
+}
+
+#[derive(Clone, Debug, PartialEq)]
+pub struct Mismatch {
+  pub inputs: Vec<bool>,
+  pub output: String,
+  pub expected: bool,
+  pub got: bool,
+}
+
+pub struct Circuit {
+  // (input name, neuron id), `evaluate` and `verify` take the values in this order:
+  // the caller's for truth tables, alphabetical for expressions
+  pub inputs: Vec<(String, String)>,
+  pub bias: String,
+  // (output name, neuron id, expression)
+  pub outputs: Vec<(String, String, Expr)>,
+  // ticks from the inputs to the outputs
+  pub latency: u64,
+}
+
+impl Circuit {
+  // Drives the inputs (in the order of `inputs`) and returns the outputs
+  pub fn evaluate(&self, scheduler: &mut Scheduler, values: &[bool]) -> Vec<bool> {
+    let mut signals = HashMap::from([(self.bias.clone(), vec![1.0])]);
+    for ((_, neuron_id), value) in self.inputs.iter().zip(values.iter()) {
+      if *value {
+        signals.insert(neuron_id.clone(), vec![1.0]);
+      }
+    }
+    scheduler.inject(signals);
+    let fired = scheduler.run(self.latency + 1);
+    self.outputs.iter()
+      .map(|(_, neuron_id, _)| fired[self.latency as usize].contains(neuron_id))
+      .collect()
+  }
+
+  // Runs every input combination, an empty result means the circuit is correct
+  pub fn verify(&self, scheduler: &mut Scheduler) -> Result<Vec<Mismatch>> {
+    let n = self.inputs.len();
+    check_input_count(n)?;
+    let mut mismatches = Vec::new();
+    for row in 0..(1u32 << n) {
+      let values: Vec<bool> = (0..n).map(|i| row >> (n - 1 - i) & 1 == 1).collect();
+      let assignment: HashMap<String, bool> = self.inputs.iter()
+        .map(|(name, _)| name.clone())
+        .zip(values.iter().cloned())
+        .collect();
+      let got = self.evaluate(scheduler, &values);
+      for ((name, _, expr), got) in self.outputs.iter().zip(got) {
+        let expected = expr.eval(&assignment);
+        if expected != got {
+          mismatches.push(Mismatch { inputs: values.clone(), output: name.clone(), expected, got });
+        }
+      }
+    }
+    Ok(mismatches)
+  }
+}
+
+struct Compiler<'a> {
+  scheduler: &'a mut Scheduler,
+  prefix: String,
+  bias: String,
+  inputs: BTreeMap<String, String>,
+  memo: HashMap<Expr, (String, u64)>,
+  gates: usize,
+}
+
+impl<'a> Compiler<'a> {
+  fn add_memoryless(&mut self, name: String) -> String {
+    let id = self.scheduler.add_neuron(1, Some(name));
+    self.scheduler.set_neuron_timing(&id, Some(0.0), 0.0);
+    id
+  }
+
+  fn input(&mut self, name: &String) -> String {
+    if let Some(id) = self.inputs.get(name) {
+      return id.clone();
+    }
+    let id = self.add_memoryless(format!("{}{}", self.prefix, name));
+    self.inputs.insert(name.clone(), id.clone());
+    id
+  }
+
+  // Gate over already compiled sources (neuron id, depth, weight)
+  fn gate_ids(&mut self, sources: Vec<(String, u64, i32)>, threshold: i32, min_depth: u64) -> (String, u64) {
+    let depth = sources.iter().map(|(_, d, _)| d + 1).max().unwrap_or(1).max(min_depth);
+    let id = self.add_memoryless(format!("{}g{}", self.prefix, self.gates));
+    self.gates += 1;
+    let dt = self.scheduler.dt;
+    let bias = self.bias.clone();
+    self.scheduler.connect_neurons_delayed(&bias, &id, Some((1 - threshold) as f64), depth as f64 * dt);
+    for (source, source_depth, weight) in sources {
+      self.scheduler.connect_neurons_delayed(&source, &id, Some(weight as f64), (depth - source_depth) as f64 * dt);
+    }
+    (id, depth)
+  }
+
+  fn gate(&mut self, literals: Vec<(Expr, i32)>, mut threshold: i32, min_depth: u64) -> (String, u64) {
+    let mut sources = Vec::new();
+    for (expr, weight) in literals {
+      // !x with weight w is w - w * x, no need for a separate NOT neuron
+      let (expr, weight) = match expr {
+        Expr::Not(inner) => {
+          threshold -= weight;
+          (*inner, -weight)
+        },
+        expr => (expr, weight),
+      };
+      let (id, depth) = self.node(&expr, 0);
+      sources.push((id, depth, weight));
+    }
+    self.gate_ids(sources, threshold, min_depth)
+  }
+
+  fn node(&mut self, expr: &Expr, min_depth: u64) -> (String, u64) {
+    if min_depth == 0 {
+      if let Some(compiled) = self.memo.get(expr) {
+        return compiled.clone();
+      }
+    }
+    let compiled = match expr {
+      Expr::Const(value) => self.gate(Vec::new(), if *value { 0 } else { 1 }, min_depth),
+      Expr::Input(name) => {
+        let id = self.input(name);
+        if min_depth == 0 {
+          (id, 0)
+        } else {
+          self.gate_ids(vec![(id, 0, 1)], 1, min_depth)
+        }
+      },
+      Expr::Xor(es) if es.is_empty() => self.gate(Vec::new(), 1, min_depth),
+      Expr::Xor(es) if es.len() == 1 => self.node(&es[0], min_depth),
+      Expr::Xor(es) => {
+        // a ^ b = a + b - 2 * (a & b)
+        let (mut id, mut depth) = self.node(&es[0], 0);
+        for (i, e) in es[1..].iter().enumerate() {
+          let (other, other_depth) = self.node(e, 0);
+          let (both, both_depth) = self.gate_ids(vec![(id.clone(), depth, 1), (other.clone(), other_depth, 1)], 2, 0);
+          let last = i == es.len() - 2;
+          (id, depth) = self.gate_ids(
+            vec![(id, depth, 1), (other, other_depth, 1), (both, both_depth, -2)],
+            1,
+            if last { min_depth } else { 0 });
+        }
+        (id, depth)
+      },
+      _ => {
+        let (literals, threshold) = expr.as_gate().unwrap();
+        self.gate(literals, threshold, min_depth)
+      },
+    };
+    if min_depth == 0 {
+      self.memo.insert(expr.clone(), compiled.clone());
+    }
+    compiled
+  }
+}
+
+// Adds the circuit for `outputs` to `scheduler`, all neuron ids start with `prefix`
+pub fn compile(scheduler: &mut Scheduler, prefix: &str, outputs: &[(&str, Expr)]) -> Circuit {
+  let latency = outputs.iter().map(|(_, expr)| depth(expr)).max().unwrap_or(0).max(1);
+  let bias = scheduler.add_neuron(1, Some(format!("{}bias", prefix)));
+  scheduler.set_neuron_timing(&bias, Some(0.0), 0.0);
+  let mut compiler = Compiler {
+    scheduler,
+    prefix: prefix.to_string(),
+    bias: bias.clone(),
+    inputs: BTreeMap::new(),
+    memo: HashMap::new(),
+    gates: 0,
+  };
+  // inputs that no output uses still get a neuron, so `evaluate` takes all of them
+  for (_, expr) in outputs.iter() {
+    for name in expr.inputs() {
+      compiler.input(&name);
+    }
+  }
+  let mut compiled = Vec::new();
+  for (name, expr) in outputs.iter() {
+    let (id, _) = compiler.node(expr, latency);
+    compiled.push((name.to_string(), id, expr.clone()));
+  }
+  Circuit {
+    inputs: compiler.inputs.into_iter().collect(),
+    bias,
+    outputs: compiled,
+    latency,
+  }
+}
+
+pub fn compile_truth_table(scheduler: &mut Scheduler, prefix: &str, inputs: &[&str], table: &[bool]) -> Result<Circuit> {
+  let expr = Expr::from_truth_table(inputs, table)?;
+  let mut circuit = compile(scheduler, prefix, &[("out", expr)]);
+  let mut compiled: BTreeMap<String, String> = circuit.inputs.drain(..).collect();
+  for name in inputs {
+    // constant tables have no inputs in the expression
+    let id = compiled.remove(*name).unwrap_or_else(|| {
+      let id = scheduler.add_neuron(1, Some(format!("{}{}", prefix, name)));
+      scheduler.set_neuron_timing(&id, Some(0.0), 0.0);
+      id
+    });
+    circuit.inputs.push((name.to_string(), id));
+  }
+  Ok(circuit)
+}
+
+#[cfg(test)]
+mod tests {
+  use std::collections::HashMap;
+
+  use crate::scheduler::Scheduler;
+
+  use super::{compile, compile_truth_table, Expr, MAX_INPUTS};
+
+  fn check(text: &str) -> usize {
+    let mut scheduler = Scheduler::new();
+    let circuit = compile(&mut scheduler, "c/", &[("out", Expr::parse(text).unwrap())]);
+    let mismatches = circuit.verify(&mut scheduler).unwrap();
+    assert!(mismatches.is_empty(), "{}: {:?}", text, mismatches);
+    scheduler.pool.len()
+  }
+
+  #[test]
+  fn parse_precedence() {
+    let expr = Expr::parse("a | !b & c ^ d").unwrap();
+    assert_eq!(expr, Expr::Or(vec![
+      Expr::input("a"),
+      Expr::Xor(vec![
+        Expr::And(vec![Expr::Not(Box::new(Expr::input("b"))), Expr::input("c")]),
+        Expr::input("d"),
+      ]),
+    ]));
+    assert_eq!(Expr::parse("maj(a, b, 1)").unwrap(),
+      Expr::Majority(vec![Expr::input("a"), Expr::input("b"), Expr::Const(true)]));
+    assert!(Expr::parse("a & (b").is_err());
+    assert!(Expr::parse("nand(a, b)").is_err());
+    assert!(Expr::parse("a b").is_err());
+  }
+
+  #[test]
+  fn basic_gates() {
+    // inputs + bias + gates
+    assert_eq!(check("a & b"), 4);
+    assert_eq!(check("a | b"), 4);
+    assert_eq!(check("!a"), 3);
+    assert_eq!(check("a & !b"), 4);
+    assert_eq!(check("a ^ b"), 5);
+    assert_eq!(check("maj(a, b, c)"), 5);
+    check("a ^ b ^ c");
+    check("!(a | b) | (c & !maj(a, b, c))");
+    check("a | 0");
+    check("a");
+  }
+
+  #[test]
+  fn outputs_are_aligned() {
+    let mut scheduler = Scheduler::new();
+    let circuit = compile(&mut scheduler, "adder/", &[
+      ("sum", Expr::parse("a ^ b").unwrap()),
+      ("carry", Expr::parse("a & b").unwrap()),
+      ("a", Expr::parse("a").unwrap()),
+    ]);
+    assert_eq!(circuit.latency, 2);
+    assert!(circuit.verify(&mut scheduler).unwrap().is_empty());
+    assert_eq!(circuit.evaluate(&mut scheduler, &[true, true]), vec![false, true, true]);
+    assert_eq!(circuit.evaluate(&mut scheduler, &[true, false]), vec![true, false, true]);
+  }
+
+  #[test]
+  fn truth_tables() {
+    let mut scheduler = Scheduler::new();
+    let majority = [false, false, false, true, false, true, true, true];
+    let circuit = compile_truth_table(&mut scheduler, "maj/", &["a", "b", "c"], &majority).unwrap();
+    assert!(circuit.verify(&mut scheduler).unwrap().is_empty());
+    // a single gate
+    assert_eq!(scheduler.pool.len(), 5);
+
+    let parity = [false, true, true, false, true, false, false, true];
+    let expr = Expr::from_truth_table(&["a", "b", "c"], &parity).unwrap();
+    for (row, expected) in parity.iter().enumerate() {
+      let assignment = HashMap::from([
+        ("a".to_string(), row & 4 != 0),
+        ("b".to_string(), row & 2 != 0),
+        ("c".to_string(), row & 1 != 0),
+      ]);
+      assert_eq!(expr.eval(&assignment), *expected);
+    }
+    let circuit = compile_truth_table(&mut scheduler, "parity/", &["a", "b", "c"], &parity).unwrap();
+    assert!(circuit.verify(&mut scheduler).unwrap().is_empty());
+
+    // the table is in the caller's order of the inputs, b is the high bit: b & !a
+    let circuit = compile_truth_table(&mut scheduler, "ba/", &["b", "a"], &[false, false, true, false]).unwrap();
+    assert_eq!(circuit.inputs[0].0, "b");
+    assert!(circuit.verify(&mut scheduler).unwrap().is_empty());
+    assert_eq!(circuit.evaluate(&mut scheduler, &[true, false]), vec![true]);
+    assert_eq!(circuit.evaluate(&mut scheduler, &[false, true]), vec![false]);
+
+    let never = compile_truth_table(&mut scheduler, "never/", &["a"], &[false, false]).unwrap();
+    assert!(never.verify(&mut scheduler).unwrap().is_empty());
+    assert!(compile_truth_table(&mut scheduler, "bad/", &["a"], &[true]).is_err());
+  }
+
+  #[test]
+  fn too_many_inputs() {
+    let names: Vec<String> = (0..=MAX_INPUTS).map(|i| format!("x{}", i)).collect();
+    let inputs: Vec<&str> = names.iter().map(|name| name.as_str()).collect();
+    // fails on the count, long before a table of that size could be checked
+    let error = Expr::from_truth_table(&inputs, &[true]).unwrap_err();
+    assert_eq!(error.to_string(), "32 inputs, at most 31 are supported");
+    let mut scheduler = Scheduler::new();
+    assert!(compile_truth_table(&mut scheduler, "wide/", &inputs, &[true]).is_err());
+    assert!(scheduler.pool.is_empty());
+
+    let expr = Expr::And(inputs.iter().map(|name| Expr::input(name)).collect());
+    let circuit = compile(&mut scheduler, "wide/", &[("out", expr)]);
+    assert!(circuit.verify(&mut scheduler).is_err());
+  }
+}
@@ -0,0 +1,308 @@
+use std::collections::BTreeMap;
+use std::fs::File;
+use std::io::{BufReader, BufWriter, Error, Result};
+
+use serde::{Serialize, Deserialize};
+
+use crate::scheduler::Scheduler;
+
+#[derive(Clone, Debug, PartialEq, Serialize, Deserialize)]
+pub struct NeuronSpec {
+  pub id: String,
+  pub threshold: i32,
+  #[serde(default)]
+  pub tau: Option<f64>,
+  #[serde(default)]
+  pub refractory: f64,
+}
+
+#[derive(Clone, Debug, PartialEq, Serialize, Deserialize)]
+pub struct SynapseSpec {
+  pub pre: String,
+  pub post: String,
+  pub strength: f64,
+  // ms
+  pub delay: f64,
+}
+
+// Reusable sub-network with named ports. Neuron ids are local to the module,
+// every instance puts its own prefix in front of them.
+// An input port fans out to all its neurons, every neuron of an output port
+// sends to whatever the port is wired to.
+#[derive(Clone, Debug, Default, PartialEq, Serialize, Deserialize)]
+pub struct Module {
+  pub name: String,
+  pub neurons: Vec<NeuronSpec>,
+  pub synapses: Vec<SynapseSpec>,
+  pub inputs: BTreeMap<String, Vec<String>>,
+  pub outputs: BTreeMap<String, Vec<String>>,
+}
+
+impl Module {
+  // Captures the whole network of `scheduler`, ports map to neuron ids of the pool.
+  // Instances of other modules in there become part of this one, that is how modules nest.
+  pub fn from_scheduler(
+    name: &str,
+    scheduler: &Scheduler,
+    inputs: &[(&str, Vec<String>)],
+    outputs: &[(&str, Vec<String>)],
+  ) -> Result<Self> {
+    let mut module = Module {
+      name: name.to_string(),
+      ..Module::default()
+    };
+    for (id, neuron) in scheduler.pool.iter() {
+      module.neurons.push(NeuronSpec {
+        id: id.clone(),
+        threshold: neuron.threshold,
+        tau: neuron.tau,
+        refractory: neuron.refractory,
+      });
+      for dendrite in neuron.connections().iter() {
+        module.synapses.push(SynapseSpec {
+          pre: id.clone(),
+          post: dendrite.get_neuron_id().clone(),
+          strength: dendrite.get_strength(),
+          delay: dendrite.get_delay(),
+        });
+      }
+    }
+    module.inputs = inputs.iter().map(|(port, ids)| (port.to_string(), ids.clone())).collect();
+    module.outputs = outputs.iter().map(|(port, ids)| (port.to_string(), ids.clone())).collect();
+    module.validate()?;
+    Ok(module)
+  }
+
+  pub fn validate(&self) -> Result<()> {
+    let known = |id: &String| self.neurons.iter().any(|n| &n.id == id);
+    for synapse in self.synapses.iter() {
+      for id in [&synapse.pre, &synapse.post] {
+        if !known(id) {
+          return Err(Error::other(format!("module {}: synapse {} -> {} uses unknown neuron {}",
+            self.name, synapse.pre, synapse.post, id)));
+        }
+      }
+    }
+    for (port, ids) in self.inputs.iter().chain(self.outputs.iter()) {
+      if let Some(id) = ids.iter().find(|id| !known(id)) {
+        return Err(Error::other(format!("module {}: port {} uses unknown neuron {}", self.name, port, id)));
+      }
+    }
+    Ok(())
+  }
+
+  pub fn to_json(&self) -> Result<String> {
+    Ok(serde_json::to_string_pretty(self)?)
+  }
+
+  pub fn from_json(json: &str) -> Result<Self> {
+    let module: Module = serde_json::from_str(json)?;
+    module.validate()?;
+    Ok(module)
+  }
+
+  pub fn save(&self, path: &str) -> Result<()> {
+    let writer = BufWriter::new(File::create(path)?);
+    Ok(serde_json::to_writer_pretty(writer, self)?)
+  }
+
+  pub fn load(path: &str) -> Result<Self> {
+    let reader = BufReader::new(File::open(path)?);
+    let module: Module = serde_json::from_reader(reader)?;
+    module.validate()?;
+    Ok(module)
+  }
+
+  // Adds a copy of the module to `scheduler`, the neuron `x` becomes `prefix/x`
+  pub fn instantiate(&self, scheduler: &mut Scheduler, prefix: &str) -> Result<Instance> {
+    self.validate()?;
+    let scoped = |id: &String| format!("{}/{}", prefix, id);
+    if let Some(id) = self.neurons.iter().map(|n| scoped(&n.id)).find(|id| scheduler.pool.contains_key(id)) {
+      return Err(Error::other(format!("cannot instantiate {} as {}: {} already exists", self.name, prefix, id)));
+    }
+    for spec in self.neurons.iter() {
+      let id = scheduler.add_neuron(spec.threshold, Some(scoped(&spec.id)));
+      scheduler.set_neuron_timing(&id, spec.tau, spec.refractory);
+    }
+    for synapse in self.synapses.iter() {
+      scheduler.connect_neurons_delayed(&scoped(&synapse.pre), &scoped(&synapse.post), Some(synapse.strength), synapse.delay);
+    }
+    let ports = |ports: &BTreeMap<String, Vec<String>>| ports.iter()
+      .map(|(port, ids)| (port.clone(), ids.iter().map(scoped).collect()))
+      .collect();
+    Ok(Instance {
+      prefix: prefix.to_string(),
+      inputs: ports(&self.inputs),
+      outputs: ports(&self.outputs),
+    })
+  }
+}
+
+// A module placed into a `Scheduler`, ports map to the prefixed neuron ids
+#[derive(Clone, Debug, PartialEq)]
+pub struct Instance {
+  pub prefix: String,
+  pub inputs: BTreeMap<String, Vec<String>>,
+  pub outputs: BTreeMap<String, Vec<String>>,
+}
+
+impl Instance {
+  pub fn input(&self, port: &str) -> Result<&Vec<String>> {
+    self.inputs.get(port)
+      .ok_or_else(|| Error::other(format!("{} has no input port {}", self.prefix, port)))
+  }
+
+  pub fn output(&self, port: &str) -> Result<&Vec<String>> {
+    self.outputs.get(port)
+      .ok_or_else(|| Error::other(format!("{} has no output port {}", self.prefix, port)))
+  }
+}
+
+// Connects every neuron of the output port to every neuron of the input port
+pub fn wire(
+  scheduler: &mut Scheduler,
+  from: &Instance,
+  output: &str,
+  to: &Instance,
+  input: &str,
+  strength: f64,
+  delay: f64,
+) -> Result<()> {
+  let pres = from.output(output)?;
+  let posts = to.input(input)?;
+  for pre in pres.iter() {
+    for post in posts.iter() {
+      scheduler.connect_neurons_delayed(pre, post, Some(strength), delay);
+    }
+  }
+  Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+  use std::collections::HashMap;
+
+  use crate::analysis::{dominant_period, phase_lag, spike_trains};
+  use crate::harness::{build_central_pattern_generator, excitatory_ring};
+  use crate::scheduler::Scheduler;
+  use crate::stimulation::StimulationProtocol;
+
+  use super::{wire, Module, SynapseSpec};
+
+  // flexor and extensor of a leg firing in turns
+  fn ring_module() -> Module {
+    let mut scheduler = Scheduler::new();
+    let (flexor, extensor) = excitatory_ring(&mut scheduler, "flexor", "extensor", 4.0);
+    Module::from_scheduler(
+      "excitatory_ring",
+      &scheduler,
+      &[("start", vec![flexor.clone()])],
+      &[("flexor", vec![flexor]), ("extensor", vec![extensor])],
+    ).unwrap()
+  }
+
+  #[test]
+  fn one_oscillator_per_leg() {
+    let module = Module::from_json(&ring_module().to_json().unwrap()).unwrap();
+    let mut scheduler = Scheduler::new();
+    let legs: Vec<_> = (0..4)
+      .map(|i| module.instantiate(&mut scheduler, &format!("leg{}", i)).unwrap())
+      .collect();
+    assert_eq!(scheduler.pool.len(), 8);
+    assert!(scheduler.pool.contains_key("leg2/extensor"));
+    assert!(module.instantiate(&mut scheduler, "leg0").is_err());
+
+    // each leg starts the next one 2 ms after its own flexor fired
+    for i in 0..3 {
+      wire(&mut scheduler, &legs[i], "flexor", &legs[i + 1], "start", 1.0, 2.0).unwrap();
+    }
+    assert!(wire(&mut scheduler, &legs[0], "knee", &legs[1], "start", 1.0, 1.0).is_err());
+
+    scheduler.recording = true;
+    scheduler.inject(HashMap::from([(legs[0].input("start").unwrap()[0].clone(), vec![1.0])]));
+    scheduler.run(80);
+
+    let trains = spike_trains(&scheduler.spikes);
+    for leg in legs.iter() {
+      let flexor = &trains[&leg.output("flexor").unwrap()[0]];
+      let extensor = &trains[&leg.output("extensor").unwrap()[0]];
+      assert_eq!(dominant_period(flexor, 1.0, 20.0), Some(8.0));
+      let lag = phase_lag(flexor, extensor).unwrap();
+      assert!((lag - 0.5).abs() < 1e-9, "lag = {}", lag);
+    }
+    // neighbouring legs are a quarter of a cycle apart
+    let lag = phase_lag(&trains["leg0/flexor"], &trains["leg1/flexor"]).unwrap();
+    assert!((lag - 0.25).abs() < 1e-9, "lag = {}", lag);
+  }
+
+  // the CPG of the scheduler tests, fed by the signal and the leg's feedback
+  fn cpg_module() -> Module {
+    let mut scheduler = Scheduler::new();
+    let (signal, feedback1, feedback2) = build_central_pattern_generator(&mut scheduler);
+    Module::from_scheduler(
+      "cpg",
+      &scheduler,
+      &[("signal", vec![signal]), ("l1", vec![feedback1]), ("l2", vec![feedback2])],
+      &[("d1", vec!["d1".to_string()]), ("d2", vec!["d2".to_string()])],
+    ).unwrap()
+  }
+
+  #[test]
+  fn central_pattern_generator_leg() {
+    let mut scheduler = Scheduler::new();
+    let front = cpg_module().instantiate(&mut scheduler, "front").unwrap();
+    let hind = ring_module().instantiate(&mut scheduler, "hind").unwrap();
+    // the hind leg starts once the front leg's first drive fired
+    wire(&mut scheduler, &front, "d1", &hind, "start", 1.0, 1.0).unwrap();
+    scheduler.stimulation = StimulationProtocol::from_json(r#"{
+      "seed": 0,
+      "stimuli": [
+        { "type": "pulse", "targets": ["front/l1"], "start": 0, "amplitude": 1.0, "period": 10, "width": 1 },
+        { "type": "pulse", "targets": ["front/signal"], "start": 0, "amplitude": 1.0, "period": 2, "width": 1 }
+      ]
+    }"#).unwrap();
+    scheduler.recording = true;
+    scheduler.run(1000);
+
+    let trains = spike_trains(&scheduler.spikes);
+    let d1 = &trains[&front.output("d1").unwrap()[0]];
+    let d2 = &trains[&front.output("d2").unwrap()[0]];
+    let lag = phase_lag(d1, d2).unwrap();
+    assert!((lag - 0.5).abs() < 0.05, "lag = {}", lag);
+    let flexor = &trains[&hind.output("flexor").unwrap()[0]];
+    assert_eq!(flexor[0], d1[0] + 1.0);
+  }
+
+  #[test]
+  fn instantiate_checks_the_module() {
+    let mut module = ring_module();
+    module.synapses.push(SynapseSpec { pre: "knee".to_string(), post: "flexor".to_string(), strength: 1.0, delay: 1.0 });
+    let mut scheduler = Scheduler::new();
+    assert!(module.instantiate(&mut scheduler, "leg").is_err());
+    assert!(scheduler.pool.is_empty());
+  }
+
+  #[test]
+  fn modules_nest() {
+    let leg = ring_module();
+    let mut scheduler = Scheduler::new();
+    let left = leg.instantiate(&mut scheduler, "left").unwrap();
+    let right = leg.instantiate(&mut scheduler, "right").unwrap();
+    wire(&mut scheduler, &left, "extensor", &right, "start", 1.0, 1.0).unwrap();
+    let pair = Module::from_scheduler(
+      "pair",
+      &scheduler,
+      &[("start", left.input("start").unwrap().clone())],
+      &[("right", right.output("flexor").unwrap().clone())],
+    ).unwrap();
+
+    let mut body = Scheduler::new();
+    let front = pair.instantiate(&mut body, "front").unwrap();
+    assert_eq!(front.output("right").unwrap(), &vec!["front/right/flexor".to_string()]);
+    assert!(body.pool.contains_key("front/left/extensor"));
+
+    let mut broken = pair.clone();
+    broken.outputs.insert("x".to_string(), vec!["missing".to_string()]);
+    assert!(Module::from_json(&broken.to_json().unwrap()).is_err());
+  }
+}
@@ -0,0 +1,606 @@
+use std::collections::{HashMap, HashSet};
+use std::io::Result;
+use std::thread;
+
+use rand::{Rng, SeedableRng};
+use rand::rngs::StdRng;
+use rand::seq::SliceRandom;
+use serde::{Serialize, Deserialize};
+
+use crate::scheduler::Scheduler;
+
+// NeuroEvolution of Augmenting Topologies over `Scheduler` networks:
+// genomes start as inputs fully connected to outputs and grow hidden neurons
+// and synapses by mutation. Genes carry innovation numbers so that crossover
+// can line up the same structure in two parents, and the population is split
+// into species of similar topology that compete mostly among themselves.
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum NodeKind {
+  Input,
+  Output,
+  Hidden,
+}
+
+#[derive(Clone, Debug, PartialEq, Serialize, Deserialize)]
+pub struct NodeGene {
+  pub id: usize,
+  pub kind: NodeKind,
+  pub threshold: i32,
+}
+
+#[derive(Clone, Debug, PartialEq, Serialize, Deserialize)]
+pub struct ConnectionGene {
+  pub innovation: usize,
+  pub pre: usize,
+  pub post: usize,
+  pub weight: f64,
+  pub enabled: bool,
+}
+
+#[derive(Clone, Debug)]
+pub struct NeatConfig {
+  pub population_size: usize,
+  pub seed: u64,
+  // threads evaluating the fitness
+  pub threads: usize,
+  // stop as soon as a genome reaches it
+  pub fitness_target: Option<f64>,
+  // compatibility distance: excess * c1 / n + disjoint * c2 / n + mean weight difference * c3
+  pub excess_coefficient: f64,
+  pub disjoint_coefficient: f64,
+  pub weight_coefficient: f64,
+  pub compatibility_threshold: f64,
+  // new weights are uniform in [-weight_range, weight_range]
+  pub weight_range: f64,
+  pub weight_mutation_rate: f64,
+  // largest change of a perturbed weight
+  pub weight_perturbation: f64,
+  // probability that a mutated weight is replaced instead of perturbed
+  pub weight_replace_rate: f64,
+  pub threshold_mutation_rate: f64,
+  pub add_connection_rate: f64,
+  pub add_node_rate: f64,
+  pub crossover_rate: f64,
+  // fraction of every species allowed to reproduce
+  pub survival_threshold: f64,
+  // best genomes of every species copied unchanged into the next generation
+  pub elitism: usize,
+}
+
+impl Default for NeatConfig {
+  fn default() -> Self {
+    NeatConfig {
+      population_size: 100,
+      seed: 0,
+      threads: thread::available_parallelism().map_or(1, |n| n.get()),
+      fitness_target: None,
+      excess_coefficient: 1.0,
+      disjoint_coefficient: 1.0,
+      weight_coefficient: 0.4,
+      compatibility_threshold: 3.0,
+      weight_range: 2.0,
+      weight_mutation_rate: 0.8,
+      weight_perturbation: 0.5,
+      weight_replace_rate: 0.1,
+      threshold_mutation_rate: 0.1,
+      add_connection_rate: 0.1,
+      add_node_rate: 0.03,
+      crossover_rate: 0.75,
+      survival_threshold: 0.2,
+      elitism: 1,
+    }
+  }
+}
+
+// Hands out innovation numbers and node ids, the same structural mutation
+// gets the same number in every genome
+#[derive(Clone, Debug, Default)]
+pub struct Innovations {
+  next_innovation: usize,
+  next_node: usize,
+  connections: HashMap<(usize, usize), usize>,
+  // innovation of the split connection -> id of the node put in between
+  splits: HashMap<usize, usize>,
+}
+
+impl Innovations {
+  pub fn new(n_nodes: usize) -> Self {
+    Innovations {
+      next_node: n_nodes,
+      ..Innovations::default()
+    }
+  }
+
+  pub fn connection(&mut self, pre: usize, post: usize) -> usize {
+    let next = &mut self.next_innovation;
+    *self.connections.entry((pre, post)).or_insert_with(|| {
+      *next += 1;
+      *next - 1
+    })
+  }
+
+  pub fn split(&mut self, innovation: usize) -> usize {
+    let next = &mut self.next_node;
+    *self.splits.entry(innovation).or_insert_with(|| {
+      *next += 1;
+      *next - 1
+    })
+  }
+
+  pub fn new_node(&mut self) -> usize {
+    self.next_node += 1;
+    self.next_node - 1
+  }
+}
+
+#[derive(Clone, Debug, Default, PartialEq, Serialize, Deserialize)]
+pub struct Genome {
+  pub nodes: Vec<NodeGene>,
+  // sorted by innovation
+  pub connections: Vec<ConnectionGene>,
+  #[serde(default)]
+  pub fitness: f64,
+}
+
+impl Genome {
+  // Every input connected to every output with a random weight
+  pub fn minimal(n_inputs: usize, n_outputs: usize, innovations: &mut Innovations, config: &NeatConfig, rng: &mut StdRng) -> Self {
+    let mut genome = Genome::default();
+    for id in 0..n_inputs + n_outputs {
+      let kind = if id < n_inputs { NodeKind::Input } else { NodeKind::Output };
+      genome.nodes.push(NodeGene { id, kind, threshold: 1 });
+    }
+    for pre in 0..n_inputs {
+      for post in n_inputs..n_inputs + n_outputs {
+        genome.connections.push(ConnectionGene {
+          innovation: innovations.connection(pre, post),
+          pre,
+          post,
+          weight: rng.gen_range(-config.weight_range..=config.weight_range),
+          enabled: true,
+        });
+      }
+    }
+    genome
+  }
+
+  pub fn to_json(&self) -> Result<String> {
+    Ok(serde_json::to_string_pretty(self)?)
+  }
+
+  pub fn from_json(json: &str) -> Result<Self> {
+    Ok(serde_json::from_str(json)?)
+  }
+
+  fn node(&self, id: usize) -> Option<&NodeGene> {
+    self.nodes.iter().find(|node| node.id == id)
+  }
+
+  // Neuron ids in the built network: `in0`, `in1`, ... `out0`, ... and `h<node id>`
+  pub fn neuron_id(&self, id: usize) -> String {
+    let node = self.node(id).unwrap();
+    let index = self.nodes.iter().filter(|n| n.kind == node.kind && n.id < id).count();
+    match node.kind {
+      NodeKind::Input => format!("in{}", index),
+      NodeKind::Output => format!("out{}", index),
+      NodeKind::Hidden => format!("h{}", id),
+    }
+  }
+
+  pub fn inputs(&self) -> Vec<String> {
+    self.nodes.iter().filter(|n| n.kind == NodeKind::Input).map(|n| self.neuron_id(n.id)).collect()
+  }
+
+  pub fn outputs(&self) -> Vec<String> {
+    self.nodes.iter().filter(|n| n.kind == NodeKind::Output).map(|n| self.neuron_id(n.id)).collect()
+  }
+
+  pub fn to_scheduler(&self) -> Scheduler {
+    let mut scheduler = Scheduler::new();
+    let ids: HashMap<usize, String> = self.nodes.iter()
+      .map(|node| (node.id, scheduler.add_neuron(node.threshold, Some(self.neuron_id(node.id)))))
+      .collect();
+    for gene in self.connections.iter().filter(|gene| gene.enabled) {
+      scheduler.connect_neurons(&ids[&gene.pre], &ids[&gene.post], Some(gene.weight));
+    }
+    scheduler
+  }
+
+  pub fn mutate(&mut self, innovations: &mut Innovations, config: &NeatConfig, rng: &mut StdRng) {
+    if rng.gen_bool(config.add_node_rate) {
+      self.add_node(innovations, rng);
+    }
+    if rng.gen_bool(config.add_connection_rate) {
+      self.add_connection(innovations, config, rng);
+    }
+    for gene in self.connections.iter_mut() {
+      if !rng.gen_bool(config.weight_mutation_rate) {
+        continue;
+      }
+      if rng.gen_bool(config.weight_replace_rate) {
+        gene.weight = rng.gen_range(-config.weight_range..=config.weight_range);
+      } else {
+        gene.weight += rng.gen_range(-config.weight_perturbation..=config.weight_perturbation);
+      }
+    }
+    for node in self.nodes.iter_mut().filter(|n| n.kind != NodeKind::Input) {
+      if rng.gen_bool(config.threshold_mutation_rate) {
+        node.threshold = (node.threshold + if rng.gen_bool(0.5) { 1 } else { -1 }).max(1);
+      }
+    }
+  }
+
+  // New synapse between two neurons that are not connected yet, recurrent ones included.
+  // False if no free pair was found.
+  pub fn add_connection(&mut self, innovations: &mut Innovations, config: &NeatConfig, rng: &mut StdRng) -> bool {
+    let existing: HashSet<(usize, usize)> = self.connections.iter().map(|g| (g.pre, g.post)).collect();
+    let posts: Vec<usize> = self.nodes.iter().filter(|n| n.kind != NodeKind::Input).map(|n| n.id).collect();
+    for _ in 0..20 {
+      let pre = self.nodes.choose(rng).unwrap().id;
+      let post = *posts.choose(rng).unwrap();
+      if existing.contains(&(pre, post)) {
+        continue;
+      }
+      self.connections.push(ConnectionGene {
+        innovation: innovations.connection(pre, post),
+        pre,
+        post,
+        weight: rng.gen_range(-config.weight_range..=config.weight_range),
+        enabled: true,
+      });
+      self.connections.sort_by_key(|g| g.innovation);
+      return true;
+    }
+    false
+  }
+
+  // Splits an enabled synapse with a hidden neuron: pre -(1)-> new -(old weight)-> post.
+  // The signal arrives one tick later than before.
+  pub fn add_node(&mut self, innovations: &mut Innovations, rng: &mut StdRng) -> bool {
+    let enabled: Vec<usize> = (0..self.connections.len()).filter(|i| self.connections[*i].enabled).collect();
+    let i = match enabled.choose(rng) {
+      Some(i) => *i,
+      None => return false,
+    };
+    self.connections[i].enabled = false;
+    let ConnectionGene { innovation, pre, post, weight, .. } = self.connections[i].clone();
+    let mut id = innovations.split(innovation);
+    if self.node(id).is_some() {
+      id = innovations.new_node();
+    }
+    self.nodes.push(NodeGene { id, kind: NodeKind::Hidden, threshold: 1 });
+    self.connections.push(ConnectionGene {
+      innovation: innovations.connection(pre, id),
+      pre,
+      post: id,
+      weight: 1.0,
+      enabled: true,
+    });
+    self.connections.push(ConnectionGene {
+      innovation: innovations.connection(id, post),
+      pre: id,
+      post,
+      weight,
+      enabled: true,
+    });
+    self.connections.sort_by_key(|g| g.innovation);
+    true
+  }
+
+  // Child of `self`, the fitter parent, and `other`: matching genes are taken from
+  // either parent at random, disjoint and excess genes only from `self`
+  pub fn crossover(&self, other: &Genome, rng: &mut StdRng) -> Genome {
+    let others: HashMap<usize, &ConnectionGene> = other.connections.iter().map(|g| (g.innovation, g)).collect();
+    let connections = self.connections.iter()
+      .map(|gene| match others.get(&gene.innovation) {
+        Some(other_gene) => {
+          let mut child = if rng.gen_bool(0.5) { gene.clone() } else { (*other_gene).clone() };
+          // a gene disabled in either parent stays disabled most of the time
+          child.enabled = (gene.enabled && other_gene.enabled) || rng.gen_bool(0.25);
+          child
+        },
+        None => gene.clone(),
+      })
+      .collect();
+    let nodes = self.nodes.iter()
+      .map(|node| match other.node(node.id) {
+        Some(other_node) if rng.gen_bool(0.5) => NodeGene { threshold: other_node.threshold, ..node.clone() },
+        _ => node.clone(),
+      })
+      .collect();
+    Genome {
+      nodes,
+      connections,
+      fitness: 0.0,
+    }
+  }
+
+  pub fn distance(&self, other: &Genome, config: &NeatConfig) -> f64 {
+    let mine: HashMap<usize, f64> = self.connections.iter().map(|g| (g.innovation, g.weight)).collect();
+    let theirs: HashMap<usize, f64> = other.connections.iter().map(|g| (g.innovation, g.weight)).collect();
+    let max_mine = mine.keys().max().cloned().unwrap_or(0);
+    let max_theirs = theirs.keys().max().cloned().unwrap_or(0);
+    let (mut excess, mut disjoint, mut matching, mut weight_difference) = (0, 0, 0, 0.0);
+    for (innovation, weight) in mine.iter() {
+      match theirs.get(innovation) {
+        Some(w) => {
+          matching += 1;
+          weight_difference += (weight - w).abs();
+        },
+        None if *innovation > max_theirs => excess += 1,
+        None => disjoint += 1,
+      }
+    }
+    for innovation in theirs.keys().filter(|i| !mine.contains_key(i)) {
+      if *innovation > max_mine {
+        excess += 1;
+      } else {
+        disjoint += 1;
+      }
+    }
+    let n = mine.len().max(theirs.len()).max(1) as f64;
+    let mean_difference = if matching > 0 { weight_difference / matching as f64 } else { 0.0 };
+    config.excess_coefficient * excess as f64 / n
+      + config.disjoint_coefficient * disjoint as f64 / n
+      + config.weight_coefficient * mean_difference
+  }
+}
+
+#[derive(Clone, Debug)]
+pub struct Species {
+  pub id: usize,
+  pub representative: Genome,
+  // indices into `Population::genomes`
+  pub members: Vec<usize>,
+}
+
+pub struct Population {
+  pub genomes: Vec<Genome>,
+  pub species: Vec<Species>,
+  pub config: NeatConfig,
+  pub generation: usize,
+  // best genome of all generations evaluated so far
+  pub champion: Option<Genome>,
+  innovations: Innovations,
+  rng: StdRng,
+  next_species: usize,
+}
+
+impl Population {
+  pub fn new(n_inputs: usize, n_outputs: usize, config: NeatConfig) -> Self {
+    let mut rng = StdRng::seed_from_u64(config.seed);
+    let mut innovations = Innovations::new(n_inputs + n_outputs);
+    let genomes = (0..config.population_size)
+      .map(|_| Genome::minimal(n_inputs, n_outputs, &mut innovations, &config, &mut rng))
+      .collect();
+    Population {
+      genomes,
+      species: Vec::new(),
+      config,
+      generation: 0,
+      champion: None,
+      innovations,
+      rng,
+      next_species: 0,
+    }
+  }
+
+  // Runs `fitness` on every genome, split over `config.threads` threads
+  pub fn evaluate<F>(&mut self, fitness: F)
+  where
+    F: Fn(&Genome) -> f64 + Sync,
+  {
+    let chunk = self.genomes.len().div_ceil(self.config.threads.max(1)).max(1);
+    let fitness = &fitness;
+    thread::scope(|scope| {
+      for genomes in self.genomes.chunks_mut(chunk) {
+        scope.spawn(move || {
+          for genome in genomes.iter_mut() {
+            genome.fitness = fitness(genome);
+          }
+        });
+      }
+    });
+    if let Some(best) = self.best() {
+      if self.champion.as_ref().is_none_or(|c| best.fitness > c.fitness) {
+        self.champion = Some(best.clone());
+      }
+    }
+  }
+
+  pub fn best(&self) -> Option<&Genome> {
+    self.genomes.iter().max_by(|a, b| a.fitness.total_cmp(&b.fitness))
+  }
+
+  // Puts every genome into the first species whose representative is close enough
+  pub fn speciate(&mut self) {
+    for species in self.species.iter_mut() {
+      species.members.clear();
+    }
+    for (i, genome) in self.genomes.iter().enumerate() {
+      let found = self.species.iter_mut()
+        .find(|s| genome.distance(&s.representative, &self.config) < self.config.compatibility_threshold);
+      match found {
+        Some(species) => species.members.push(i),
+        None => {
+          self.species.push(Species { id: self.next_species, representative: genome.clone(), members: vec![i] });
+          self.next_species += 1;
+        },
+      }
+    }
+    self.species.retain(|s| !s.members.is_empty());
+    // the next generation is compared to a member of this one
+    for species in self.species.iter_mut() {
+      let member = *species.members.choose(&mut self.rng).unwrap();
+      species.representative = self.genomes[member].clone();
+    }
+  }
+
+  // Replaces the genomes by their offspring. Every species gets a share of the
+  // population proportional to its mean fitness (explicit fitness sharing).
+  pub fn reproduce(&mut self) {
+    let size = self.config.population_size;
+    let min = self.genomes.iter().map(|g| g.fitness).fold(f64::INFINITY, f64::min);
+    let shared: Vec<f64> = self.species.iter()
+      .map(|s| s.members.iter().map(|i| self.genomes[*i].fitness - min + 1e-3).sum::<f64>() / s.members.len() as f64)
+      .collect();
+    let total: f64 = shared.iter().sum();
+    let mut counts: Vec<usize> = shared.iter().map(|f| (size as f64 * f / total).floor() as usize).collect();
+    let mut order: Vec<usize> = (0..counts.len()).collect();
+    order.sort_by(|a, b| shared[*b].total_cmp(&shared[*a]));
+    for i in order.iter().cycle().take(size - counts.iter().sum::<usize>()) {
+      counts[*i] += 1;
+    }
+
+    let mut next = Vec::with_capacity(size);
+    for (species, count) in self.species.iter().zip(counts) {
+      let mut members = species.members.clone();
+      members.sort_by(|a, b| self.genomes[*b].fitness.total_cmp(&self.genomes[*a].fitness));
+      for i in members.iter().take(self.config.elitism.min(count)) {
+        next.push(self.genomes[*i].clone());
+      }
+      let survivors = ((members.len() as f64 * self.config.survival_threshold).ceil() as usize).max(1);
+      let parents = &members[..survivors];
+      for _ in self.config.elitism.min(count)..count {
+        let first = &self.genomes[*parents.choose(&mut self.rng).unwrap()];
+        let mut child = if parents.len() > 1 && self.rng.gen_bool(self.config.crossover_rate) {
+          let second = &self.genomes[*parents.choose(&mut self.rng).unwrap()];
+          if second.fitness > first.fitness {
+            second.crossover(first, &mut self.rng)
+          } else {
+            first.crossover(second, &mut self.rng)
+          }
+        } else {
+          first.clone()
+        };
+        child.mutate(&mut self.innovations, &self.config, &mut self.rng);
+        next.push(child);
+      }
+    }
+    self.genomes = next;
+    self.generation += 1;
+  }
+
+  // Evaluates and breeds for at most `generations` generations, returns the champion
+  pub fn evolve<F>(&mut self, generations: usize, fitness: F) -> Genome
+  where
+    F: Fn(&Genome) -> f64 + Sync,
+  {
+    for _ in 0..generations {
+      self.evaluate(&fitness);
+      let champion = self.champion.as_ref().unwrap();
+      if self.config.fitness_target.is_some_and(|target| champion.fitness >= target) {
+        break;
+      }
+      self.speciate();
+      self.reproduce();
+    }
+    self.champion.clone().unwrap_or_default()
+  }
+}
+
+#[cfg(test)]
+mod tests {
+  use rand::SeedableRng;
+  use rand::rngs::StdRng;
+
+  use crate::cosim::{CoSimulation, Environment};
+  use crate::frozen_lake::FrozenLake;
+  use crate::sensor::Encoding;
+
+  use super::{Genome, Innovations, NeatConfig, Population};
+
+  #[test]
+  fn structural_mutations_share_innovations() {
+    let config = NeatConfig::default();
+    let mut rng = StdRng::seed_from_u64(1);
+    let mut innovations = Innovations::new(3);
+    let mut a = Genome::minimal(2, 1, &mut innovations, &config, &mut rng);
+    let mut b = a.clone();
+    assert_eq!(a.connections.len(), 2);
+
+    // the same split in two genomes gives the same node and innovations
+    let mut rng_a = StdRng::seed_from_u64(7);
+    let mut rng_b = StdRng::seed_from_u64(7);
+    assert!(a.add_node(&mut innovations, &mut rng_a));
+    assert!(b.add_node(&mut innovations, &mut rng_b));
+    assert_eq!(a.nodes, b.nodes);
+    assert_eq!(a.connections.iter().filter(|g| g.enabled).count(), 3);
+    assert_eq!(a.distance(&b, &config), 0.0);
+
+    let hidden = a.nodes[3].id;
+    assert_eq!(a.neuron_id(hidden), format!("h{}", hidden));
+    let scheduler = a.to_scheduler();
+    assert_eq!(scheduler.pool.len(), 4);
+    assert_eq!(a.inputs(), vec!["in0", "in1"]);
+    assert_eq!(a.outputs(), vec!["out0"]);
+
+    // excess genes of the fitter parent are inherited, the other one's are not
+    b.connections.truncate(2);
+    b.nodes.truncate(3);
+    let child = a.crossover(&b, &mut rng);
+    assert_eq!(child.connections.len(), a.connections.len());
+    let child = b.crossover(&a, &mut rng);
+    assert_eq!(child.connections.len(), 2);
+    assert!(a.distance(&b, &config) > 0.0);
+    assert_eq!(Genome::from_json(&a.to_json().unwrap()).unwrap(), a);
+  }
+
+  // out0 should fire exactly when in1 does and out1 when in0 does
+  fn crossed_fitness(genome: &Genome) -> f64 {
+    let (inputs, outputs) = (genome.inputs(), genome.outputs());
+    let mut correct = 0.0;
+    for pattern in [[false, false], [false, true], [true, false], [true, true]] {
+      let mut scheduler = genome.to_scheduler();
+      let signals = inputs.iter().zip(pattern).filter(|(_, on)| *on).map(|(id, _)| (id.clone(), vec![1.0])).collect();
+      scheduler.inject(signals);
+      let fired: Vec<String> = scheduler.run(4).concat();
+      for (output, expected) in outputs.iter().zip([pattern[1], pattern[0]]) {
+        if fired.contains(output) == expected {
+          correct += 1.0;
+        }
+      }
+    }
+    correct
+  }
+
+  #[test]
+  fn evolves_crossed_wiring() {
+    let config = NeatConfig { population_size: 30, seed: 3, threads: 4, fitness_target: Some(8.0), ..NeatConfig::default() };
+    let mut population = Population::new(2, 2, config.clone());
+    let champion = population.evolve(30, crossed_fitness);
+    assert_eq!(champion.fitness, 8.0);
+
+    // the same seed gives the same run, whatever the number of threads
+    let mut again = Population::new(2, 2, NeatConfig { threads: 1, ..config });
+    assert_eq!(again.evolve(30, crossed_fitness), champion);
+    assert_eq!(again.generation, population.generation);
+  }
+
+  const ACTION_NAMES: [&str; 4] = ["north", "south", "east", "west"];
+
+  // reward of one episode plus how far the agent got towards the goal
+  fn lake_fitness(genome: &Genome) -> f64 {
+    let mut scheduler = genome.to_scheduler();
+    scheduler.add_sensor("position", genome.inputs(), Encoding::OneHot { amplitude: 1.0 });
+    for (name, output) in ACTION_NAMES.iter().zip(genome.outputs()) {
+      scheduler.add_actuator(name, vec![(output, 1.0)], 1.0);
+    }
+    let actuators = ACTION_NAMES.iter().map(|name| name.to_string()).collect();
+    let mut cosim = CoSimulation::new(scheduler, FrozenLake::new(), 3, vec!["position".to_string()], actuators);
+    let (reward, _) = cosim.run_episode(12).unwrap();
+    let cell = cosim.env.observe()[0] as usize;
+    reward + (cell / 4 + cell % 4) as f64 / 6.0
+  }
+
+  #[test]
+  fn evolves_frozen_lake_controller() {
+    let config = NeatConfig { population_size: 50, seed: 5, fitness_target: Some(2.0), ..NeatConfig::default() };
+    let mut population = Population::new(16, 4, config);
+    let champion = population.evolve(60, lake_fitness);
+    // reached the goal without walking into a wall
+    assert_eq!(champion.fitness, 2.0, "after {} generations", population.generation);
+  }
+}
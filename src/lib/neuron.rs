@@ -40,16 +40,19 @@ pub type NeuronId = String;
 //   }
 // }
 
-struct Dendrite {
+pub struct Dendrite {
   neuron_id: String,
   strength: f64,
+  // ms from the action potential to the arrival of the signal
+  delay: f64,
 }
 
 impl Dendrite {
   pub fn new(neuron_id: String, strength: f64) -> Self {
     Dendrite {
       neuron_id,
-      strength
+      strength,
+      delay: DEFAULT_DELAY,
     }
   }
 
@@ -65,6 +68,22 @@ impl Dendrite {
     &self.neuron_id
   }
 
+  pub fn get_strength(&self) -> f64 {
+    self.strength
+  }
+
+  pub fn set_strength(&mut self, strength: f64) {
+    self.strength = strength;
+  }
+
+  pub fn get_delay(&self) -> f64 {
+    self.delay
+  }
+
+  pub fn set_delay(&mut self, delay: f64) {
+    self.delay = delay;
+  }
+
   // pub fn get_neuron_mut(&mut self) -> &mut Arc<Neuron> {
   //   &mut self.neuron
   // }
@@ -86,6 +105,9 @@ impl Dendrite {
   // }
 }
 
+// Synaptic delay in ms used when none is given, one tick with the default timestep
+pub const DEFAULT_DELAY: f64 = 1.0;
+
 // Transform functions
 fn sum(values: Vec<f64>) -> f64 {
   values.iter().sum()
@@ -104,6 +126,12 @@ pub struct Neuron {
 //   pub id: Uuid,
   pub potential: f64,
   pub threshold: i32,
+  // membrane time constant in ms, the potential leaks towards 0; `None` keeps it forever
+  pub tau: Option<f64>,
+  // ms after an action potential during which all input is ignored
+  pub refractory: f64,
+  // tick of the last action potential
+  pub last_spike: Option<u64>,
   transform_fn: fn(Vec<f64>) -> f64,
 //   scheduler: RefCell<Scheduler>,
 //   activation_delay: u64,
@@ -118,6 +146,9 @@ impl Neuron {
       name: String::new(),
       potential: 0.0,
       threshold: i32::MAX,
+      tau: None,
+      refractory: 0.0,
+      last_spike: None,
       transform_fn: sum,
     }
   }
@@ -130,6 +161,9 @@ impl Neuron {
       name: name.unwrap_or_else(|| Uuid::new_v4().to_string()),
       potential: 0.0,
       threshold,
+      tau: None,
+      refractory: 0.0,
+      last_spike: None,
       transform_fn: sum,
     }
   }
@@ -155,15 +189,42 @@ impl Neuron {
     &self.name
   }
 
+  pub fn connections(&self) -> &Vec<Box<Dendrite>> {
+    &self.post_synaptic_connections
+  }
+
+  pub fn connections_mut(&mut self) -> &mut Vec<Box<Dendrite>> {
+    &mut self.post_synaptic_connections
+  }
+
   pub fn connect_to(&mut self, post_neuron_id: String, strength: Option<f64>) {
+    self.connect_to_delayed(post_neuron_id, strength, DEFAULT_DELAY);
+  }
+
+  pub fn connect_to_delayed(&mut self, post_neuron_id: String, strength: Option<f64>, delay: f64) {
     self.post_synaptic_connections.push(Box::new(
       Dendrite {
         neuron_id: post_neuron_id,
         strength: strength.unwrap_or(0.0),
+        delay,
       }
     ));
   }
 
+  pub fn is_refractory(&self, time: u64, dt: f64) -> bool {
+    match self.last_spike {
+      Some(spike_time) => (time.saturating_sub(spike_time) as f64) * dt < self.refractory,
+      None => false,
+    }
+  }
+
+  // Leak of the membrane potential over `dt` ms
+  pub fn decay(&mut self, dt: f64) {
+    if let Some(tau) = self.tau {
+      self.potential *= (-dt / tau).exp();
+    }
+  }
+
   // fn create_dendrite(self: &Arc<Self>, strength: Option<f64>) -> Dendrite {
   //   Dendrite::new(
   //     Arc::clone(&self),
@@ -306,14 +367,6 @@ impl Neuron {
 //   }
 // }
 
-// struct Sensor {
-//   connections: Vec<RefCell<Dendrite>>,
-// }
-
-// impl Sensor {
-//   // Implement Sensor methods as needed
-// }
-
 
 #[cfg(test)]
 mod tests {
@@ -0,0 +1,145 @@
+use std::fs::File;
+use std::io::{BufReader, BufWriter, Error, Read, Result, Write};
+
+use ndarray::{ArrayD, ArrayView, Dimension, IxDyn};
+
+// Minimal reader and writer of numpy .npy files (format 1.0), little endian
+// float32 and float64 arrays, enough to exchange weights with numpy
+
+const MAGIC: &[u8] = b"\x93NUMPY";
+
+// Writes `array` as '<f4' in C order
+pub fn write<D: Dimension>(path: &str, array: ArrayView<f32, D>) -> Result<()> {
+  let shape: Vec<String> = array.shape().iter().map(|n| n.to_string()).collect();
+  let shape = match shape.len() {
+    1 => format!("({},)", shape[0]),
+    _ => format!("({})", shape.join(", ")),
+  };
+  let mut header = format!("{{'descr': '<f4', 'fortran_order': False, 'shape': {}, }}", shape);
+  // magic, version and header length take 10 bytes, the data starts 64 byte aligned
+  let total = (10 + header.len() + 1).div_ceil(64) * 64;
+  header += &" ".repeat(total - 10 - header.len() - 1);
+  header.push('\n');
+
+  let mut writer = BufWriter::new(File::create(path)?);
+  writer.write_all(MAGIC)?;
+  writer.write_all(&[1, 0])?;
+  writer.write_all(&(header.len() as u16).to_le_bytes())?;
+  writer.write_all(header.as_bytes())?;
+  for x in array.iter() {
+    writer.write_all(&x.to_le_bytes())?;
+  }
+  writer.flush()
+}
+
+// Value of `key` in the header dict, up to the next top-level comma
+fn header_value<'a>(header: &'a str, key: &str) -> Result<&'a str> {
+  let start = header.find(&format!("'{}':", key))
+    .ok_or_else(|| Error::other(format!("npy header has no {}: {}", key, header)))?
+    + key.len() + 3;
+  let rest = header[start..].trim_start();
+  let end = if rest.starts_with('(') {
+    rest.find(')').map(|i| i + 1)
+  } else {
+    rest.find(',')
+  };
+  end.map(|end| rest[..end].trim())
+    .ok_or_else(|| Error::other(format!("malformed npy header: {}", header)))
+}
+
+// Reads a float32 or float64 array, as f32
+pub fn read(path: &str) -> Result<ArrayD<f32>> {
+  let mut bytes = Vec::new();
+  BufReader::new(File::open(path)?).read_to_end(&mut bytes)
+    .map_err(|e| Error::other(format!("{}: {}", path, e)))?;
+  parse(&bytes).map_err(|e| Error::other(format!("{}: {}", path, e)))
+}
+
+pub fn parse(bytes: &[u8]) -> Result<ArrayD<f32>> {
+  if bytes.len() < 10 || &bytes[..6] != MAGIC {
+    return Err(Error::other("not an npy file"));
+  }
+  let (header_len, offset) = match bytes[6] {
+    1 => (u16::from_le_bytes([bytes[8], bytes[9]]) as usize, 10),
+    2 | 3 if bytes.len() >= 12 => (u32::from_le_bytes([bytes[8], bytes[9], bytes[10], bytes[11]]) as usize, 12),
+    version => return Err(Error::other(format!("unsupported npy version {}", version))),
+  };
+  let header = bytes.get(offset..offset + header_len)
+    .and_then(|h| std::str::from_utf8(h).ok())
+    .ok_or_else(|| Error::other("truncated npy header"))?;
+
+  let descr = header_value(header, "descr")?.trim_matches(|c| c == '\'' || c == '"');
+  let fortran_order = match header_value(header, "fortran_order")? {
+    "False" => false,
+    "True" => true,
+    other => return Err(Error::other(format!("bad fortran_order {}", other))),
+  };
+  let shape: Vec<usize> = header_value(header, "shape")?
+    .trim_matches(|c| c == '(' || c == ')')
+    .split(',')
+    .map(|n| n.trim())
+    .filter(|n| !n.is_empty())
+    .map(|n| n.parse().map_err(|_| Error::other(format!("bad shape {}", header))))
+    .collect::<Result<_>>()?;
+
+  let data = &bytes[offset + header_len..];
+  let count: usize = shape.iter().product();
+  let values: Vec<f32> = match descr {
+    "<f4" => data.chunks_exact(4).take(count).map(|b| f32::from_le_bytes([b[0], b[1], b[2], b[3]])).collect(),
+    "<f8" => data.chunks_exact(8).take(count)
+      .map(|b| f64::from_le_bytes([b[0], b[1], b[2], b[3], b[4], b[5], b[6], b[7]]) as f32)
+      .collect(),
+    other => return Err(Error::other(format!("unsupported dtype {}, expected <f4 or <f8", other))),
+  };
+  if values.len() != count {
+    return Err(Error::other(format!("shape {:?} needs {} values, the file has {}", shape, count, values.len())));
+  }
+  if fortran_order {
+    // column major is the transpose of the reversed shape in row major
+    let reversed: Vec<usize> = shape.iter().rev().cloned().collect();
+    let array = ArrayD::from_shape_vec(IxDyn(&reversed), values).map_err(Error::other)?;
+    Ok(array.reversed_axes().as_standard_layout().into_owned())
+  } else {
+    ArrayD::from_shape_vec(IxDyn(&shape), values).map_err(Error::other)
+  }
+}
+
+#[cfg(test)]
+mod tests {
+  use ndarray::{arr1, arr2, Ix2};
+
+  use super::{parse, read, write};
+
+  #[test]
+  fn round_trip() {
+    let path = std::env::temp_dir().join(format!("qu_npy_{}.npy", std::process::id()));
+    let path = path.to_str().unwrap();
+    let a = arr2(&[[1.0f32, 2.5, -3.0], [4.0, 5.0, 6.0]]);
+    write(path, a.view()).unwrap();
+    let bytes = std::fs::read(path).unwrap();
+    // the header is padded to 128 bytes, like numpy does
+    assert_eq!(bytes.len(), 128 + 6 * 4);
+    assert_eq!(read(path).unwrap().into_dimensionality::<Ix2>().unwrap(), a);
+
+    write(path, arr1(&[7.0f32]).view()).unwrap();
+    assert_eq!(read(path).unwrap().shape(), &[1]);
+    std::fs::remove_file(path).unwrap();
+  }
+
+  #[test]
+  fn reads_numpy_float64_in_fortran_order() {
+    // np.save(f, np.asfortranarray(np.array([[1., 2.], [3., 4.]])))
+    let header = "{'descr': '<f8', 'fortran_order': True, 'shape': (2, 2), }";
+    let mut bytes = b"\x93NUMPY\x01\x00".to_vec();
+    bytes.extend_from_slice(&(header.len() as u16).to_le_bytes());
+    bytes.extend_from_slice(header.as_bytes());
+    for x in [1.0f64, 3.0, 2.0, 4.0] {
+      bytes.extend_from_slice(&x.to_le_bytes());
+    }
+    let a = parse(&bytes).unwrap().into_dimensionality::<Ix2>().unwrap();
+    assert_eq!(a, arr2(&[[1.0, 2.0], [3.0, 4.0]]));
+
+    assert!(parse(&bytes[..bytes.len() - 8]).is_err());
+    assert!(parse(b"PK\x03\x04").is_err());
+  }
+}
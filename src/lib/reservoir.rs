@@ -0,0 +1,304 @@
+use std::io::{Error, Result};
+
+use ndarray::prelude::*;
+use ndarray::Array2;
+use rand::{Rng, SeedableRng};
+use rand::rngs::StdRng;
+
+use crate::ann::ANN;
+use crate::scheduler::Scheduler;
+use crate::sensor::Encoding;
+
+// Liquid state machine: a fixed random recurrent network of leaky neurons is
+// driven by the inputs, and only a readout of its (low-pass filtered) spikes is trained
+
+#[derive(Clone, Debug)]
+pub struct ReservoirConfig {
+  pub size: usize,
+  // probability of a connection between two reservoir neurons
+  pub connectivity: f64,
+  pub inhibitory_fraction: f64,
+  pub excitatory_weight: f64,
+  // strength of the connections of inhibitory neurons, negative
+  pub inhibitory_weight: f64,
+  // probability that an input neuron projects to a reservoir neuron
+  pub input_connectivity: f64,
+  pub input_weight: f64,
+  pub threshold: i32,
+  // membrane time constant, ms
+  pub tau: f64,
+  // ms
+  pub refractory: f64,
+  // synaptic delays are uniform in [dt, max_delay] ms
+  pub max_delay: f64,
+  // time constant of the spike traces that make up the state, ms
+  pub filter_tau: f64,
+  pub seed: u64,
+}
+
+impl Default for ReservoirConfig {
+  fn default() -> Self {
+    ReservoirConfig {
+      size: 100,
+      connectivity: 0.1,
+      inhibitory_fraction: 0.2,
+      excitatory_weight: 0.5,
+      inhibitory_weight: -1.0,
+      input_connectivity: 0.3,
+      input_weight: 1.0,
+      threshold: 1,
+      tau: 5.0,
+      refractory: 2.0,
+      max_delay: 3.0,
+      filter_tau: 10.0,
+      seed: 0,
+    }
+  }
+}
+
+pub struct Reservoir {
+  // reservoir neuron ids, in the order of the state vector
+  pub neurons: Vec<String>,
+  // one sensor per input channel
+  pub sensors: Vec<String>,
+  pub filter_tau: f64,
+  traces: Array1<f64>,
+}
+
+impl Reservoir {
+  // Adds the reservoir to `scheduler`. Every input channel gets its own input neuron,
+  // fed by a sensor with the given encoding and projecting to random reservoir neurons.
+  pub fn build(scheduler: &mut Scheduler, prefix: &str, config: &ReservoirConfig, inputs: &[(&str, Encoding)]) -> Self {
+    let mut rng = StdRng::seed_from_u64(config.seed);
+    let dt = scheduler.dt;
+    let delay = |rng: &mut StdRng| if config.max_delay > dt { rng.gen_range(dt..=config.max_delay) } else { dt };
+
+    let neurons: Vec<String> = (0..config.size)
+      .map(|i| {
+        let id = scheduler.add_neuron(config.threshold, Some(format!("{}r{}", prefix, i)));
+        scheduler.set_neuron_timing(&id, Some(config.tau), config.refractory);
+        id
+      })
+      .collect();
+    let n_inhibitory = (config.size as f64 * config.inhibitory_fraction).round() as usize;
+    for (pre, pre_id) in neurons.iter().enumerate() {
+      let strength = if pre < n_inhibitory { config.inhibitory_weight } else { config.excitatory_weight };
+      for (post, post_id) in neurons.iter().enumerate() {
+        if pre != post && rng.gen_bool(config.connectivity) {
+          let d = delay(&mut rng);
+          scheduler.connect_neurons_delayed(pre_id, post_id, Some(strength), d);
+        }
+      }
+    }
+
+    let mut sensors = Vec::new();
+    for (name, encoding) in inputs.iter() {
+      let input = scheduler.add_neuron(1, Some(format!("{}{}", prefix, name)));
+      for post_id in neurons.iter() {
+        if rng.gen_bool(config.input_connectivity) {
+          scheduler.connect_neurons(&input, post_id, Some(config.input_weight));
+        }
+      }
+      let sensor = format!("{}{}", prefix, name);
+      scheduler.add_sensor(&sensor, vec![input], encoding.clone());
+      sensors.push(sensor);
+    }
+
+    Reservoir {
+      traces: Array1::zeros(neurons.len()),
+      neurons,
+      sensors,
+      filter_tau: config.filter_tau,
+    }
+  }
+
+  // Filtered spikes of the reservoir neurons
+  pub fn state(&self) -> Array1<f64> {
+    self.traces.clone()
+  }
+
+  pub fn reset_state(&mut self) {
+    self.traces.fill(0.0);
+  }
+
+  // One tick of the scheduler, the traces decay and every spike adds 1
+  pub fn tick(&mut self, scheduler: &mut Scheduler) -> Vec<String> {
+    let fired = scheduler.tick();
+    self.traces *= (-scheduler.dt / self.filter_tau).exp();
+    for (i, id) in self.neurons.iter().enumerate() {
+      if fired.contains(id) {
+        self.traces[i] += 1.0;
+      }
+    }
+    fired
+  }
+
+  // Holds every row of `samples` (one value per input channel) on the sensors for
+  // `ticks_per_sample` ticks and returns the state at the end of each, samples x neurons
+  pub fn run(&mut self, scheduler: &mut Scheduler, samples: &Array2<f64>, ticks_per_sample: u64) -> Array2<f64> {
+    let mut states = Array2::zeros((samples.nrows(), self.neurons.len()));
+    for (sample, mut state) in samples.rows().into_iter().zip(states.rows_mut()) {
+      for (sensor, value) in self.sensors.iter().zip(sample.iter()) {
+        scheduler.set_sensor(sensor, *value);
+      }
+      for _ in 0..ticks_per_sample {
+        self.tick(scheduler);
+      }
+      state.assign(&self.traces);
+    }
+    states
+  }
+}
+
+// Appends a column of ones for the bias
+fn with_bias(states: &Array2<f64>) -> Array2<f64> {
+  let mut x = Array2::ones((states.nrows(), states.ncols() + 1));
+  x.slice_mut(s![.., ..states.ncols()]).assign(states);
+  x
+}
+
+// Solves a x = b for a square `a` by Gaussian elimination with partial pivoting
+fn solve(mut a: Array2<f64>, mut b: Array2<f64>) -> Result<Array2<f64>> {
+  let n = a.nrows();
+  for col in 0..n {
+    let pivot = (col..n).max_by(|i, j| a[[*i, col]].abs().total_cmp(&a[[*j, col]].abs())).unwrap();
+    if a[[pivot, col]].abs() < 1e-12 {
+      return Err(Error::other("singular system, try a larger ridge"));
+    }
+    if pivot != col {
+      for k in 0..n {
+        a.swap([pivot, k], [col, k]);
+      }
+      for k in 0..b.ncols() {
+        b.swap([pivot, k], [col, k]);
+      }
+    }
+    for row in col + 1..n {
+      let factor = a[[row, col]] / a[[col, col]];
+      if factor == 0.0 {
+        continue;
+      }
+      for k in col..n {
+        a[[row, k]] -= factor * a[[col, k]];
+      }
+      for k in 0..b.ncols() {
+        b[[row, k]] -= factor * b[[col, k]];
+      }
+    }
+  }
+  let mut x = Array2::zeros(b.raw_dim());
+  for row in (0..n).rev() {
+    for k in 0..b.ncols() {
+      let sum: f64 = (row + 1..n).map(|j| a[[row, j]] * x[[j, k]]).sum();
+      x[[row, k]] = (b[[row, k]] - sum) / a[[row, row]];
+    }
+  }
+  Ok(x)
+}
+
+// Linear map from reservoir states to outputs, the last row of `weights` is the bias
+pub struct LinearReadout {
+  // (neurons + 1) x outputs
+  pub weights: Array2<f64>,
+}
+
+impl LinearReadout {
+  // Ridge regression: minimizes |X W - Y|^2 + ridge |W|^2 over samples x neurons
+  // states and samples x outputs targets
+  pub fn fit(states: &Array2<f64>, targets: &Array2<f64>, ridge: f64) -> Result<Self> {
+    if states.nrows() != targets.nrows() {
+      return Err(Error::other(format!("{} states but {} targets", states.nrows(), targets.nrows())));
+    }
+    let x = with_bias(states);
+    let mut xtx = x.t().dot(&x);
+    for i in 0..xtx.nrows() {
+      xtx[[i, i]] += ridge;
+    }
+    let xty = x.t().dot(targets);
+    Ok(LinearReadout { weights: solve(xtx, xty)? })
+  }
+
+  pub fn predict(&self, states: &Array2<f64>) -> Array2<f64> {
+    with_bias(states).dot(&self.weights)
+  }
+}
+
+// Trains an `ANN` with one hidden layer as a non-linear readout of one output
+pub fn train_ann_readout(states: &Array2<f64>, targets: &Array1<f64>, hidden: usize, epochs: i32) -> ANN {
+  let mut ann = ANN::new(&[states.ncols(), hidden, 1]);
+  ann.train(epochs, states.mapv(|x| x as f32), targets.view().insert_axis(Axis(1)).mapv(|y| y as f32));
+  ann
+}
+
+#[cfg(test)]
+mod tests {
+  use ndarray::prelude::*;
+  use ndarray::Array2;
+  use rand::{Rng, SeedableRng};
+  use rand::rngs::StdRng;
+
+  use crate::scheduler::Scheduler;
+  use crate::sensor::Encoding;
+
+  use super::{train_ann_readout, LinearReadout, Reservoir, ReservoirConfig};
+
+  fn accuracy(predicted: ArrayView1<f64>, targets: ArrayView1<f64>) -> f64 {
+    let correct = predicted.iter().zip(targets.iter())
+      .filter(|(p, t)| (**p > 0.5) == (**t > 0.5))
+      .count();
+    correct as f64 / targets.len() as f64
+  }
+
+  #[test]
+  fn least_squares_recovers_linear_map() {
+    let states = arr2(&[[0.0, 1.0], [1.0, 0.0], [1.0, 1.0], [2.0, 1.0]]);
+    let targets = states.map_axis(Axis(1), |s| 2.0 * s[0] - s[1] + 0.5).insert_axis(Axis(1));
+    let readout = LinearReadout::fit(&states, &targets, 0.0).unwrap();
+    let error = (&readout.predict(&states) - &targets).mapv(f64::abs).sum();
+    assert!(error < 1e-9, "error = {}", error);
+    assert!(LinearReadout::fit(&states, &targets.slice(s![..2, ..]).to_owned(), 0.0).is_err());
+  }
+
+  // Reservoir states for 300 random bits, the target is the previous bit
+  fn previous_input_states() -> (Array2<f64>, Array1<f64>) {
+    let mut scheduler = Scheduler::new();
+    let config = ReservoirConfig { seed: 1, ..ReservoirConfig::default() };
+    let mut reservoir = Reservoir::build(&mut scheduler, "lsm/", &config, &[("u", Encoding::Rate { amplitude: 1.0 })]);
+
+    let mut rng = StdRng::seed_from_u64(2);
+    let n = 300;
+    let bits: Vec<f64> = (0..n).map(|_| if rng.gen_bool(0.5) { 1.0 } else { 0.0 }).collect();
+    let samples = Array2::from_shape_vec((n, 1), bits.clone()).unwrap();
+    let states = reservoir.run(&mut scheduler, &samples, 10);
+
+    let previous = Array1::from_shape_fn(n - 1, |i| bits[i]);
+    (states.slice(s![1.., ..]).to_owned(), previous)
+  }
+
+  #[test]
+  fn remembers_previous_input() {
+    let (states, previous) = previous_input_states();
+    let previous = previous.insert_axis(Axis(1));
+    let (train, test) = (200, states.nrows());
+    let readout = LinearReadout::fit(
+      &states.slice(s![..train, ..]).to_owned(), &previous.slice(s![..train, ..]).to_owned(), 1e-2).unwrap();
+    let predicted = readout.predict(&states.slice(s![train..test, ..]).to_owned());
+    let score = accuracy(predicted.column(0), previous.slice(s![train..test, 0]));
+    assert!(score > 0.9, "accuracy {}", score);
+  }
+
+  #[test]
+  fn ann_readout_remembers_previous_input() {
+    let (states, previous) = previous_input_states();
+    let (train, test) = (200, states.nrows());
+    // the states sit around the same rate, standardized they don't saturate the sigmoids
+    let mean = states.slice(s![..train, ..]).mean_axis(Axis(0)).unwrap();
+    let std = states.slice(s![..train, ..]).std_axis(Axis(0), 0.0).mapv(|x| x.max(1e-9));
+    let states = (&states - &mean) / &std;
+    let ann = train_ann_readout(
+      &states.slice(s![..train, ..]).to_owned(), &previous.slice(s![..train]).to_owned(), 8, 50);
+    let predicted = ann.forward_batch(states.slice(s![train..test, ..]).mapv(|x| x as f32).view()).mapv(f64::from);
+    let score = accuracy(predicted.column(0), previous.slice(s![train..test]));
+    assert!(score > 0.9, "accuracy {}", score);
+  }
+}
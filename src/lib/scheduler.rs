@@ -3,9 +3,16 @@ use std::rc::Rc;
 use std::sync::Arc;
 use std::thread;
 use std::collections::{HashMap, BTreeMap};
+use std::io::{Error, Result};
+use serde::{Serialize, Deserialize};
 use uuid::Uuid;
 
+use crate::dense::DenseNetwork;
+use crate::lint::{self, LintConfig, LintReport};
 use crate::neuron::{Neuron};
+use crate::sensor::{Actuator, Encoding, Sensor};
+use crate::snapshot::{Frame, History, Snapshot};
+use crate::stimulation::StimulationProtocol;
 
 // The time required to transmit a signal from one neuron through action potential 
 // to dendrites of the next connected neuron can vary, but it typically ranges from 
@@ -22,9 +29,36 @@ use crate::neuron::{Neuron};
 // during which the neuron can generate another action potential, 
 // but only in response to a stronger-than-normal stimulus. Can range from 2 to 4 milliseconds or more.
 
+// Neuron time constants, refractory periods, synaptic delays and stimulation times
+// are given in ms and converted to ticks with `dt`, the length of one tick in ms.
+
+#[derive(Clone, Debug, PartialEq, Serialize, Deserialize)]
+pub struct Spike {
+  // ms since the start of the simulation
+  pub time: f64,
+  pub neuron: String,
+}
+
 pub struct Scheduler {
   pub pool: BTreeMap<String, Arc<Box<Neuron>>>,
+  // ticks since the start, see `time_ms` for the simulated time
   pub time: u64,
+  // ms per tick
+  pub dt: f64,
+  // signals waiting for delivery, keyed by the tick they arrive at
+  pub events: BTreeMap<u64, HashMap<String, Vec<f64>>>,
+  pub stimulation: StimulationProtocol,
+  // disabled until `enable_history` is called
+  pub history: History,
+  pub sensors: BTreeMap<String, Sensor>,
+  pub actuators: BTreeMap<String, Actuator>,
+  // spikes are collected into `spikes` only while `recording` is on
+  pub recording: bool,
+  pub spikes: Vec<Spike>,
+  // prints every signal a neuron integrates, off by default
+  pub verbose: bool,
+  // clock-driven update, see `enable_dense_mode`
+  dense: Option<DenseNetwork>,
 }
 
 impl Scheduler {
@@ -32,9 +66,28 @@ impl Scheduler {
     Scheduler {
       pool: BTreeMap::new(),
       time: 0,
+      dt: 1.0,
+      events: BTreeMap::new(),
+      stimulation: StimulationProtocol::new(),
+      history: History::new(0),
+      sensors: BTreeMap::new(),
+      actuators: BTreeMap::new(),
+      recording: false,
+      spikes: Vec::new(),
+      verbose: false,
+      dense: None,
     }
   }
 
+  pub fn time_ms(&self) -> f64 {
+    self.time as f64 * self.dt
+  }
+
+  // Anything shorter than a tick still takes one tick
+  pub fn ms_to_ticks(&self, ms: f64) -> u64 {
+    ms_to_ticks(ms, self.dt)
+  }
+
   pub fn add_neuron(&mut self, threshold: i32, name: Option<String>) -> String {
     let neuron = Box::new(Neuron::new(threshold, name));
     let neuron_id = neuron.get_name().clone();
@@ -46,15 +99,100 @@ impl Scheduler {
   pub fn connect_neurons(&mut self, pre_id: &String, post_id: &String, strength: Option<f64>) {
     let pre = self.find_neuron_by_id_mut(pre_id).unwrap();
     pre.connect_to(post_id.clone(), strength);
+    self.history.invalidate_keyframe();
+  }
+
+  pub fn connect_neurons_delayed(&mut self, pre_id: &String, post_id: &String, strength: Option<f64>, delay: f64) {
+    let pre = self.find_neuron_by_id_mut(pre_id).unwrap();
+    pre.connect_to_delayed(post_id.clone(), strength, delay);
+    self.history.invalidate_keyframe();
+  }
+
+  // Membrane time constant and refractory period, both in ms
+  pub fn set_neuron_timing(&mut self, neuron_id: &String, tau: Option<f64>, refractory: f64) {
+    let neuron = self.find_neuron_by_id_mut(neuron_id).unwrap();
+    neuron.tau = tau;
+    neuron.refractory = refractory;
+  }
+
+  // Sensor feeds an external value into `targets` every tick
+  pub fn add_sensor(&mut self, name: &str, targets: Vec<String>, encoding: Encoding) {
+    self.sensors.insert(name.to_string(), Sensor::new(targets, encoding));
+  }
+
+  // Actuator turns spikes of the `sources` neurons into a continuous command
+  pub fn add_actuator(&mut self, name: &str, sources: Vec<(String, f64)>, decay: f64) {
+    self.actuators.insert(name.to_string(), Actuator::new(sources, decay));
+  }
+
+  pub fn set_sensor(&mut self, name: &str, value: f64) {
+    if let Some(sensor) = self.sensors.get_mut(name) {
+      sensor.set_value(value);
+    } else {
+      println!("Failed to get sensor '{}'", name);
+    }
+  }
+
+  pub fn read_actuator(&self, name: &str) -> Option<f64> {
+    self.actuators.get(name).map(|actuator| actuator.command())
+  }
+
+  // Signals delivered together with everything else on the next tick
+  pub fn inject(&mut self, signals: HashMap<String, Vec<f64>>) {
+    let pending = self.events.entry(self.time).or_default();
+    for (neuron_id, mut strengths) in signals {
+      pending.entry(neuron_id).or_default().append(&mut strengths);
+    }
+  }
+
+  // Changes every connection from `pre_id` to `post_id`, false if there is none
+  pub fn set_connection_strength(&mut self, pre_id: &String, post_id: &String, strength: f64) -> bool {
+    self.update_connection_strengths(std::slice::from_ref(pre_id), |_, post, _| (post == post_id).then_some(strength)) > 0
+  }
+
+  // Gives every connection of the `pre_ids` neurons the strength `update` returns
+  // for (pre id, post id, old strength), none keeps the old one. The history and
+  // the dense mode see all changes at once, returns how many connections changed.
+  pub fn update_connection_strengths(
+    &mut self,
+    pre_ids: &[String],
+    mut update: impl FnMut(&String, &String, f64) -> Option<f64>,
+  ) -> usize {
+    let mut changed = 0;
+    for pre_id in pre_ids.iter() {
+      if let Some(pre) = self.find_neuron_by_id_mut(pre_id) {
+        for dendrite in pre.connections_mut().iter_mut() {
+          if let Some(strength) = update(pre_id, dendrite.get_neuron_id(), dendrite.get_strength()) {
+            dendrite.set_strength(strength);
+            changed += 1;
+          }
+        }
+      }
+    }
+    if changed > 0 {
+      self.history.invalidate_keyframe();
+    }
+    if changed > 0 && self.is_dense() {
+      self.enable_dense_mode();
+    }
+    changed
+  }
+
+  // Static checks of the topology, see `lint::lint`
+  pub fn lint(&self, config: &LintConfig) -> LintReport {
+    lint::lint(self, config)
   }
 
   pub fn prepare_next_layer(&mut self, mut activated_neurons: HashMap<String, Vec<f64>>) -> Vec<String> {
     let mut neurons_next_layer: Vec<String> = Vec::new();
+    let verbose = self.verbose;
     for (neuron_id, signals) in activated_neurons.iter() {
       if let Some(neuron) = self.find_neuron_by_id_mut(&neuron_id) {
         // TODO: name it! it's potential activity or something
         let prev_potential = neuron.potential;
-        println!("{} + {:?} ===> {}", prev_potential, signals, neuron_id.clone());
+        if verbose {
+          println!("{} + {:?} ===> {}", prev_potential, signals, neuron_id.clone());
+        }
         let result = neuron.process_signals(signals);
         if let Some(potential_diff) = result {
           neuron.update_potential(potential_diff);
@@ -92,6 +230,250 @@ impl Scheduler {
       
   }
 
+  // One clock step: deliver pending signals and stimulation for the current tick,
+  // fire neurons that crossed the threshold and schedule their output.
+  pub fn tick(&mut self) -> Vec<String> {
+    if self.history.capacity() > 0 {
+      let frame = self.frame();
+      let mut history = std::mem::take(&mut self.history);
+      history.push(frame, || self.snapshot());
+      self.history = history;
+    }
+
+    let time = self.time;
+    let dt = self.dt;
+    let mut signals = self.events.remove(&time).unwrap_or_default();
+    self.stimulation.apply(time, dt, &mut signals);
+    for sensor in self.sensors.values_mut() {
+      sensor.encode(&mut signals);
+    }
+
+    let fired = match self.dense.as_mut() {
+      Some(dense) => dense.step(time, dt, &signals),
+      None => self.event_step(time, dt, signals),
+    };
+
+    for actuator in self.actuators.values_mut() {
+      actuator.integrate(&fired);
+    }
+    if self.recording {
+      for neuron_id in fired.iter() {
+        self.spikes.push(Spike {
+          time: time as f64 * dt,
+          neuron: neuron_id.clone(),
+        });
+      }
+    }
+
+    self.time += 1;
+    fired
+  }
+
+  fn event_step(&mut self, time: u64, dt: f64, mut signals: HashMap<String, Vec<f64>>) -> Vec<String> {
+    for neuron in self.pool.values_mut() {
+      if let Some(neuron) = Arc::get_mut(neuron) {
+        neuron.decay(dt);
+      }
+    }
+    signals.retain(|neuron_id, _| {
+      self.pool.get(neuron_id).is_none_or(|neuron| !neuron.is_refractory(time, dt))
+    });
+    let mut fired = self.prepare_next_layer(signals);
+    fired.sort();
+
+    let mut outgoing: Vec<(u64, String, f64)> = Vec::new();
+    for neuron_id in fired.iter() {
+      if let Some(neuron) = self.find_neuron_by_id_mut(neuron_id) {
+        neuron.potential = 0.0;
+        neuron.last_spike = Some(time);
+        for dendrite in neuron.connections().iter() {
+          let arrival = time + ms_to_ticks(dendrite.get_delay(), dt);
+          outgoing.push((arrival, dendrite.get_neuron_id().clone(), dendrite.get_strength()));
+        }
+      }
+    }
+    for (arrival, neuron_id, strength) in outgoing {
+      self.events.entry(arrival).or_default()
+        .entry(neuron_id).or_default()
+        .push(strength);
+    }
+    fired
+  }
+
+  // Switches to the clock-driven matrix update. Weights and timing are copied,
+  // call it again after changing the network.
+  pub fn enable_dense_mode(&mut self) {
+    self.disable_dense_mode();
+    self.dense = Some(DenseNetwork::from_scheduler(self));
+  }
+
+  // Back to the event-driven update, the dense state is written into the neurons
+  pub fn disable_dense_mode(&mut self) {
+    if let Some(dense) = self.dense.take() {
+      for (id, neuron) in self.pool.iter_mut() {
+        if let Some(neuron) = Arc::get_mut(neuron) {
+          neuron.potential = dense.potential(id).unwrap_or(neuron.potential);
+          neuron.last_spike = dense.last_spike(id);
+        }
+      }
+      for (arrival, signals) in dense.pending_events(self.time) {
+        let pending = self.events.entry(arrival).or_default();
+        for (neuron_id, mut strengths) in signals {
+          pending.entry(neuron_id).or_default().append(&mut strengths);
+        }
+      }
+    }
+  }
+
+  pub fn is_dense(&self) -> bool {
+    self.dense.is_some()
+  }
+
+  // Runs `ticks` clock steps, returns the neurons fired at every step
+  pub fn run(&mut self, ticks: u64) -> Vec<Vec<String>> {
+    (0..ticks).map(|_| self.tick()).collect()
+  }
+
+  // Signals in flight, in dense mode including the ones inside the network
+  fn pending_events(&self) -> BTreeMap<u64, HashMap<String, Vec<f64>>> {
+    let mut events = self.events.clone();
+    if let Some(dense) = self.dense.as_ref() {
+      for (arrival, signals) in dense.pending_events(self.time) {
+        let pending = events.entry(arrival).or_default();
+        for (neuron_id, mut strengths) in signals {
+          pending.entry(neuron_id).or_default().append(&mut strengths);
+        }
+      }
+    }
+    events
+  }
+
+  // (potential, last spike) of a neuron in the pool
+  fn neuron_state(&self, id: &String, neuron: &Neuron) -> (f64, Option<u64>) {
+    match self.dense.as_ref() {
+      Some(dense) => (dense.potential(id).unwrap_or(neuron.potential), dense.last_spike(id)),
+      None => (neuron.potential, neuron.last_spike),
+    }
+  }
+
+  pub fn snapshot(&self) -> Snapshot {
+    let mut snapshot = Snapshot {
+      time: self.time,
+      time_ms: self.time_ms(),
+      events: self.pending_events(),
+      ..Snapshot::default()
+    };
+    for (id, neuron) in self.pool.iter() {
+      let (potential, last_spike) = self.neuron_state(id, neuron);
+      snapshot.potentials.insert(id.clone(), potential);
+      let weights = neuron.connections().iter()
+        .map(|dendrite| dendrite.get_strength())
+        .collect();
+      snapshot.weights.insert(id.clone(), weights);
+      if let Some(spike_time) = last_spike {
+        snapshot.last_spikes.insert(id.clone(), spike_time);
+      }
+    }
+    for (name, sensor) in self.sensors.iter() {
+      snapshot.sensors.insert(name.clone(), sensor.phase());
+    }
+    for (name, actuator) in self.actuators.iter() {
+      snapshot.actuators.insert(name.clone(), actuator.command());
+    }
+    snapshot
+  }
+
+  // What `history` keeps of every tick, the weights only go into its keyframes
+  fn frame(&self) -> Frame {
+    let (potentials, last_spikes) = self.pool.iter()
+      .map(|(id, neuron)| self.neuron_state(id, neuron))
+      .unzip();
+    Frame {
+      time: self.time,
+      time_ms: self.time_ms(),
+      potentials,
+      last_spikes,
+      events: self.pending_events(),
+      sensors: self.sensors.values().map(|sensor| sensor.phase()).collect(),
+      actuators: self.actuators.values().map(|actuator| actuator.command()).collect(),
+    }
+  }
+
+  // Nothing changes unless the whole snapshot fits the network
+  pub fn restore(&mut self, snapshot: &Snapshot) -> Result<()> {
+    for id in snapshot.potentials.keys().chain(snapshot.weights.keys()) {
+      if self.find_neuron_by_id_mut(id).is_none() {
+        return Err(Error::other(format!("cannot restore neuron '{}'", id)));
+      }
+    }
+    for (id, weights) in snapshot.weights.iter() {
+      let connections = self.pool[id].connections().len();
+      if connections != weights.len() {
+        return Err(Error::other(format!(
+          "neuron '{}' has {} connections, snapshot has {}",
+          id, connections, weights.len())));
+      }
+    }
+    if let Some(name) = snapshot.sensors.keys().find(|name| !self.sensors.contains_key(*name)) {
+      return Err(Error::other(format!("cannot restore sensor '{}'", name)));
+    }
+    if let Some(name) = snapshot.actuators.keys().find(|name| !self.actuators.contains_key(*name)) {
+      return Err(Error::other(format!("cannot restore actuator '{}'", name)));
+    }
+
+    for (id, potential) in snapshot.potentials.iter() {
+      if let Some(neuron) = self.find_neuron_by_id_mut(id) {
+        neuron.potential = *potential;
+        neuron.last_spike = snapshot.last_spikes.get(id).copied();
+      }
+    }
+    for (id, weights) in snapshot.weights.iter() {
+      if let Some(neuron) = self.find_neuron_by_id_mut(id) {
+        for (dendrite, strength) in neuron.connections_mut().iter_mut().zip(weights.iter()) {
+          dendrite.set_strength(*strength);
+        }
+      }
+    }
+    for (name, phase) in snapshot.sensors.iter() {
+      if let Some(sensor) = self.sensors.get_mut(name) {
+        sensor.set_phase(*phase);
+      }
+    }
+    for (name, command) in snapshot.actuators.iter() {
+      if let Some(actuator) = self.actuators.get_mut(name) {
+        actuator.set_command(*command);
+      }
+    }
+    self.events = snapshot.events.clone();
+    self.time = snapshot.time;
+    if self.dense.is_some() {
+      self.dense = Some(DenseNetwork::from_scheduler(self));
+    }
+    Ok(())
+  }
+
+  // Keep the state of the last `capacity` ticks to be able to step backwards
+  pub fn enable_history(&mut self, capacity: usize) {
+    self.history = History::new(capacity);
+  }
+
+  // Goes back to the state before the last tick, false if there is no history left
+  pub fn step_back(&mut self) -> bool {
+    match self.history.pop() {
+      Some(snapshot) => self.restore(&snapshot).is_ok(),
+      None => false,
+    }
+  }
+
+  // Goes back up to `ticks` ticks, returns how many were actually rewound
+  pub fn rewind(&mut self, ticks: usize) -> usize {
+    let mut rewound = 0;
+    while rewound < ticks && self.step_back() {
+      rewound += 1;
+    }
+    rewound
+  }
+
   pub fn find_neuron_by_id_mut(&mut self, neuron_id: &String) -> Option<&mut Box<Neuron>> {
     if let Some(tt) = self.pool.get_mut(neuron_id) {
       return Arc::get_mut(tt);
@@ -119,6 +501,9 @@ impl Scheduler {
   }
 }
 
+fn ms_to_ticks(ms: f64, dt: f64) -> u64 {
+  ((ms / dt).round() as u64).max(1)
+}
 
 #[cfg(test)]
 mod tests {
@@ -126,7 +511,11 @@ mod tests {
   use std::collections::HashMap;
   use std::rc::Rc;
   use std::sync::Arc;
+  use crate::harness::{build_central_pattern_generator, Expected, Harness};
   use crate::neuron::Neuron;
+  use crate::sensor::Encoding;
+  use crate::snapshot::Snapshot;
+  use crate::stimulation::StimulationProtocol;
 
   use super::Scheduler;
 
@@ -220,100 +609,306 @@ mod tests {
     scheduler.connect_neurons(&correction, &fix, Some(1.0));
     scheduler.connect_neurons(&fix, &encoder, Some(1.0));
 
-    println!("-- signal 1 ({}): (1, 1) --", scheduler.time);
-    let a1 = scheduler.prepare_next_layer(HashMap::from([
-      (i1.clone(), vec![1.0]),
-      (i2.clone(), vec![1.0]),
-    ]));
-    scheduler.send_action_potential(a1);
-    // scheduler.print_pool();
-
-    println!("-- signal 2 ({}): (0, 1) --", scheduler.time);
-    let a2 = scheduler.prepare_next_layer(HashMap::from([
-      (i1.clone(), vec![1.0]),
-      (i2.clone(), vec![1.0]),
-    ]));
-    scheduler.send_action_potential(a2);
-    // scheduler.print_pool();
+    // (1, 1) then (0, 1)
+    let harness = Harness::new()
+      .input("i1", &i1)
+      .input("i2", &i2)
+      .output("encoder", &encoder)
+      .output("correction", &correction)
+      .output("latent", &latent)
+      .output("fix", &fix);
+    let recording = harness.run(&mut scheduler, &[vec![1.0, 1.0], vec![0.0, 1.0]], 8);
+    Expected::new(0)
+      .output("encoder", "....1...")
+      .output("correction", "..1.....")
+      .output("latent", "........")
+      .output("fix", "...1....")
+      .assert_matches(&recording);
   }
 
-  // CPG
   #[test]
   fn central_pattern_generator() {
     let mut scheduler = Box::new(Scheduler::new());
-    
-    let signal = scheduler.add_neuron(1, Some("signal".to_string()));
-    let feedback1 = scheduler.add_neuron(1, Some("l1".to_string()));
-    let feedback2 = scheduler.add_neuron(1, Some("l2".to_string()));
-    
-    {
-      let drive1 = scheduler.add_neuron(10, Some("d1".to_string()));
-      let drive2 = scheduler.add_neuron(10, Some("d2".to_string()));
-      let a1 = scheduler.add_neuron(2, Some("a1".to_string()));
-      let a2 = scheduler.add_neuron(2, Some("a2".to_string()));
-      let c1 = scheduler.add_neuron(1, Some("c1".to_string()));
-      let c2 = scheduler.add_neuron(1, Some("c2".to_string()));
-      let uv1 = scheduler.add_neuron(1, Some("uv1".to_string()));
-      let uv2 = scheduler.add_neuron(1, Some("uv2".to_string()));
-      
-      scheduler.connect_neurons(&signal, &a1, Some(1.0));
-      scheduler.connect_neurons(&signal, &a2, Some(1.0));
-      
-      scheduler.connect_neurons(&feedback1, &uv1, Some(0.5));
-      scheduler.connect_neurons(&feedback1, &c1, Some(0.5));
-      scheduler.connect_neurons(&feedback2, &uv2, Some(0.5));
-      scheduler.connect_neurons(&feedback2, &c2, Some(0.5));
-      
-      scheduler.connect_neurons(&uv1, &a1, Some(1.0));
-      scheduler.connect_neurons(&uv1, &uv1, Some(-1.0));
-      scheduler.connect_neurons(&uv1, &uv2, Some(-0.25));
-      scheduler.connect_neurons(&uv1, &c1, Some(0.25));
-      scheduler.connect_neurons(&uv1, &c2, Some(-0.25));
-      
-      scheduler.connect_neurons(&uv2, &a2, Some(1.0));
-      scheduler.connect_neurons(&uv2, &uv2, Some(-1.0));
-      scheduler.connect_neurons(&uv2, &uv1, Some(-0.25));
-      scheduler.connect_neurons(&uv2, &c2, Some(0.25));
-      scheduler.connect_neurons(&uv2, &c1, Some(-0.25));
-
-      scheduler.connect_neurons(&a1, &drive1, Some(1.0));
-      scheduler.connect_neurons(&a1, &uv1, Some(1.0));
-      scheduler.connect_neurons(&a1, &uv2, Some(-0.25));
-      scheduler.connect_neurons(&a2, &drive2, Some(1.0));
-      scheduler.connect_neurons(&a2, &uv2, Some(1.0));
-      scheduler.connect_neurons(&a2, &uv1, Some(-0.25));
-
-      scheduler.connect_neurons(&c1, &c2, Some(-1.0));
-      scheduler.connect_neurons(&c1, &uv1, Some(1.0)); // modulatory
-      scheduler.connect_neurons(&c1, &a1, Some(1.0)); // modulatory
-      scheduler.connect_neurons(&c2, &c1, Some(-1.0));
-      scheduler.connect_neurons(&c2, &uv2, Some(1.0)); // modulatory
-      scheduler.connect_neurons(&c2, &a2, Some(1.0)); // modulatory
-    }
-
-    println!("-- signal 1 ({}) --", scheduler.time);
-    let a1 = scheduler.prepare_next_layer(HashMap::from([
-      (feedback1.clone(), vec![1.0]),
-    ]));
-    scheduler.send_action_potential(a1.clone());
-
-    println!("-- signal 2 ({}) --", scheduler.time);
-    scheduler.send_action_potential(a1);
-
-    println!("-- signal 2 ({}) --", scheduler.time);
-    let a2 = scheduler.prepare_next_layer(HashMap::from([
-      (signal.clone(), vec![1.0]),
-      (feedback1.clone(), vec![1.0]),
-    ]));
-    scheduler.send_action_potential(a2);
-
-    println!("-- signal 3 ({}) --", scheduler.time);
-    let a3 = scheduler.prepare_next_layer(HashMap::from([
-      (signal.clone(), vec![1.0]),
-      (feedback1.clone(), vec![1.0]),
-    ]));
-    scheduler.send_action_potential(a3);
+    let (signal, feedback1, _) = build_central_pattern_generator(&mut scheduler);
+    let mut harness = Harness::new()
+      .input("signal", &signal)
+      .input("l1", &feedback1);
+    for name in ["a1", "a2", "c1", "c2", "uv1", "uv2", "d1", "d2"] {
+      let id = name.to_string();
+      harness = harness.output(name, &id);
+    }
+
+    // feedback alone, then feedback together with the signal
+    let vectors = [vec![0.0, 1.0], vec![0.0, 0.0], vec![1.0, 1.0], vec![1.0, 1.0]];
+    let recording = harness.run(&mut scheduler, &vectors, 10);
+    Expected::new(0)
+      .output("a1", "....1..1..")
+      .output("a2", "....1.....")
+      .output("c1", "...1..1...")
+      .output("c2", "..........")
+      .output("uv1", "...1.1..1.")
+      .output("uv2", "..........")
+      // the drives only integrate during this short run
+      .output("d1", "..........")
+      .output("d2", "..........")
+      .assert_matches(&recording);
+  }
+
+  #[test]
+  fn central_pattern_generator_protocol() {
+    let mut scheduler = Box::new(Scheduler::new());
+    build_central_pattern_generator(&mut scheduler);
+    scheduler.stimulation = StimulationProtocol::from_json(
+      include_str!("../../protocols/cpg.json")).unwrap();
+
+    let fired = scheduler.run(40);
+    assert_eq!(scheduler.time, 40);
+    for t in [0, 10, 20, 30] {
+      assert!(fired[t].contains(&"l1".to_string()), "l1 silent at {}: {:?}", t, fired[t]);
+    }
+    assert!(fired[20].contains(&"signal".to_string()), "{:?}", fired[20]);
+    assert!(!fired[0].contains(&"signal".to_string()), "{:?}", fired[0]);
+  }
+
+  #[test]
+  fn tick_delivers_next_step() {
+    let mut scheduler = Box::new(Scheduler::new());
+    let a = scheduler.add_neuron(1, Some("a".to_string()));
+    let b = scheduler.add_neuron(1, Some("b".to_string()));
+    scheduler.connect_neurons(&a, &b, Some(1.0));
+    scheduler.events.insert(0, HashMap::from([(a.clone(), vec![1.0])]));
+
+    assert_eq!(scheduler.tick(), vec![a.clone()]);
+    assert_eq!(scheduler.tick(), vec![b.clone()]);
+    assert!(scheduler.tick().is_empty());
+    assert!(scheduler.events.is_empty());
+  }
+
+  #[test]
+  fn snapshot_restore() {
+    let mut scheduler = Box::new(Scheduler::new());
+    build_central_pattern_generator(&mut scheduler);
+    scheduler.stimulation = StimulationProtocol::from_json(
+      include_str!("../../protocols/cpg.json")).unwrap();
+
+    scheduler.run(22);
+    let snapshot = scheduler.snapshot();
+    let expected = scheduler.run(10);
+
+    let json = snapshot.to_json().unwrap();
+    scheduler.restore(&Snapshot::from_json(&json).unwrap()).unwrap();
+    assert_eq!(scheduler.time, 22);
+    assert_eq!(scheduler.snapshot(), snapshot);
+    assert_eq!(scheduler.run(10), expected);
+  }
+
+  #[test]
+  fn history_step_back() {
+    let mut scheduler = Box::new(Scheduler::new());
+    build_central_pattern_generator(&mut scheduler);
+    scheduler.stimulation = StimulationProtocol::from_json(
+      include_str!("../../protocols/cpg.json")).unwrap();
+    scheduler.enable_history(5);
+
+    scheduler.run(20);
+    let state_at_17 = scheduler.history.get(2).unwrap();
+    assert_eq!(state_at_17.time, 17);
+    let expected = scheduler.run(3);
+
+    assert_eq!(scheduler.rewind(6), 5);
+    assert_eq!(scheduler.time, 18);
+    assert!(!scheduler.step_back());
+
+    scheduler.restore(&state_at_17).unwrap();
+    assert_eq!(scheduler.run(6)[3..], expected[..]);
+  }
+
+  #[test]
+  fn history_keeps_few_keyframes() {
+    let mut scheduler = Box::new(Scheduler::new());
+    build_central_pattern_generator(&mut scheduler);
+    scheduler.stimulation = StimulationProtocol::from_json(
+      include_str!("../../protocols/cpg.json")).unwrap();
+    scheduler.enable_history(100);
+    scheduler.history.set_keyframe_interval(25);
+
+    scheduler.run(40);
+    let weights = scheduler.snapshot().weights;
+    let expected = scheduler.run(60);
+    assert_eq!(scheduler.history.len(), 100);
+    assert_eq!(scheduler.history.keyframes(), 4);
+
+    // weights changed between ticks come back on rewind
+    let (pre, post) = (String::from("uv1"), String::from("c1"));
+    assert!(scheduler.set_connection_strength(&pre, &post, -5.0));
+    scheduler.tick();
+    assert_eq!(scheduler.history.keyframes(), 5);
+    assert_eq!(scheduler.rewind(61), 61);
+    assert_eq!(scheduler.time, 40);
+    assert_eq!(scheduler.snapshot().weights, weights);
+    assert_eq!(scheduler.run(60), expected);
+  }
+
+  #[test]
+  fn failed_restore_changes_nothing() {
+    let mut scheduler = Box::new(Scheduler::new());
+    build_central_pattern_generator(&mut scheduler);
+    scheduler.stimulation = StimulationProtocol::from_json(
+      include_str!("../../protocols/cpg.json")).unwrap();
+    scheduler.run(10);
+    let before = scheduler.snapshot();
+
+    let mut snapshot = before.clone();
+    snapshot.time = 3;
+    for potential in snapshot.potentials.values_mut() {
+      *potential = 0.5;
+    }
+    snapshot.weights.insert("unknown".to_string(), vec![]);
+    assert!(scheduler.restore(&snapshot).is_err());
+    assert_eq!(scheduler.snapshot(), before);
+
+    snapshot.weights.remove("unknown");
+    let id = snapshot.weights.keys().last().unwrap().clone();
+    snapshot.weights.get_mut(&id).unwrap().push(1.0);
+    assert!(scheduler.restore(&snapshot).is_err());
+    assert_eq!(scheduler.snapshot(), before);
+  }
 
+  #[test]
+  fn sensor_to_actuator() {
+    let mut scheduler = Box::new(Scheduler::new());
+    let input = scheduler.add_neuron(1, Some("input".to_string()));
+    let output = scheduler.add_neuron(1, Some("output".to_string()));
+    scheduler.connect_neurons(&input, &output, Some(1.0));
+    scheduler.add_sensor("light", vec![input.clone()], Encoding::Rate { amplitude: 1.0 });
+    scheduler.add_actuator("motor", vec![(output.clone(), 0.1)], 1.0);
+
+    scheduler.set_sensor("light", 0.5);
+    let fired = scheduler.run(10);
+    assert_eq!(fired[0], Vec::<String>::new());
+    assert_eq!(fired[1], vec![input.clone()]);
+    assert_eq!(fired[2], vec![output.clone()]);
+    // the 5th output spike is still on its way
+    assert!((scheduler.read_actuator("motor").unwrap() - 0.4).abs() < 1e-9);
+
+    scheduler.set_sensor("light", 0.0);
+    scheduler.run(10);
+    assert!((scheduler.read_actuator("motor").unwrap() - 0.5).abs() < 1e-9);
+    assert!(scheduler.read_actuator("wheel").is_none());
+  }
+
+  #[test]
+  fn delays_in_ms() {
+    let mut scheduler = Box::new(Scheduler::new());
+    scheduler.dt = 0.5;
+    scheduler.recording = true;
+    let a = scheduler.add_neuron(1, Some("a".to_string()));
+    let b = scheduler.add_neuron(1, Some("b".to_string()));
+    let c = scheduler.add_neuron(1, Some("c".to_string()));
+    scheduler.connect_neurons_delayed(&a, &b, Some(1.0), 2.0);
+    scheduler.connect_neurons(&b, &c, Some(1.0));
+    scheduler.events.insert(0, HashMap::from([(a.clone(), vec![1.0])]));
+
+    scheduler.run(10);
+    assert_eq!(scheduler.time_ms(), 5.0);
+    assert_eq!(scheduler.ms_to_ticks(2.0), 4);
+    let spikes: Vec<(f64, &str)> = scheduler.spikes.iter()
+      .map(|spike| (spike.time, spike.neuron.as_str()))
+      .collect();
+    assert_eq!(spikes, vec![(0.0, "a"), (2.0, "b"), (3.0, "c")]);
+  }
+
+  #[test]
+  fn leak_and_refractory_period() {
+    let mut scheduler = Box::new(Scheduler::new());
+    let leaky = scheduler.add_neuron(1, Some("leaky".to_string()));
+    let tonic = scheduler.add_neuron(1, Some("tonic".to_string()));
+    scheduler.set_neuron_timing(&leaky, Some(10.0), 0.0);
+    scheduler.set_neuron_timing(&tonic, None, 2.5);
+    scheduler.add_sensor("drive", vec![tonic.clone()], Encoding::Current { gain: 1.0 });
+    scheduler.set_sensor("drive", 1.0);
+    scheduler.events.insert(0, HashMap::from([(leaky.clone(), vec![0.5])]));
+
+    let fired = scheduler.run(10);
+    let leaky_neuron = scheduler.find_neuron_by_id(&leaky);
+    let expected = 0.5 * (-0.9f64).exp();
+    assert!((leaky_neuron.potential - expected).abs() < 1e-9, "potential = {}", leaky_neuron.potential);
+
+    let tonic_spikes: Vec<usize> = (0..10).filter(|t| fired[*t].contains(&tonic)).collect();
+    assert_eq!(tonic_spikes, vec![0, 3, 6, 9]);
+  }
+
+  // a bit of everything the dense mode has to reproduce: delays, leak,
+  // refractory period, inhibition and a self-loop
+  fn build_mixed_network(scheduler: &mut Scheduler) {
+    let names = ["n0", "n1", "n2", "n3", "n4", "n5"];
+    for (i, name) in names.iter().enumerate() {
+      scheduler.add_neuron(1 + (i as i32 % 2), Some(name.to_string()));
+    }
+    let n = |i: usize| names[i].to_string();
+    scheduler.connect_neurons(&n(0), &n(1), Some(1.0));
+    scheduler.connect_neurons(&n(0), &n(2), Some(0.75));
+    scheduler.connect_neurons_delayed(&n(1), &n(3), Some(1.25), 3.0);
+    scheduler.connect_neurons(&n(2), &n(3), Some(0.5));
+    scheduler.connect_neurons(&n(2), &n(4), Some(1.0));
+    scheduler.connect_neurons(&n(3), &n(4), Some(-0.5));
+    scheduler.connect_neurons(&n(3), &n(0), Some(0.25));
+    scheduler.connect_neurons_delayed(&n(4), &n(5), Some(2.0), 2.0);
+    scheduler.connect_neurons(&n(5), &n(5), Some(-1.0));
+    scheduler.connect_neurons(&n(5), &n(1), Some(1.0));
+    scheduler.set_neuron_timing(&n(2), Some(5.0), 0.0);
+    scheduler.set_neuron_timing(&n(4), None, 2.0);
+    scheduler.stimulation = StimulationProtocol::from_json(r#"{
+      "seed": 3,
+      "stimuli": [
+        { "type": "pulse", "targets": ["n0"], "period": 7, "width": 2, "amplitude": 1.0 },
+        { "type": "poisson", "targets": ["n2", "n3"], "rate": 0.3, "amplitude": 0.5 }
+      ]
+    }"#).unwrap();
+  }
+
+  #[test]
+  fn dense_mode_matches_event_driven() {
+    let mut event_driven = Box::new(Scheduler::new());
+    build_central_pattern_generator(&mut event_driven);
+    event_driven.stimulation = StimulationProtocol::from_json(
+      include_str!("../../protocols/cpg.json")).unwrap();
+    let mut dense = Box::new(Scheduler::new());
+    build_central_pattern_generator(&mut dense);
+    dense.stimulation = event_driven.stimulation.clone();
+    dense.enable_dense_mode();
+    assert_eq!(dense.run(40), event_driven.run(40));
+    assert_eq!(dense.snapshot(), event_driven.snapshot());
+
+    let mut event_driven = Box::new(Scheduler::new());
+    event_driven.dt = 0.5;
+    build_mixed_network(&mut event_driven);
+    let mut dense = Box::new(Scheduler::new());
+    dense.dt = 0.5;
+    build_mixed_network(&mut dense);
+    dense.enable_dense_mode();
+    assert!(dense.is_dense());
+    let fired = dense.run(100);
+    assert_eq!(fired, event_driven.run(100));
+    for name in ["n0", "n1", "n2", "n3", "n4", "n5"] {
+      assert!(fired.iter().any(|f| f.contains(&name.to_string())), "{} never fired", name);
+    }
+
+    // switching back keeps the signals in flight
+    dense.disable_dense_mode();
+    assert_eq!(dense.run(50), event_driven.run(50));
+  }
+
+  #[test]
+  fn dense_mode_history() {
+    let mut scheduler = Box::new(Scheduler::new());
+    build_mixed_network(&mut scheduler);
+    scheduler.enable_dense_mode();
+    scheduler.enable_history(10);
+    scheduler.run(30);
+    let expected = scheduler.run(5);
+    assert_eq!(scheduler.rewind(5), 5);
+    assert_eq!(scheduler.run(5), expected);
   }
 
   #[test]
@@ -0,0 +1,192 @@
+use std::collections::HashMap;
+
+use serde::{Serialize, Deserialize};
+
+// How an external value is turned into input signals for the sensor neurons
+#[derive(Clone, Debug, PartialEq, Serialize, Deserialize)]
+#[serde(tag = "type", rename_all = "snake_case")]
+pub enum Encoding {
+  // value * gain is injected as input current every tick
+  Current { gain: f64 },
+  // value in [0, 1] is the fraction of ticks with a spike, spikes are spread evenly
+  Rate { amplitude: f64 },
+  // targets have preferred values spread evenly over [min, max],
+  // each one receives a gaussian bump around its preferred value
+  Population { min: f64, max: f64, amplitude: f64 },
+  // rounded value is the index of the only target that receives a signal
+  OneHot { amplitude: f64 },
+}
+
+pub struct Sensor {
+  pub targets: Vec<String>,
+  pub encoding: Encoding,
+  value: f64,
+  phase: f64,
+}
+
+impl Sensor {
+  pub fn new(targets: Vec<String>, encoding: Encoding) -> Self {
+    Sensor {
+      targets,
+      encoding,
+      value: 0.0,
+      phase: 0.0,
+    }
+  }
+
+  pub fn value(&self) -> f64 {
+    self.value
+  }
+
+  pub fn set_value(&mut self, value: f64) {
+    self.value = value;
+  }
+
+  pub fn phase(&self) -> f64 {
+    self.phase
+  }
+
+  pub fn set_phase(&mut self, phase: f64) {
+    self.phase = phase;
+  }
+
+  // Adds this tick's input signals to `signals`
+  pub fn encode(&mut self, signals: &mut HashMap<String, Vec<f64>>) {
+    match self.encoding {
+      Encoding::Current { gain } => {
+        let current = self.value * gain;
+        if current != 0.0 {
+          for target in self.targets.iter() {
+            signals.entry(target.clone()).or_default().push(current);
+          }
+        }
+      },
+      Encoding::Rate { amplitude } => {
+        self.phase += self.value.clamp(0.0, 1.0);
+        if self.phase >= 1.0 {
+          self.phase -= 1.0;
+          for target in self.targets.iter() {
+            signals.entry(target.clone()).or_default().push(amplitude);
+          }
+        }
+      },
+      Encoding::Population { min, max, amplitude } => {
+        let n = self.targets.len();
+        let width = if n > 1 { (max - min) / ((n - 1) as f64) } else { max - min };
+        for (i, target) in self.targets.iter().enumerate() {
+          let center = if n > 1 { min + width * (i as f64) } else { (min + max) / 2.0 };
+          let distance = if width > 0.0 { (self.value - center) / width } else { 0.0 };
+          let current = amplitude * (-0.5 * distance * distance).exp();
+          if current > 1e-3 * amplitude.abs() {
+            signals.entry(target.clone()).or_default().push(current);
+          }
+        }
+      },
+      Encoding::OneHot { amplitude } => {
+        let idx = self.value.round();
+        if idx >= 0.0 && (idx as usize) < self.targets.len() {
+          signals.entry(self.targets[idx as usize].clone()).or_default().push(amplitude);
+        }
+      },
+    }
+  }
+}
+
+pub struct Actuator {
+  // output neuron and how much each of its spikes adds to the command
+  pub sources: Vec<(String, f64)>,
+  // fraction of the command kept from one tick to the next, 0 = only the last tick counts
+  pub decay: f64,
+  command: f64,
+}
+
+impl Actuator {
+  pub fn new(sources: Vec<(String, f64)>, decay: f64) -> Self {
+    Actuator {
+      sources,
+      decay,
+      command: 0.0,
+    }
+  }
+
+  pub fn command(&self) -> f64 {
+    self.command
+  }
+
+  pub fn set_command(&mut self, command: f64) {
+    self.command = command;
+  }
+
+  pub fn integrate(&mut self, fired: &[String]) {
+    self.command *= self.decay;
+    for (neuron_id, gain) in self.sources.iter() {
+      if fired.contains(neuron_id) {
+        self.command += gain;
+      }
+    }
+  }
+}
+
+#[cfg(test)]
+mod tests {
+  use std::collections::HashMap;
+
+  use super::{Actuator, Encoding, Sensor};
+
+  fn names(n: usize) -> Vec<String> {
+    (0..n).map(|i| format!("in{}", i)).collect()
+  }
+
+  #[test]
+  fn rate_encoding() {
+    let mut sensor = Sensor::new(names(1), Encoding::Rate { amplitude: 1.0 });
+    sensor.set_value(0.25);
+    let mut spikes = 0;
+    for _ in 0..100 {
+      let mut signals = HashMap::new();
+      sensor.encode(&mut signals);
+      spikes += signals.len();
+    }
+    assert_eq!(spikes, 25);
+  }
+
+  #[test]
+  fn population_encoding() {
+    let mut sensor = Sensor::new(names(5), Encoding::Population { min: 0.0, max: 1.0, amplitude: 1.0 });
+    sensor.set_value(0.75);
+    let mut signals = HashMap::new();
+    sensor.encode(&mut signals);
+    let strongest = signals.iter()
+      .max_by(|a, b| a.1[0].total_cmp(&b.1[0]))
+      .map(|(id, _)| id.clone())
+      .unwrap();
+    assert_eq!(strongest, "in3");
+    assert_eq!(signals["in3"], vec![1.0]);
+  }
+
+  #[test]
+  fn one_hot_encoding() {
+    let mut sensor = Sensor::new(names(4), Encoding::OneHot { amplitude: 2.0 });
+    sensor.set_value(2.0);
+    let mut signals = HashMap::new();
+    sensor.encode(&mut signals);
+    assert_eq!(signals, HashMap::from([("in2".to_string(), vec![2.0])]));
+
+    sensor.set_value(7.0);
+    let mut signals = HashMap::new();
+    sensor.encode(&mut signals);
+    assert!(signals.is_empty());
+  }
+
+  #[test]
+  fn actuator_integrates_spikes() {
+    let mut actuator = Actuator::new(vec![("up".to_string(), 1.0), ("down".to_string(), -1.0)], 0.5);
+    actuator.integrate(&["up".to_string()]);
+    actuator.integrate(&["up".to_string()]);
+    assert_eq!(actuator.command(), 1.5);
+    actuator.integrate(&["up".to_string(), "down".to_string()]);
+    assert_eq!(actuator.command(), 0.75);
+    actuator.integrate(&[]);
+    assert_eq!(actuator.command(), 0.375);
+  }
+}
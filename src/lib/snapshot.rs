@@ -0,0 +1,286 @@
+use std::collections::{BTreeMap, HashMap, VecDeque};
+use std::fs::File;
+use std::io::{BufReader, BufWriter, Result};
+use std::sync::Arc;
+
+use serde::{Serialize, Deserialize};
+
+// Dynamic state of a `Scheduler` at the beginning of a tick.
+// The topology (neurons and their connections) is not part of it,
+// a snapshot can only be restored into the network it was taken from.
+#[derive(Clone, Debug, Default, PartialEq, Serialize, Deserialize)]
+pub struct Snapshot {
+  pub time: u64,
+  #[serde(default)]
+  pub time_ms: f64,
+  pub potentials: BTreeMap<String, f64>,
+  // tick of the last action potential, for the refractory period
+  #[serde(default)]
+  pub last_spikes: BTreeMap<String, u64>,
+  // strengths of the post-synaptic connections in the order they were made
+  pub weights: BTreeMap<String, Vec<f64>>,
+  pub events: BTreeMap<u64, HashMap<String, Vec<f64>>>,
+  // encoder phases of the sensors, sensor values are external input and not kept
+  #[serde(default)]
+  pub sensors: BTreeMap<String, f64>,
+  // integrated commands of the actuators
+  #[serde(default)]
+  pub actuators: BTreeMap<String, f64>,
+}
+
+impl Snapshot {
+  pub fn to_json(&self) -> Result<String> {
+    Ok(serde_json::to_string_pretty(self)?)
+  }
+
+  pub fn from_json(json: &str) -> Result<Self> {
+    Ok(serde_json::from_str(json)?)
+  }
+
+  pub fn save(&self, path: &str) -> Result<()> {
+    let writer = BufWriter::new(File::create(path)?);
+    Ok(serde_json::to_writer_pretty(writer, self)?)
+  }
+
+  pub fn load(path: &str) -> Result<Self> {
+    let reader = BufReader::new(File::open(path)?);
+    Ok(serde_json::from_reader(reader)?)
+  }
+}
+
+// Dynamic state of one tick without names and weights, the vectors follow
+// the order of `Scheduler::pool` (and of the sensors and actuators). It only
+// makes sense together with a `Snapshot` of the same network, see `History`.
+#[derive(Clone, Debug, Default, PartialEq)]
+pub struct Frame {
+  pub time: u64,
+  pub time_ms: f64,
+  pub potentials: Vec<f64>,
+  pub last_spikes: Vec<Option<u64>>,
+  pub events: BTreeMap<u64, HashMap<String, Vec<f64>>>,
+  pub sensors: Vec<f64>,
+  pub actuators: Vec<f64>,
+}
+
+impl Frame {
+  // Full snapshot with the names and weights of `keyframe`
+  pub fn to_snapshot(&self, keyframe: &Snapshot) -> Snapshot {
+    let mut snapshot = Snapshot {
+      time: self.time,
+      time_ms: self.time_ms,
+      weights: keyframe.weights.clone(),
+      events: self.events.clone(),
+      ..Snapshot::default()
+    };
+    for (i, id) in keyframe.weights.keys().enumerate() {
+      snapshot.potentials.insert(id.clone(), self.potentials[i]);
+      if let Some(spike_time) = self.last_spikes[i] {
+        snapshot.last_spikes.insert(id.clone(), spike_time);
+      }
+    }
+    snapshot.sensors = keyframe.sensors.keys().cloned().zip(self.sensors.iter().cloned()).collect();
+    snapshot.actuators = keyframe.actuators.keys().cloned().zip(self.actuators.iter().cloned()).collect();
+    snapshot
+  }
+}
+
+// Bounded ring buffer of the past ticks, the oldest one is dropped when full.
+// Every tick is a compact `Frame`, a full `Snapshot` for the names and weights
+// is only taken every `keyframe_interval` ticks and shared by the frames after it.
+#[derive(Clone, Debug)]
+pub struct History {
+  capacity: usize,
+  keyframe_interval: usize,
+  frames: VecDeque<(Arc<Snapshot>, Frame)>,
+  // frames pushed since the last keyframe
+  since_keyframe: usize,
+  // the network changed, the next frame needs a new keyframe
+  stale: bool,
+}
+
+impl Default for History {
+  fn default() -> Self {
+    History::new(0)
+  }
+}
+
+impl History {
+  pub fn new(capacity: usize) -> Self {
+    History {
+      capacity,
+      keyframe_interval: 64,
+      frames: VecDeque::with_capacity(capacity),
+      since_keyframe: 0,
+      stale: true,
+    }
+  }
+
+  pub fn capacity(&self) -> usize {
+    self.capacity
+  }
+
+  pub fn set_keyframe_interval(&mut self, interval: usize) {
+    self.keyframe_interval = interval.max(1);
+  }
+
+  pub fn len(&self) -> usize {
+    self.frames.len()
+  }
+
+  pub fn is_empty(&self) -> bool {
+    self.frames.is_empty()
+  }
+
+  // Number of full snapshots kept
+  pub fn keyframes(&self) -> usize {
+    let mut count = 0;
+    let mut last: Option<&Arc<Snapshot>> = None;
+    for (keyframe, _) in self.frames.iter() {
+      if last.is_none_or(|last| !Arc::ptr_eq(last, keyframe)) {
+        count += 1;
+      }
+      last = Some(keyframe);
+    }
+    count
+  }
+
+  // Weights or neurons changed, the next push takes a new keyframe
+  pub fn invalidate_keyframe(&mut self) {
+    self.stale = true;
+  }
+
+  // `keyframe` is only called when a new full snapshot is due
+  pub fn push(&mut self, frame: Frame, keyframe: impl FnOnce() -> Snapshot) {
+    if self.capacity == 0 {
+      return;
+    }
+    let current = match self.frames.back() {
+      Some((snapshot, _)) if !self.stale
+        && self.since_keyframe < self.keyframe_interval
+        && snapshot.potentials.len() == frame.potentials.len() => snapshot.clone(),
+      _ => {
+        self.stale = false;
+        self.since_keyframe = 0;
+        Arc::new(keyframe())
+      },
+    };
+    if self.frames.len() == self.capacity {
+      self.frames.pop_front();
+    }
+    self.frames.push_back((current, frame));
+    self.since_keyframe += 1;
+  }
+
+  pub fn pop(&mut self) -> Option<Snapshot> {
+    let (keyframe, frame) = self.frames.pop_back()?;
+    self.since_keyframe = match self.frames.back() {
+      Some((last, _)) => self.frames.iter().rev().take_while(|(k, _)| Arc::ptr_eq(k, last)).count(),
+      None => 0,
+    };
+    Some(frame.to_snapshot(&keyframe))
+  }
+
+  // 0 is the most recent tick
+  pub fn get(&self, ticks_ago: usize) -> Option<Snapshot> {
+    if ticks_ago >= self.frames.len() {
+      return None;
+    }
+    let (keyframe, frame) = &self.frames[self.frames.len() - 1 - ticks_ago];
+    Some(frame.to_snapshot(keyframe))
+  }
+
+  pub fn clear(&mut self) {
+    self.frames.clear();
+    self.stale = true;
+  }
+
+  pub fn iter(&self) -> impl Iterator<Item = &Frame> {
+    self.frames.iter().map(|(_, frame)| frame)
+  }
+}
+
+#[cfg(test)]
+mod tests {
+  use std::collections::{BTreeMap, HashMap};
+
+  use super::{Frame, History, Snapshot};
+
+  fn at(time: u64) -> Snapshot {
+    Snapshot {
+      time,
+      ..Snapshot::default()
+    }
+  }
+
+  // one neuron "a" with potential `time`
+  fn frame(time: u64) -> Frame {
+    Frame {
+      time,
+      time_ms: time as f64,
+      potentials: vec![time as f64],
+      last_spikes: vec![None],
+      ..Frame::default()
+    }
+  }
+
+  fn keyframe(time: u64) -> Snapshot {
+    let mut snapshot = at(time);
+    snapshot.potentials.insert("a".to_string(), time as f64);
+    snapshot.weights.insert("a".to_string(), vec![0.5]);
+    snapshot
+  }
+
+  #[test]
+  fn ring_buffer_drops_oldest() {
+    let mut history = History::new(3);
+    for t in 0..5 {
+      history.push(frame(t), || keyframe(t));
+    }
+    assert_eq!(history.len(), 3);
+    assert_eq!(history.get(0).unwrap().time, 4);
+    assert_eq!(history.get(2).unwrap().time, 2);
+    assert!(history.get(3).is_none());
+    assert_eq!(history.pop().unwrap().time, 4);
+    assert_eq!(history.len(), 2);
+  }
+
+  #[test]
+  fn keyframes_only_every_interval() {
+    let mut history = History::new(20);
+    history.set_keyframe_interval(4);
+    let mut taken = Vec::new();
+    for t in 0..10 {
+      history.push(frame(t), || {
+        taken.push(t);
+        keyframe(t)
+      });
+    }
+    assert_eq!(taken, vec![0, 4, 8]);
+    assert_eq!(history.keyframes(), 3);
+
+    // a frame comes back as a full snapshot with the names and weights of its keyframe
+    let snapshot = history.get(3).unwrap();
+    assert_eq!(snapshot.time, 6);
+    assert_eq!(snapshot.potentials, BTreeMap::from([("a".to_string(), 6.0)]));
+    assert_eq!(snapshot.weights, keyframe(4).weights);
+
+    // popping back into the previous keyframe continues its count
+    for _ in 0..3 {
+      history.pop();
+    }
+    history.push(frame(7), || unreachable!());
+    history.invalidate_keyframe();
+    history.push(frame(8), || keyframe(8));
+    assert_eq!(history.keyframes(), 3);
+  }
+
+  #[test]
+  fn json_round_trip() {
+    let mut snapshot = at(12);
+    snapshot.potentials.insert("a".to_string(), 0.5);
+    snapshot.weights.insert("a".to_string(), vec![1.0, -0.25]);
+    snapshot.events.insert(13, HashMap::from([("b".to_string(), vec![1.0])]));
+    let json = snapshot.to_json().unwrap();
+    assert_eq!(Snapshot::from_json(&json).unwrap(), snapshot);
+  }
+}
\ No newline at end of file
@@ -0,0 +1,279 @@
+use std::collections::HashMap;
+use std::fs::File;
+use std::io::{BufReader, Result};
+
+use rand::{Rng, SeedableRng};
+use rand::rngs::StdRng;
+use serde::{Serialize, Deserialize};
+
+// Shape of the input current over time. All times are in ms relative to
+// the start of the stimulus window and rounded to whole ticks with `dt`,
+// like synaptic delays.
+#[derive(Clone, Debug, PartialEq, Serialize, Deserialize)]
+#[serde(tag = "type", rename_all = "snake_case")]
+pub enum Waveform {
+  // tonic input, the same value every tick
+  Constant { amplitude: f64 },
+  // `amplitude` for `width` ms out of every `period` ms, silent for a zero width or period
+  Pulse { amplitude: f64, period: f64, width: f64 },
+  // linear change from `from` to `to` over `duration` ms, then holds `to`
+  Ramp { from: f64, to: f64, duration: f64 },
+  // silent for a zero or non-finite `period`, like `Pulse`
+  Sine { amplitude: f64, period: f64, #[serde(default)] phase: f64, #[serde(default)] offset: f64 },
+  // background noise: every tick each target receives `amplitude` with probability `rate`
+  Poisson { rate: f64, amplitude: f64 },
+}
+
+#[derive(Clone, Debug, PartialEq, Serialize, Deserialize)]
+pub struct Stimulus {
+  pub targets: Vec<String>,
+  // ms
+  #[serde(default)]
+  pub start: f64,
+  // ms, exclusive, `None` means the stimulus never ends
+  #[serde(default)]
+  pub stop: Option<f64>,
+  #[serde(flatten)]
+  pub waveform: Waveform,
+}
+
+// Nearest tick, unlike synaptic delays a window may start at 0
+fn ticks(ms: f64, dt: f64) -> u64 {
+  (ms / dt).round().max(0.0) as u64
+}
+
+// Positive durations last at least one tick so a short pulse is not lost
+fn duration_ticks(ms: f64, dt: f64) -> Option<u64> {
+  if ms > 0.0 && ms.is_finite() {
+    Some(ticks(ms, dt).max(1))
+  } else {
+    None
+  }
+}
+
+impl Stimulus {
+  pub fn is_active(&self, time: u64, dt: f64) -> bool {
+    time >= ticks(self.start, dt) && self.stop.is_none_or(|stop| time < ticks(stop, dt))
+  }
+
+  // Deterministic part of the waveform, `None` for noise or when there is no input this tick
+  fn value(&self, time: u64, dt: f64) -> Option<f64> {
+    let t = time - ticks(self.start, dt);
+    let value = match &self.waveform {
+      Waveform::Constant { amplitude } => *amplitude,
+      Waveform::Pulse { amplitude, period, width } => {
+        let period = duration_ticks(*period, dt)?;
+        let width = duration_ticks(*width, dt)?;
+        if t % period >= width {
+          return None;
+        }
+        *amplitude
+      },
+      Waveform::Ramp { from, to, duration } => {
+        let elapsed = t as f64 * dt;
+        if *duration <= 0.0 || elapsed >= *duration {
+          *to
+        } else {
+          from + (to - from) * elapsed / duration
+        }
+      },
+      Waveform::Sine { amplitude, period, phase, offset } => {
+        if *period == 0.0 || !period.is_finite() {
+          return None;
+        }
+        offset + amplitude * (2.0 * std::f64::consts::PI * (t as f64 * dt) / period + phase).sin()
+      },
+      Waveform::Poisson { .. } => return None,
+    };
+    if value == 0.0 {
+      None
+    } else {
+      Some(value)
+    }
+  }
+}
+
+#[derive(Clone, Debug, Default, PartialEq, Serialize, Deserialize)]
+pub struct StimulationProtocol {
+  // seed for the noise generators, the same seed replays the same noise
+  #[serde(default)]
+  pub seed: u64,
+  pub stimuli: Vec<Stimulus>,
+}
+
+impl StimulationProtocol {
+  pub fn new() -> Self {
+    StimulationProtocol::default()
+  }
+
+  pub fn from_json(json: &str) -> Result<Self> {
+    Ok(serde_json::from_str(json)?)
+  }
+
+  pub fn from_file(path: &str) -> Result<Self> {
+    let reader = BufReader::new(File::open(path)?);
+    Ok(serde_json::from_reader(reader)?)
+  }
+
+  pub fn add(&mut self, stimulus: Stimulus) {
+    self.stimuli.push(stimulus);
+  }
+
+  pub fn is_empty(&self) -> bool {
+    self.stimuli.is_empty()
+  }
+
+  // Adds the input currents for tick `time` to `signals`, the same map
+  // `Scheduler::prepare_next_layer` consumes. `dt` is the length of a tick in ms.
+  pub fn apply(&self, time: u64, dt: f64, signals: &mut HashMap<String, Vec<f64>>) {
+    for (idx, stimulus) in self.stimuli.iter().enumerate() {
+      if !stimulus.is_active(time, dt) {
+        continue;
+      }
+      if let Waveform::Poisson { rate, amplitude } = stimulus.waveform {
+        // noise depends only on (seed, stimulus, time), so rewinding replays it exactly
+        let mut rng = StdRng::seed_from_u64(
+          self.seed
+            ^ (idx as u64).wrapping_mul(0xD1B5_4A32_D192_ED03)
+            ^ time.wrapping_mul(0x9E37_79B9_7F4A_7C15));
+        for target in stimulus.targets.iter() {
+          if rng.gen::<f64>() < rate {
+            signals.entry(target.clone()).or_default().push(amplitude);
+          }
+        }
+      } else if let Some(value) = stimulus.value(time, dt) {
+        for target in stimulus.targets.iter() {
+          signals.entry(target.clone()).or_default().push(value);
+        }
+      }
+    }
+  }
+}
+
+#[cfg(test)]
+mod tests {
+  use std::collections::HashMap;
+
+  use super::{StimulationProtocol, Stimulus, Waveform};
+
+  fn collect(protocol: &StimulationProtocol, target: &str, ticks: u64) -> Vec<f64> {
+    collect_dt(protocol, target, ticks, 1.0)
+  }
+
+  fn collect_dt(protocol: &StimulationProtocol, target: &str, ticks: u64, dt: f64) -> Vec<f64> {
+    (0..ticks).map(|time| {
+      let mut signals = HashMap::new();
+      protocol.apply(time, dt, &mut signals);
+      signals.get(target).map_or(0.0, |s| s.iter().sum())
+    }).collect()
+  }
+
+  #[test]
+  fn pulse_and_window() {
+    let mut protocol = StimulationProtocol::new();
+    protocol.add(Stimulus {
+      targets: vec!["a".to_string()],
+      start: 2.0,
+      stop: Some(8.0),
+      waveform: Waveform::Pulse { amplitude: 1.0, period: 3.0, width: 1.0 },
+    });
+    let values = collect(&protocol, "a", 10);
+    assert_eq!(values, vec![0.0, 0.0, 1.0, 0.0, 0.0, 1.0, 0.0, 0.0, 0.0, 0.0]);
+
+    protocol.stimuli[0].waveform = Waveform::Pulse { amplitude: 1.0, period: 3.0, width: 0.0 };
+    assert_eq!(collect(&protocol, "a", 10), vec![0.0; 10]);
+  }
+
+  #[test]
+  fn times_are_in_ms() {
+    let mut protocol = StimulationProtocol::new();
+    protocol.add(Stimulus {
+      targets: vec!["a".to_string()],
+      start: 1.0,
+      stop: Some(4.0),
+      waveform: Waveform::Pulse { amplitude: 1.0, period: 1.5, width: 0.5 },
+    });
+    // ticks of 0.5 ms: the window is ticks 2..8, a pulse every 3 ticks lasting 1
+    let values = collect_dt(&protocol, "a", 10, 0.5);
+    assert_eq!(values, vec![0.0, 0.0, 1.0, 0.0, 0.0, 1.0, 0.0, 0.0, 0.0, 0.0]);
+
+    protocol.stimuli[0] = Stimulus {
+      targets: vec!["a".to_string()],
+      start: 0.0,
+      stop: None,
+      waveform: Waveform::Ramp { from: 0.0, to: 1.0, duration: 2.0 },
+    };
+    assert_eq!(collect_dt(&protocol, "a", 6, 0.5), vec![0.0, 0.25, 0.5, 0.75, 1.0, 1.0]);
+
+    protocol.stimuli[0].waveform = Waveform::Sine { amplitude: 2.0, period: 4.0, phase: 0.0, offset: 0.0 };
+    // a quarter period is 1 ms, two ticks
+    let values = collect_dt(&protocol, "a", 3, 0.5);
+    assert!((values[2] - 2.0).abs() < 1e-9, "values = {:?}", values);
+  }
+
+  #[test]
+  fn ramp_and_constant() {
+    let mut protocol = StimulationProtocol::new();
+    protocol.add(Stimulus {
+      targets: vec!["a".to_string()],
+      start: 0.0,
+      stop: None,
+      waveform: Waveform::Ramp { from: 0.0, to: 1.0, duration: 4.0 },
+    });
+    protocol.add(Stimulus {
+      targets: vec!["a".to_string()],
+      start: 0.0,
+      stop: Some(2.0),
+      waveform: Waveform::Constant { amplitude: 0.5 },
+    });
+    let values = collect(&protocol, "a", 6);
+    assert_eq!(values, vec![0.5, 0.75, 0.5, 0.75, 1.0, 1.0]);
+  }
+
+  #[test]
+  fn sine_period() {
+    let mut protocol = StimulationProtocol::new();
+    protocol.add(Stimulus {
+      targets: vec!["a".to_string()],
+      start: 0.0,
+      stop: None,
+      waveform: Waveform::Sine { amplitude: 2.0, period: 4.0, phase: 0.0, offset: 0.0 },
+    });
+    let values = collect(&protocol, "a", 4);
+    assert!((values[1] - 2.0).abs() < 1e-9, "values = {:?}", values);
+    assert!((values[3] + 2.0).abs() < 1e-9, "values = {:?}", values);
+
+    protocol.stimuli[0].waveform = Waveform::Sine { amplitude: 2.0, period: 0.0, phase: 0.0, offset: 1.0 };
+    assert_eq!(collect(&protocol, "a", 4), vec![0.0; 4]);
+  }
+
+  #[test]
+  fn poisson_is_reproducible() {
+    let json = r#"{
+      "seed": 7,
+      "stimuli": [
+        { "type": "poisson", "targets": ["a", "b"], "rate": 0.3, "amplitude": 1.0 }
+      ]
+    }"#;
+    let protocol = StimulationProtocol::from_json(json).unwrap();
+    let first = collect(&protocol, "a", 1000);
+    let second = collect(&protocol, "a", 1000);
+    assert_eq!(first, second);
+    let rate = first.iter().sum::<f64>() / 1000.0;
+    assert!((rate - 0.3).abs() < 0.05, "rate = {}", rate);
+  }
+
+  #[test]
+  fn load_json() {
+    let json = r#"{
+      "stimuli": [
+        { "type": "constant", "targets": ["a"], "start": 1, "stop": 3, "amplitude": 2.0 }
+      ]
+    }"#;
+    let protocol = StimulationProtocol::from_json(json).unwrap();
+    assert_eq!(protocol.stimuli[0].waveform, Waveform::Constant { amplitude: 2.0 });
+    assert_eq!(collect(&protocol, "a", 4), vec![0.0, 2.0, 2.0, 0.0]);
+
+    assert!(StimulationProtocol::from_json("{ \"stimuli\": [{ \"type\": \"square\" }] }").is_err());
+  }
+}
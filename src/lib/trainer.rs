@@ -0,0 +1,261 @@
+use ndarray::prelude::*;
+use rand::SeedableRng;
+use rand::rngs::StdRng;
+use rand::seq::SliceRandom;
+
+use crate::ann::ANN;
+
+// Share of the samples that are classified right. One output is a yes/no
+// decision at 0.5, several outputs are one class each and the largest one wins.
+pub fn accuracy(outputs: ArrayView2<f32>, targets: ArrayView2<f32>) -> f32 {
+  let argmax = |row: ArrayView1<f32>| row.iter()
+    .enumerate()
+    .fold((0, f32::NEG_INFINITY), |best, (i, x)| if *x > best.1 { (i, *x) } else { best })
+    .0;
+  let correct = outputs.rows().into_iter().zip(targets.rows())
+    .filter(|(y, t)| if y.len() == 1 { (y[0] > 0.5) == (t[0] > 0.5) } else { argmax(*y) == argmax(*t) })
+    .count();
+  correct as f32 / outputs.nrows().max(1) as f32
+}
+
+#[derive(Clone, Debug, PartialEq)]
+pub struct Epoch {
+  // counted from 0 in this run
+  pub epoch: usize,
+  // mean loss during the updates
+  pub loss: f32,
+  // of the network at the end of the epoch
+  pub accuracy: f32,
+  // `None` without a validation set
+  pub validation_loss: Option<f32>,
+  pub validation_accuracy: Option<f32>,
+}
+
+#[derive(Clone, Debug, PartialEq)]
+pub struct TrainingReport {
+  pub history: Vec<Epoch>,
+  // epoch of the lowest validation loss (training loss without a validation set),
+  // `None` if no epoch ran
+  pub best_epoch: Option<usize>,
+  // ran out of patience, not stopped by a callback or the epoch limit
+  pub stopped_early: bool,
+}
+
+impl TrainingReport {
+  pub fn best(&self) -> Option<&Epoch> {
+    self.best_epoch.map(|epoch| &self.history[epoch])
+  }
+}
+
+// Sees every epoch and the network after it, false stops the training
+pub type Callback = Box<dyn FnMut(&Epoch, &ANN) -> bool>;
+
+// Training loop around `ANN::train_epoch`. Holds back part of the data for validation,
+// records loss and accuracy every epoch and stops when the validation loss
+// has not improved for `patience` epochs. The network ends up with the weights
+// of its best epoch.
+pub struct Trainer {
+  pub epochs: usize,
+  // share of the samples held back, picked at random with `seed`
+  pub validation_fraction: f32,
+  pub seed: u64,
+  // `None` runs all epochs
+  pub patience: Option<usize>,
+  // smallest decrease of the loss that counts as an improvement
+  pub min_delta: f32,
+  pub restore_best: bool,
+  callbacks: Vec<Callback>,
+}
+
+impl Trainer {
+  pub fn new(epochs: usize) -> Self {
+    Trainer {
+      epochs,
+      validation_fraction: 0.0,
+      seed: 0,
+      patience: None,
+      min_delta: 0.0,
+      restore_best: true,
+      callbacks: Vec::new(),
+    }
+  }
+
+  pub fn validation(mut self, fraction: f32, seed: u64) -> Self {
+    self.validation_fraction = fraction;
+    self.seed = seed;
+    self
+  }
+
+  pub fn patience(mut self, epochs: usize, min_delta: f32) -> Self {
+    self.patience = Some(epochs);
+    self.min_delta = min_delta;
+    self
+  }
+
+  pub fn on_epoch(mut self, callback: impl FnMut(&Epoch, &ANN) -> bool + 'static) -> Self {
+    self.callbacks.push(Box::new(callback));
+    self
+  }
+
+  // (training, validation) sample indices
+  fn split(&self, n: usize) -> (Vec<usize>, Vec<usize>) {
+    let mut order: Vec<usize> = (0..n).collect();
+    order.shuffle(&mut StdRng::seed_from_u64(self.seed));
+    let n_validation = (n as f32 * self.validation_fraction.clamp(0.0, 1.0)).round() as usize;
+    let validation = order.split_off(n - n_validation.min(n));
+    (order, validation)
+  }
+
+  pub fn fit(&mut self, network: &mut ANN, inputs: ArrayView2<f32>, targets: ArrayView2<f32>) -> TrainingReport {
+    let (train, validation) = self.split(inputs.nrows());
+    let (x, y) = (inputs.select(Axis(0), &train), targets.select(Axis(0), &train));
+    let (vx, vy) = (inputs.select(Axis(0), &validation), targets.select(Axis(0), &validation));
+
+    let mut history = Vec::new();
+    let mut best: Option<(f32, ANN)> = None;
+    let mut best_epoch = None;
+    let mut stopped_early = false;
+    for epoch in 0..self.epochs {
+      let loss = network.train_epoch(x.view(), y.view(), epoch);
+      let report = Epoch {
+        epoch,
+        loss,
+        accuracy: accuracy(network.forward_batch(x.view()).view(), y.view()),
+        validation_loss: (!validation.is_empty()).then(|| network.evaluate(vx.view(), vy.view())),
+        validation_accuracy: (!validation.is_empty()).then(|| accuracy(network.forward_batch(vx.view()).view(), vy.view())),
+      };
+
+      let score = report.validation_loss.unwrap_or(loss);
+      if best.as_ref().is_none_or(|(best, _)| score < best - self.min_delta) {
+        best = Some((score, network.clone()));
+        best_epoch = Some(epoch);
+      }
+      let mut keep_going = true;
+      for callback in self.callbacks.iter_mut() {
+        keep_going &= callback(&report, network);
+      }
+      history.push(report);
+      if !keep_going {
+        break;
+      }
+      if self.patience.zip(best_epoch).is_some_and(|(patience, best_epoch)| epoch - best_epoch >= patience) {
+        stopped_early = true;
+        break;
+      }
+    }
+
+    if let Some((_, checkpoint)) = best.filter(|_| self.restore_best) {
+      *network = checkpoint;
+    }
+    TrainingReport { history, best_epoch, stopped_early }
+  }
+}
+
+#[cfg(test)]
+mod tests {
+  use std::cell::RefCell;
+  use std::rc::Rc;
+
+  use ndarray::prelude::*;
+  use rand::{Rng, SeedableRng};
+  use rand::rngs::StdRng;
+
+  use crate::ann::{Linear, SoftmaxCrossEntropy, Tanh, ANN};
+
+  use super::{accuracy, Trainer};
+
+  // two classes on either side of the line a + b = 0, one-hot
+  fn classes(n: usize) -> (Array2<f32>, Array2<f32>) {
+    let mut rng = StdRng::seed_from_u64(1);
+    let inputs: Array2<f32> = Array2::from_shape_fn((n, 2), |_| rng.gen_range(-1.0..1.0));
+    let targets = Array2::from_shape_fn((n, 2), |(i, j)| {
+      let above = inputs[[i, 0]] + inputs[[i, 1]] > 0.0;
+      if above == (j == 1) { 1.0 } else { 0.0 }
+    });
+    (inputs, targets)
+  }
+
+  fn classifier() -> ANN {
+    let mut network = ANN::with_activations(&[2, 8, 2], vec![Box::new(Tanh), Box::new(Linear)]);
+    network.initialize(0);
+    network.loss = Box::new(SoftmaxCrossEntropy);
+    network.batch_size = 8;
+    network
+  }
+
+  #[test]
+  fn accuracy_of_one_and_many_outputs() {
+    let y = arr2(&[[0.9], [0.2], [0.6]]);
+    let t = arr2(&[[1.0], [1.0], [0.0]]);
+    assert!((accuracy(y.view(), t.view()) - 1.0 / 3.0).abs() < 1e-6);
+    let y = arr2(&[[0.1, 0.7, 0.2], [0.5, 0.4, 0.1]]);
+    let t = arr2(&[[0.0, 1.0, 0.0], [0.0, 0.0, 1.0]]);
+    assert_eq!(accuracy(y.view(), t.view()), 0.5);
+  }
+
+  #[test]
+  fn learns_with_validation() {
+    let (inputs, targets) = classes(200);
+    let mut network = classifier();
+    let report = Trainer::new(60).validation(0.25, 2).fit(&mut network, inputs.view(), targets.view());
+    assert_eq!(report.history.len(), 60);
+    assert!(!report.stopped_early);
+    let last = &report.history[59];
+    assert!(last.validation_accuracy.unwrap() > 0.9, "{:?}", last);
+    assert!(last.loss < report.history[0].loss);
+  }
+
+  #[test]
+  fn stops_on_a_plateau_with_the_best_weights() {
+    let (inputs, targets) = classes(100);
+    let mut network = classifier();
+    // a rate this high makes the validation loss jump around
+    network.learning_rate = 5.0;
+    let seen = Rc::new(RefCell::new(Vec::new()));
+    let log = seen.clone();
+    let report = Trainer::new(200)
+      .validation(0.3, 2)
+      .patience(5, 0.0)
+      .on_epoch(move |epoch, _| {
+        log.borrow_mut().push(epoch.validation_loss.unwrap());
+        true
+      })
+      .fit(&mut network, inputs.view(), targets.view());
+
+    assert!(report.stopped_early);
+    assert_eq!(report.history.len(), report.best_epoch.unwrap() + 6);
+    let losses = seen.borrow();
+    assert_eq!(losses.len(), report.history.len());
+    let lowest = losses.iter().cloned().fold(f32::INFINITY, f32::min);
+    assert_eq!(report.best().unwrap().validation_loss, Some(lowest));
+
+    // the network is back at its best epoch
+    let trainer = Trainer::new(0).validation(0.3, 2);
+    let (_, validation) = trainer.split(inputs.nrows());
+    let vx = inputs.select(Axis(0), &validation);
+    let vy = targets.select(Axis(0), &validation);
+    assert_eq!(network.evaluate(vx.view(), vy.view()), lowest);
+  }
+
+  #[test]
+  fn callback_stops_training() {
+    let (inputs, targets) = classes(40);
+    let mut network = classifier();
+    let report = Trainer::new(50)
+      .on_epoch(|epoch, _| epoch.epoch < 2)
+      .fit(&mut network, inputs.view(), targets.view());
+    assert_eq!(report.history.len(), 3);
+    assert!(!report.stopped_early);
+    assert_eq!(report.history[0].validation_loss, None);
+  }
+
+  #[test]
+  fn no_epochs_no_best() {
+    let (inputs, targets) = classes(10);
+    let mut network = classifier();
+    let report = Trainer::new(0).fit(&mut network, inputs.view(), targets.view());
+    assert!(report.history.is_empty());
+    assert_eq!(report.best_epoch, None);
+    assert_eq!(report.best(), None);
+  }
+}
@@ -3,26 +3,21 @@ use std::convert::TryInto;
 use std::hash::Hash;
 use std::io::{Error, Result};
 use std::sync::Mutex;
+use std::time::Duration;
 
-use frozen_lake::GridPos;
 use rand::Rng;
 use plotters::{prelude::*, data};
 use ndarray::{array, Array2, Axis};
 use actix_web::{web, App, HttpServer, HttpResponse, Responder};
 use serde::{Serialize, Deserialize};
 
-mod ann;
-mod frozen_lake;
-mod grid_world;
-// mod window;
-mod neuron;
-mod scheduler;
-mod gl;
-
-use crate::{
-  frozen_lake::{ACTIONS, FrozenLake},
-  grid_world::GridWorld,
-};
+use qu::frozen_lake::{FrozenLake, GridPos};
+use qu::grid_world::{GridWorld, ACTIONS};
+use qu::host::{HostState, SimulationHost};
+use qu::module::Module;
+use qu::scheduler::Scheduler;
+use qu::snapshot::Snapshot;
+use qu::stimulation::StimulationProtocol;
 
 fn vec2array<T, const N: usize>(v: Vec<T>) -> [T; N] {
     v.try_into()
@@ -74,10 +69,17 @@ fn simulate() {
 
 }
 
+// everything a lake request touches, behind one lock so a request sees it consistent
+struct Lake {
+  env: FrozenLake,
+  step: i32,
+  reward: f32,
+}
+
 struct AppState {
-  env: Mutex<FrozenLake>,
-  step: Mutex<i32>,
-  reward: Mutex<f32>,
+  lake: Mutex<Lake>,
+  // the network runs on its own thread, requests only send commands and read its state
+  host: SimulationHost,
 }
 
 // Define a struct to represent your data
@@ -93,8 +95,8 @@ struct EnvData {
 }
 
 async fn env_get_handler(data: web::Data<AppState>) -> impl Responder {
-  let env = data.env.lock().unwrap();
-  let reward = data.reward.lock().unwrap();
+  let lake = data.lake.lock().unwrap();
+  let env = &lake.env;
 
   let loc = env.loc();
   let meta_state = env.create_meta_state(loc);
@@ -102,7 +104,7 @@ async fn env_get_handler(data: web::Data<AppState>) -> impl Responder {
     loc: *loc,
     grid_world: env.grid_world(),
     probs: env.probs(),
-    total_reward: *reward,
+    total_reward: lake.reward,
     step_reward: 0.0,
     action_prob: env.action_prob(&meta_state),
     meta_state: meta_state.to_vec(),
@@ -115,17 +117,16 @@ async fn env_get_handler(data: web::Data<AppState>) -> impl Responder {
 }
 
 async fn step_post_handler(data: web::Data<AppState>) -> impl Responder {
-  let mut env = data.env.lock().unwrap();
-  let mut step = data.step.lock().unwrap();
-  let mut reward = data.reward.lock().unwrap();
+  let mut lake = data.lake.lock().unwrap();
 
-  let prev_loc = env.loc();
-  let prev_meta_state = env.create_meta_state(prev_loc);
+  let prev_loc = lake.env.loc();
+  let prev_meta_state = lake.env.create_meta_state(prev_loc);
 
-  let (_, _, step_reward, _) = env.step();
+  let (_, _, step_reward, _) = lake.env.step();
   
-  *step += 1;
-  *reward += step_reward;
+  lake.step += 1;
+  lake.reward += step_reward;
+  let env = &lake.env;
   let loc = env.loc();
   let meta_state = env.create_meta_state(loc);
 
@@ -133,7 +134,7 @@ async fn step_post_handler(data: web::Data<AppState>) -> impl Responder {
     loc: *loc,
     grid_world: env.grid_world(),
     probs: env.probs(),
-    total_reward: *reward,
+    total_reward: lake.reward,
     step_reward: step_reward,
     action_prob: env.action_prob(&prev_meta_state),
     meta_state: meta_state.to_vec(),
@@ -152,8 +153,8 @@ struct MoveData {
 }
 
 async fn move_post_handler(data: web::Data<AppState>, move_data: web::Json<MoveData>) -> impl Responder {
-  let mut env = data.env.lock().unwrap();
-  let loc = env.loc_mut();
+  let mut lake = data.lake.lock().unwrap();
+  let loc = lake.env.loc_mut();
   loc[0] = move_data.x;
   loc[1] = move_data.y;
   HttpResponse::Ok()
@@ -161,20 +162,109 @@ async fn move_post_handler(data: web::Data<AppState>, move_data: web::Json<MoveD
     .body("GOOD")
 }
 
+#[derive(Serialize)]
+struct NetworkData<'a> {
+  snapshot: &'a Snapshot,
+  fired: &'a Vec<String>,
+  running: bool,
+  error: &'a Option<String>,
+}
+
+fn network_response(state: &HostState) -> HttpResponse {
+  let network_data = NetworkData {
+    snapshot: &state.snapshot,
+    fired: &state.fired,
+    running: state.running,
+    error: &state.error,
+  };
+  let json_response = serde_json::to_string(&network_data)
+    .expect("Failed to serialize JSON");
+  let mut response = match state.error {
+    Some(_) => HttpResponse::BadRequest(),
+    None => HttpResponse::Ok(),
+  };
+  response
+    .content_type("application/json")
+    .body(json_response)
+}
+
+// never waits for the simulation, a running network is read mid run
+async fn network_get_handler(data: web::Data<AppState>) -> impl Responder {
+  network_response(&data.host.state())
+}
+
+#[derive(Deserialize)]
+struct StepData {
+  ticks: u64,
+}
+
+async fn network_step_post_handler(data: web::Data<AppState>, step_data: web::Json<StepData>) -> impl Responder {
+  data.host.step(step_data.ticks);
+  network_response(&data.host.sync())
+}
+
+async fn network_run_post_handler(data: web::Data<AppState>) -> impl Responder {
+  data.host.run();
+  network_response(&data.host.sync())
+}
+
+async fn network_pause_post_handler(data: web::Data<AppState>) -> impl Responder {
+  data.host.pause();
+  network_response(&data.host.sync())
+}
+
+async fn network_inject_post_handler(data: web::Data<AppState>, signals: web::Json<HashMap<String, Vec<f64>>>) -> impl Responder {
+  data.host.inject(signals.into_inner());
+  network_response(&data.host.sync())
+}
+
+#[derive(Deserialize)]
+struct WeightData {
+  pre: String,
+  post: String,
+  strength: f64,
+}
+
+async fn network_weight_post_handler(data: web::Data<AppState>, weight_data: web::Json<WeightData>) -> impl Responder {
+  data.host.set_weight(&weight_data.pre, &weight_data.post, weight_data.strength);
+  network_response(&data.host.sync())
+}
+
+// the module given on the command line, instantiated as `net/..`, otherwise an empty network
+fn create_scheduler() -> std::io::Result<Scheduler> {
+  let mut scheduler = Scheduler::new();
+  if let Some(path) = std::env::args().nth(1) {
+    Module::load(&path)?.instantiate(&mut scheduler, "net")?;
+  }
+  if let Some(path) = std::env::args().nth(2) {
+    scheduler.stimulation = StimulationProtocol::from_file(&path)?;
+  }
+  Ok(scheduler)
+}
+
 #[actix_web::main]
 async fn main() -> std::io::Result<()> {
-  let env = web::Data::new(AppState {
-    env: Mutex::new(FrozenLake::new()),
-    step: Mutex::new(0),
-    reward: Mutex::new(0.0),
+  let data = web::Data::new(AppState {
+    lake: Mutex::new(Lake {
+      env: FrozenLake::new(),
+      step: 0,
+      reward: 0.0,
+    }),
+    host: SimulationHost::spawn(create_scheduler()?, Duration::from_millis(100)),
   });
   // Start the web server
   HttpServer::new(move || {
     App::new()
-      .app_data(env.clone())
+      .app_data(data.clone())
       .route("/env", web::get().to(env_get_handler))
       .route("/step", web::post().to(step_post_handler))
       .route("/move", web::post().to(move_post_handler))
+      .route("/network", web::get().to(network_get_handler))
+      .route("/network/step", web::post().to(network_step_post_handler))
+      .route("/network/run", web::post().to(network_run_post_handler))
+      .route("/network/pause", web::post().to(network_pause_post_handler))
+      .route("/network/inject", web::post().to(network_inject_post_handler))
+      .route("/network/weight", web::post().to(network_weight_post_handler))
   })
     .bind(("127.0.0.1", 8080))?
     .run()